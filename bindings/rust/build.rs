@@ -20,17 +20,24 @@ fn main() {
     println!("cargo:rerun-if-changed=../../unicornafl.cpp");
     println!("cargo:rerun-if-changed=../../CMakeLists.txt");
 
-    Command::new("make")
-        .args(&["-C", "../.."])
-        .status()
-        .expect("failed to build unicornafl");
+    // The Rust runtime replaces libunicornafl, we only need unicorn itself.
+    let rust_runtime = env::var_os("CARGO_FEATURE_RUST_RUNTIME").is_some();
+
+    let mut make = Command::new("make");
+    make.args(&["-C", "../.."]);
+    if rust_runtime {
+        make.arg("unicorn");
+    }
+    make.status().expect("failed to build unicornafl");
 
     link_search(
         Some(build_helper::SearchKind::Native),
         build_helper::out_dir(),
     );
 
-    add_lib("../../build", "unicornafl");
+    if !rust_runtime {
+        add_lib("../../build", "unicornafl");
+    }
 
     add_lib("../../build/unicorn", "unicorn-static");
     add_lib("../../build/unicorn", "unicorn-common");
@@ -1,84 +1,340 @@
 //! Bindings for `unicornafl`
 //!
 
-use std::ffi::{c_int, c_void};
+use std::convert::TryFrom;
+#[cfg(not(feature = "rust-runtime"))]
+use std::ffi::{c_char, c_void};
+use std::ffi::{c_int, CString};
 use std::marker::PhantomData;
+use std::mem;
+use std::ops::RangeInclusive;
+use std::ptr;
 use std::slice;
 
 use crate::consts::uc_error;
 use crate::ffi::uc_handle;
 use crate::Unicorn;
 
+#[cfg(feature = "rust-runtime")]
+use crate::runtime::{uc_afl_forkserver_start, uc_afl_fuzz_ex};
+
+#[cfg(not(feature = "rust-runtime"))]
 extern "C" {
     fn uc_afl_forkserver_start(
         engine: uc_handle,
         exits: *const u64,
         exit_count: libc::size_t,
     ) -> AflRet;
-    fn uc_afl_fuzz(
+    fn uc_afl_fuzz_ex(
         engine: uc_handle,
-        input_file: *const i8,
+        input_file: *const c_char,
         place_input_callback: *mut c_void,
         exits: *const u64,
         exit_count: libc::size_t,
+        fuzz_callback: *mut c_void,
         validate_crash_callback: *mut c_void,
         always_validate: bool,
         persistent_iters: u32,
+        options: *const uc_afl_options,
         data: *mut c_void,
     ) -> AflRet;
 }
 
+/// Mirrors `uc_afl_options` in `unicornafl.h`.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+pub(crate) struct uc_afl_options {
+    pub size: u32,
+    pub autodict: *const u8,
+    pub autodict_len: libc::size_t,
+    pub map_size: u32,
+    pub cmplog_rtns: *const CmpLogRoutine,
+    pub cmplog_rtn_count: libc::size_t,
+    pub cov_mode: uc_afl_cov_mode,
+    pub cov_ngram_size: u32,
+    pub cov_ranges: *const AddressRange,
+    pub cov_range_count: libc::size_t,
+    pub cov_exclude_ranges: *const AddressRange,
+    pub cov_exclude_range_count: libc::size_t,
+    pub persistent_restore: bool,
+}
+
+/// Mirrors `uc_afl_cov_mode` in `unicornafl.h`.
+#[allow(non_camel_case_types, clippy::enum_variant_names)]
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub(crate) enum uc_afl_cov_mode {
+    UC_AFL_COV_EDGE = 0,
+    UC_AFL_COV_BLOCK = 1,
+    UC_AFL_COV_NGRAM = 2,
+    UC_AFL_COV_CTX = 3,
+    UC_AFL_COV_EDGE_ID = 4,
+}
+
+/// How blocks are recorded in the coverage map.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum CoverageMode {
+    /// Classic AFL edge coverage.
+    #[default]
+    Edge,
+    /// Only which blocks were hit. The fastest, but blind to new paths between
+    /// known blocks.
+    Block,
+    /// Edges from the last `N - 1` blocks, like `AFL_LLVM_NGRAM_SIZE`. `N` has to be
+    /// between 2 and 16.
+    NGram(u32),
+    /// Edges in their calling context, like `AFL_LLVM_CTX`. Unicorn has no notion of
+    /// calls, so the context is derived from the stack pointer.
+    Context,
+    /// Every edge gets its own map entry, numbered in the order the edges are first
+    /// hit. Nothing collides until the map is full, after which new edges fall back
+    /// to `Edge`. Split compares are still hashed.
+    EdgeId,
+}
+
+/// An address range, mirrors `uc_afl_range` in `unicornafl.h`.
+///
+/// `end` is inclusive like for hooks.
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct AddressRange {
+    pub begin: u64,
+    pub end: u64,
+}
+
+impl From<RangeInclusive<u64>> for AddressRange {
+    fn from(range: RangeInclusive<u64>) -> Self {
+        AddressRange {
+            begin: *range.start(),
+            end: *range.end(),
+        }
+    }
+}
+
+/// What a compare routine registered for CmpLog compares, mirrors
+/// `uc_afl_cmplog_rtn_kind` in `unicornafl.h`.
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CmpLogRoutineKind {
+    /// `int strcmp(const char* s1, const char* s2)`
+    Strcmp = 0,
+    /// `int strncmp(const char* s1, const char* s2, size_t n)`
+    Strncmp = 1,
+    /// `int memcmp(const void* s1, const void* s2, size_t n)`
+    Memcmp = 2,
+}
+
+/// The calling convention of a compare routine, mirrors `uc_afl_cmplog_cc` in
+/// `unicornafl.h`.
+///
+/// Registers left at 0 (`INVALID`) are taken from the default calling convention
+/// of the arch instead.
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct CmpLogCallingConv {
+    /// The register with the pointer to the first operand.
+    pub s1_reg: i32,
+    /// The register with the pointer to the second operand.
+    pub s2_reg: i32,
+    /// The register with the length, unused for `CmpLogRoutineKind::Strcmp`.
+    pub n_reg: i32,
+}
+
+/// A compare routine in the target whose operands are logged for CmpLog.
+///
+/// The operands are read from the arguments when the routine is entered.
+#[repr(C)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct CmpLogRoutine {
+    /// The entry address of the routine.
+    pub address: u64,
+    pub kind: CmpLogRoutineKind,
+    pub cc: CmpLogCallingConv,
+}
+
+impl CmpLogRoutine {
+    /// A routine following the default calling convention of the arch.
+    pub fn new(address: u64, kind: CmpLogRoutineKind) -> Self {
+        CmpLogRoutine {
+            address,
+            kind,
+            cc: CmpLogCallingConv::default(),
+        }
+    }
+
+    /// Takes the operand pointers from `s1` and `s2` and the length from `n`,
+    /// e.g. `(arm::Register::R4, arm::Register::R5, Some(arm::Register::R6))`.
+    pub fn with_registers<T: Into<i32>>(mut self, s1: T, s2: T, n: Option<T>) -> Self {
+        self.cc = CmpLogCallingConv {
+            s1_reg: s1.into(),
+            s2_reg: s2.into(),
+            n_reg: n.map_or(0, Into::into),
+        };
+        self
+    }
+}
+
+/// Extra options for `afl_fuzz_with_options` and `afl_fuzz_custom_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct AflFuzzOptions {
+    /// Tokens offered to afl-fuzz as autodictionary during the forkserver handshake.
+    /// Each token may be at most 255 bytes long, empty tokens are skipped.
+    pub dictionary: Vec<Vec<u8>>,
+    /// Size of the coverage map in bytes, between 64 bytes and 1 GiB. Without it
+    /// `AFL_MAP_SIZE` is used, or 64k if that is not set either. afl-fuzz has to be
+    /// started with at least the same `AFL_MAP_SIZE`, or the harness fails to start.
+    pub map_size: Option<u32>,
+    /// Compare routines logged when afl-fuzz runs with CmpLog (`-c 0`). Compare
+    /// instructions are always logged.
+    pub cmplog_routines: Vec<CmpLogRoutine>,
+    /// How blocks are recorded in the coverage map.
+    pub coverage: CoverageMode,
+    /// If not empty, only blocks in these ranges are instrumented for coverage,
+    /// compcov and CmpLog. `AFL_UNICORN_INST_RANGES` adds to them, e.g.
+    /// `0x10000-0x1ffff,0x40000-0x40fff`.
+    pub coverage_ranges: Vec<AddressRange>,
+    /// Blocks in these ranges are never instrumented, even if they are in
+    /// `coverage_ranges`. `AFL_UNICORN_EXCLUDE_RANGES` adds to them.
+    pub coverage_exclude_ranges: Vec<AddressRange>,
+    /// In persistent mode, snapshot the registers before the first iteration and
+    /// restore them, together with every page the emulated code wrote to, before
+    /// each further iteration. Memory written with `mem_write`, e.g. in the input
+    /// placement callback, is not restored.
+    pub persistent_restore: bool,
+}
+
+impl AflFuzzOptions {
+    /// Encodes `dictionary` the way AFL++ expects it: every token prefixed with its length.
+    fn autodict(&self) -> Result<Vec<u8>, AflRet> {
+        let mut autodict = vec![];
+        for token in self.dictionary.iter().filter(|token| !token.is_empty()) {
+            let len = u8::try_from(token.len()).map_err(|_| AflRet::Error)?;
+            autodict.push(len);
+            autodict.extend_from_slice(token);
+        }
+        Ok(autodict)
+    }
+
+    /// The C view of these options, borrowing `autodict` from `autodict()`.
+    fn as_c_options(&self, autodict: &[u8]) -> uc_afl_options {
+        let (cov_mode, cov_ngram_size) = match self.coverage {
+            CoverageMode::Edge => (uc_afl_cov_mode::UC_AFL_COV_EDGE, 0),
+            CoverageMode::Block => (uc_afl_cov_mode::UC_AFL_COV_BLOCK, 0),
+            CoverageMode::NGram(n) => (uc_afl_cov_mode::UC_AFL_COV_NGRAM, n),
+            CoverageMode::Context => (uc_afl_cov_mode::UC_AFL_COV_CTX, 0),
+            CoverageMode::EdgeId => (uc_afl_cov_mode::UC_AFL_COV_EDGE_ID, 0),
+        };
+
+        uc_afl_options {
+            size: mem::size_of::<uc_afl_options>() as u32,
+            autodict: autodict.as_ptr(),
+            autodict_len: autodict.len(),
+            map_size: self.map_size.unwrap_or(0),
+            cmplog_rtns: self.cmplog_routines.as_ptr(),
+            cmplog_rtn_count: self.cmplog_routines.len(),
+            cov_mode,
+            cov_ngram_size,
+            cov_ranges: self.coverage_ranges.as_ptr(),
+            cov_range_count: self.coverage_ranges.len(),
+            cov_exclude_ranges: self.coverage_exclude_ranges.as_ptr(),
+            cov_exclude_range_count: self.coverage_exclude_ranges.len(),
+            persistent_restore: self.persistent_restore,
+        }
+    }
+}
+
+/// Mirrors `uc_afl_ret` in `unicornafl.h`.
 #[repr(C)]
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum AflRet {
-    Error = 0,
-    Child = 1,
-    NoAfl = 2,
-    Finished = 3,
+    /// Everything went fine.
+    Ok = 0,
+    /// Something went horribly wrong in the parent.
+    Error = 1,
+    /// Fork worked, we are a child.
+    Child = 2,
+    /// No AFL found, no need to fork.
+    NoAfl = 3,
+    /// Forkserver already running, this may be an error.
+    CalledTwice = 4,
+    /// We forked before but now AFL is gone, time to quit.
+    Finished = 5,
 }
 
+impl TryFrom<c_int> for AflRet {
+    type Error = c_int;
+
+    fn try_from(value: c_int) -> Result<Self, c_int> {
+        match value {
+            0 => Ok(AflRet::Ok),
+            1 => Ok(AflRet::Error),
+            2 => Ok(AflRet::Child),
+            3 => Ok(AflRet::NoAfl),
+            4 => Ok(AflRet::CalledTwice),
+            5 => Ok(AflRet::Finished),
+            _ => Err(value),
+        }
+    }
+}
+
+impl AflRet {
+    fn into_result(self) -> Result<(), AflRet> {
+        match self {
+            AflRet::Error | AflRet::CalledTwice => Err(self),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Placeholder fuzz callback type for `afl_fuzz`, where unicornafl runs the emulation itself.
+type AflFuzzDefault<'a, D> = fn(&mut Unicorn<'a, D>) -> Result<(), uc_error>;
+
 /// Callback structure we use to call handler functions
-struct AflFuzzCallback<'a, 'afl, D: 'a, F: 'a, G: 'a>
+struct AflFuzzCallback<'a, 'afl, D: 'a, F: 'a, G: 'a, H: 'a>
 where
     F: 'afl + FnMut(&mut Unicorn<'a, D>, &mut [u8], i32) -> bool,
     G: 'afl + FnMut(&mut Unicorn<'a, D>, uc_error, &[u8], i32) -> bool,
+    H: FnMut(&mut Unicorn<'a, D>) -> Result<(), uc_error>,
 {
     pub uc: Unicorn<'a, D>,
     pub input_callback: F,
     pub validate_callback: G,
+    /// Only set for `afl_fuzz_custom`, `afl_fuzz` lets unicornafl drive the emulation.
+    pub fuzz_callback: Option<H>,
     pub phantom: PhantomData<&'afl ()>,
 }
 
-unsafe extern "C" fn input_placement_callback_proxy<'a, 'afl, D, F, G>(
+unsafe extern "C" fn input_placement_callback_proxy<'a, 'afl, D, F, G, H>(
     uc: uc_handle,
     input: *mut u8,
-    input_len: c_int,
-    persistent_round: c_int,
-    user_data: *mut AflFuzzCallback<'a, 'afl, D, F, G>,
+    input_len: libc::size_t,
+    persistent_round: u32,
+    user_data: *mut AflFuzzCallback<'a, 'afl, D, F, G, H>,
 ) -> bool
 where
     F: 'afl + FnMut(&mut Unicorn<'a, D>, &mut [u8], i32) -> bool,
     G: 'afl + FnMut(&mut Unicorn<'a, D>, uc_error, &[u8], i32) -> bool,
+    H: FnMut(&mut Unicorn<'a, D>) -> Result<(), uc_error>,
 {
     let user_data = &mut *user_data;
     debug_assert_eq!(uc, user_data.uc.inner().uc);
-    debug_assert!(input_len >= 0);
-    #[allow(clippy::cast_sign_loss)]
-    let safe_input = slice::from_raw_parts_mut(input, input_len as usize);
-    (user_data.input_callback)(&mut user_data.uc, safe_input, persistent_round)
+    let safe_input = slice::from_raw_parts_mut(input, input_len);
+    #[allow(clippy::cast_possible_wrap)]
+    (user_data.input_callback)(&mut user_data.uc, safe_input, persistent_round as i32)
 }
 
-unsafe extern "C" fn crash_validation_callback_proxy<'a, 'afl, D, F, G>(
+unsafe extern "C" fn crash_validation_callback_proxy<'a, 'afl, D, F, G, H>(
     uc: uc_handle,
     error: uc_error,
     input: *mut u8,
     input_len: c_int,
     persistent_round: c_int,
-    user_data: *mut AflFuzzCallback<'a, 'afl, D, F, G>,
+    user_data: *mut AflFuzzCallback<'a, 'afl, D, F, G, H>,
 ) -> bool
 where
     F: 'afl + FnMut(&mut Unicorn<'a, D>, &mut [u8], i32) -> bool,
     G: 'afl + FnMut(&mut Unicorn<'a, D>, uc_error, &[u8], i32) -> bool,
+    H: FnMut(&mut Unicorn<'a, D>) -> Result<(), uc_error>,
 {
     let user_data = &mut *user_data;
     debug_assert_eq!(uc, user_data.uc.inner().uc);
@@ -88,17 +344,61 @@ where
     (user_data.validate_callback)(&mut user_data.uc, error, safe_input, persistent_round)
 }
 
+unsafe extern "C" fn fuzz_callback_proxy<'a, 'afl, D, F, G, H>(
+    uc: uc_handle,
+    user_data: *mut AflFuzzCallback<'a, 'afl, D, F, G, H>,
+) -> uc_error
+where
+    F: 'afl + FnMut(&mut Unicorn<'a, D>, &mut [u8], i32) -> bool,
+    G: 'afl + FnMut(&mut Unicorn<'a, D>, uc_error, &[u8], i32) -> bool,
+    H: FnMut(&mut Unicorn<'a, D>) -> Result<(), uc_error>,
+{
+    let user_data = &mut *user_data;
+    debug_assert_eq!(uc, user_data.uc.inner().uc);
+    let fuzz_callback = user_data
+        .fuzz_callback
+        .as_mut()
+        .expect("fuzz callback proxy called without a fuzz callback");
+    match fuzz_callback(&mut user_data.uc) {
+        Ok(()) => uc_error::OK,
+        Err(err) => err,
+    }
+}
+
+/// The side of the fork `afl_forkserver_start` returned in.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ForkserverRole {
+    /// A freshly forked child, read the input and run the emulation, then exit.
+    Child,
+    /// The forkserver parent, AFL is gone and it is time to quit.
+    Parent,
+    /// No AFL found and nothing was forked, run the emulation once.
+    NoAfl,
+}
+
+impl ForkserverRole {
+    fn from_ret(ret: AflRet) -> Result<Self, AflRet> {
+        match ret {
+            AflRet::Child => Ok(ForkserverRole::Child),
+            AflRet::Finished => Ok(ForkserverRole::Parent),
+            AflRet::NoAfl => Ok(ForkserverRole::NoAfl),
+            _ => Err(ret),
+        }
+    }
+}
+
 /// Starts the AFL forkserver on some Unicorn emulation.
 ///
-/// Multiple exit addresses can be specified. The Unicorn emulation has to be
-/// started manually before by using `emu_start`.
-pub fn afl_forkserver_start<'a, D>(uc: &mut Unicorn<'a, D>, exits: &[u64]) -> Result<(), AflRet> {
+/// Unlike `afl_fuzz`, this does not own the run loop: the process forks for every
+/// testcase right here, similar to `AFL_ENTRYPOINT` in QEMU mode. The child gets
+/// `ForkserverRole::Child` and is expected to read its input, run the emulation
+/// with `emu_start` and exit. Multiple exit addresses can be specified.
+pub fn afl_forkserver_start<'a, D>(
+    uc: &mut Unicorn<'a, D>,
+    exits: &[u64],
+) -> Result<ForkserverRole, AflRet> {
     let err = unsafe { uc_afl_forkserver_start(uc.inner().uc, exits.as_ptr(), exits.len()) };
-    if err == AflRet::Error {
-        Err(err)
-    } else {
-        Ok(())
-    }
+    ForkserverRole::from_ret(err)
 }
 
 /// All-in-one fuzzing setup function.
@@ -117,6 +417,34 @@ pub fn afl_fuzz<'afl, 'a, D, F, G>(
     always_validate: bool,
     persistent_iters: u32,
 ) -> Result<(), AflRet>
+where
+    F: 'a + 'afl + FnMut(&mut Unicorn<'a, D>, &mut [u8], i32) -> bool,
+    G: 'a + 'afl + FnMut(&mut Unicorn<'a, D>, uc_error, &[u8], i32) -> bool,
+{
+    afl_fuzz_with_options(
+        uc,
+        input_file,
+        input_placement_callback,
+        exits,
+        crash_validation_callback,
+        always_validate,
+        persistent_iters,
+        &AflFuzzOptions::default(),
+    )
+}
+
+/// `afl_fuzz` with extra options, see `AflFuzzOptions`.
+#[allow(clippy::too_many_arguments)]
+pub fn afl_fuzz_with_options<'afl, 'a, D, F, G>(
+    uc: &mut Unicorn<'a, D>,
+    input_file: &str,
+    input_placement_callback: F,
+    exits: &[u64],
+    crash_validation_callback: G,
+    always_validate: bool,
+    persistent_iters: u32,
+    options: &AflFuzzOptions,
+) -> Result<(), AflRet>
 where
     F: 'a + 'afl + FnMut(&mut Unicorn<'a, D>, &mut [u8], i32) -> bool,
     G: 'a + 'afl + FnMut(&mut Unicorn<'a, D>, uc_error, &[u8], i32) -> bool,
@@ -124,32 +452,252 @@ where
     let afl_fuzz_callback = Box::pin(AflFuzzCallback {
         input_callback: input_placement_callback,
         validate_callback: crash_validation_callback,
+        fuzz_callback: None::<AflFuzzDefault<'a, D>>,
         uc: Unicorn {
             inner: uc.inner.clone(),
         },
         phantom: PhantomData,
     });
 
-    #[allow(clippy::cast_possible_wrap)]
-    let mut cstyle_input_file: Vec<i8> = input_file.bytes().map(|x| x as i8).collect();
-    cstyle_input_file.push(0);
+    let cstyle_input_file = CString::new(input_file).map_err(|_| AflRet::Error)?;
+    let autodict = options.autodict()?;
+    let c_options = options.as_c_options(&autodict);
 
     let err = unsafe {
-        uc_afl_fuzz(
+        uc_afl_fuzz_ex(
             uc.inner_mut().uc,
             cstyle_input_file.as_ptr(),
-            input_placement_callback_proxy::<'a, 'afl, D, F, G> as _,
+            input_placement_callback_proxy::<'a, 'afl, D, F, G, AflFuzzDefault<'a, D>> as _,
             exits.as_ptr(),
             exits.len(),
-            crash_validation_callback_proxy::<'a, 'afl, D, F, G> as _,
+            ptr::null_mut(),
+            crash_validation_callback_proxy::<'a, 'afl, D, F, G, AflFuzzDefault<'a, D>> as _,
+            always_validate,
+            persistent_iters,
+            &c_options,
+            &*afl_fuzz_callback as *const _ as _,
+        )
+    };
+    err.into_result()
+}
+
+/// Fuzzing setup function with a custom fuzz callback.
+///
+/// Works like `afl_fuzz`, but instead of running a single `emu_start` up to the
+/// exits, unicornafl calls `fuzz_callback` for every testcase once the input has
+/// been placed. This allows harnesses to drive the emulation themselves, e.g. with
+/// several `emu_start` calls or a syscall loop. Any error returned by the callback
+/// is handed to `crash_validation_callback` like an emulation error would be.
+pub fn afl_fuzz_custom<'afl, 'a, D, F, G, H>(
+    uc: &mut Unicorn<'a, D>,
+    input_file: &str,
+    input_placement_callback: F,
+    fuzz_callback: H,
+    crash_validation_callback: G,
+    always_validate: bool,
+    persistent_iters: u32,
+) -> Result<(), AflRet>
+where
+    F: 'a + 'afl + FnMut(&mut Unicorn<'a, D>, &mut [u8], i32) -> bool,
+    G: 'a + 'afl + FnMut(&mut Unicorn<'a, D>, uc_error, &[u8], i32) -> bool,
+    H: 'a + 'afl + FnMut(&mut Unicorn<'a, D>) -> Result<(), uc_error>,
+{
+    afl_fuzz_custom_with_options(
+        uc,
+        input_file,
+        input_placement_callback,
+        fuzz_callback,
+        crash_validation_callback,
+        always_validate,
+        persistent_iters,
+        &AflFuzzOptions::default(),
+    )
+}
+
+/// `afl_fuzz_custom` with extra options, see `AflFuzzOptions`.
+#[allow(clippy::too_many_arguments)]
+pub fn afl_fuzz_custom_with_options<'afl, 'a, D, F, G, H>(
+    uc: &mut Unicorn<'a, D>,
+    input_file: &str,
+    input_placement_callback: F,
+    fuzz_callback: H,
+    crash_validation_callback: G,
+    always_validate: bool,
+    persistent_iters: u32,
+    options: &AflFuzzOptions,
+) -> Result<(), AflRet>
+where
+    F: 'a + 'afl + FnMut(&mut Unicorn<'a, D>, &mut [u8], i32) -> bool,
+    G: 'a + 'afl + FnMut(&mut Unicorn<'a, D>, uc_error, &[u8], i32) -> bool,
+    H: 'a + 'afl + FnMut(&mut Unicorn<'a, D>) -> Result<(), uc_error>,
+{
+    let afl_fuzz_callback = Box::pin(AflFuzzCallback {
+        input_callback: input_placement_callback,
+        validate_callback: crash_validation_callback,
+        fuzz_callback: Some(fuzz_callback),
+        uc: Unicorn {
+            inner: uc.inner.clone(),
+        },
+        phantom: PhantomData,
+    });
+
+    let cstyle_input_file = CString::new(input_file).map_err(|_| AflRet::Error)?;
+    let autodict = options.autodict()?;
+    let c_options = options.as_c_options(&autodict);
+
+    let err = unsafe {
+        uc_afl_fuzz_ex(
+            uc.inner_mut().uc,
+            cstyle_input_file.as_ptr(),
+            input_placement_callback_proxy::<'a, 'afl, D, F, G, H> as _,
+            ptr::null(),
+            0,
+            fuzz_callback_proxy::<'a, 'afl, D, F, G, H> as _,
+            crash_validation_callback_proxy::<'a, 'afl, D, F, G, H> as _,
             always_validate,
             persistent_iters,
+            &c_options,
             &*afl_fuzz_callback as *const _ as _,
         )
     };
-    if err == AflRet::Error {
-        Err(err)
-    } else {
-        Ok(())
+    err.into_result()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AflFuzzOptions, AflRet, CmpLogCallingConv, CmpLogRoutine, CmpLogRoutineKind,
+        ForkserverRole,
+    };
+    use std::convert::TryFrom;
+    use std::ffi::c_int;
+    use std::mem;
+
+    const HEADER: &str = include_str!("../../../include/unicornafl/unicornafl.h");
+
+    const RUST_RETS: [(&str, AflRet); 6] = [
+        ("UC_AFL_RET_OK", AflRet::Ok),
+        ("UC_AFL_RET_ERROR", AflRet::Error),
+        ("UC_AFL_RET_CHILD", AflRet::Child),
+        ("UC_AFL_RET_NO_AFL", AflRet::NoAfl),
+        ("UC_AFL_RET_CALLED_TWICE", AflRet::CalledTwice),
+        ("UC_AFL_RET_FINISHED", AflRet::Finished),
+    ];
+
+    /// Parses `typedef enum uc_afl_ret { ... }` from the C header.
+    fn header_rets() -> Vec<(String, c_int)> {
+        let start = HEADER
+            .find("typedef enum uc_afl_ret {")
+            .expect("uc_afl_ret not found in unicornafl.h");
+        let body = &HEADER[start..];
+        let body = &body[body.find('{').unwrap() + 1..body.find('}').unwrap()];
+
+        let mut next = 0;
+        body.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (name, value) = match entry.split_once('=') {
+                    Some((name, value)) => (name.trim(), value.trim().parse().unwrap()),
+                    None => (entry, next),
+                };
+                next = value + 1;
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn header_matches_afl_ret() {
+        let header = header_rets();
+        assert_eq!(header.len(), RUST_RETS.len());
+        for ((c_name, c_value), (rust_name, rust_ret)) in header.iter().zip(RUST_RETS.iter()) {
+            assert_eq!(c_name, rust_name);
+            assert_eq!(*c_value, *rust_ret as c_int, "{} differs", c_name);
+        }
+    }
+
+    #[test]
+    fn afl_ret_round_trips() {
+        for (_, ret) in RUST_RETS.iter() {
+            assert_eq!(AflRet::try_from(*ret as c_int), Ok(*ret));
+        }
+        for (_, value) in header_rets() {
+            assert_eq!(AflRet::try_from(value).unwrap() as c_int, value);
+        }
+    }
+
+    #[test]
+    fn afl_ret_rejects_unknown_values() {
+        assert_eq!(AflRet::try_from(-1), Err(-1));
+        assert_eq!(
+            AflRet::try_from(RUST_RETS.len() as c_int),
+            Err(RUST_RETS.len() as c_int)
+        );
+    }
+
+    #[test]
+    fn afl_ret_has_c_enum_size() {
+        assert_eq!(mem::size_of::<AflRet>(), mem::size_of::<c_int>());
+    }
+
+    #[test]
+    fn into_result_only_fails_on_errors() {
+        assert_eq!(AflRet::Ok.into_result(), Ok(()));
+        assert_eq!(AflRet::Child.into_result(), Ok(()));
+        assert_eq!(AflRet::NoAfl.into_result(), Ok(()));
+        assert_eq!(AflRet::Finished.into_result(), Ok(()));
+        assert_eq!(AflRet::Error.into_result(), Err(AflRet::Error));
+        assert_eq!(AflRet::CalledTwice.into_result(), Err(AflRet::CalledTwice));
+    }
+
+    #[test]
+    fn forkserver_role_from_ret() {
+        assert_eq!(ForkserverRole::from_ret(AflRet::Child), Ok(ForkserverRole::Child));
+        assert_eq!(ForkserverRole::from_ret(AflRet::Finished), Ok(ForkserverRole::Parent));
+        assert_eq!(ForkserverRole::from_ret(AflRet::NoAfl), Ok(ForkserverRole::NoAfl));
+        assert_eq!(ForkserverRole::from_ret(AflRet::Ok), Err(AflRet::Ok));
+        assert_eq!(ForkserverRole::from_ret(AflRet::Error), Err(AflRet::Error));
+        assert_eq!(
+            ForkserverRole::from_ret(AflRet::CalledTwice),
+            Err(AflRet::CalledTwice)
+        );
+    }
+
+    #[test]
+    fn autodict_is_length_prefixed() {
+        let options = AflFuzzOptions {
+            dictionary: vec![b"GET".to_vec(), vec![], b"\x7fELF".to_vec()],
+            ..Default::default()
+        };
+        assert_eq!(options.autodict(), Ok(b"\x03GET\x04\x7fELF".to_vec()));
+        assert_eq!(AflFuzzOptions::default().autodict(), Ok(vec![]));
+    }
+
+    #[test]
+    fn autodict_rejects_long_tokens() {
+        let options = AflFuzzOptions {
+            dictionary: vec![vec![0x41; 256]],
+            ..Default::default()
+        };
+        assert_eq!(options.autodict(), Err(AflRet::Error));
+    }
+
+    #[test]
+    fn cmplog_routine_registers() {
+        let rtn = CmpLogRoutine::new(0x1000, CmpLogRoutineKind::Memcmp);
+        assert_eq!(rtn.cc, CmpLogCallingConv::default());
+
+        let rtn = rtn.with_registers(crate::arm::Register::R4, crate::arm::Register::R5, None);
+        assert_eq!(rtn.cc.s1_reg, crate::arm::Register::R4 as i32);
+        assert_eq!(rtn.cc.s2_reg, crate::arm::Register::R5 as i32);
+        assert_eq!(rtn.cc.n_reg, 0);
+    }
+
+    #[test]
+    fn cmplog_routine_has_c_layout() {
+        // uint64_t address, uc_afl_cmplog_rtn_kind kind, uc_afl_cmplog_cc cc
+        assert_eq!(mem::size_of::<CmpLogCallingConv>(), 12);
+        assert_eq!(mem::size_of::<CmpLogRoutine>(), 24);
     }
 }
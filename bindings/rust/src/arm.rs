@@ -170,3 +170,52 @@ impl From<Register> for i32 {
         r as i32
     }
 }
+
+// ARM CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel {
+    ARM_926 = 0,
+    ARM_946,
+    ARM_1026,
+    ARM_1136_R2,
+    ARM_1136,
+    ARM_1176,
+    ARM_11MPCORE,
+    CORTEX_M0,
+    CORTEX_M3,
+    CORTEX_M4,
+    CORTEX_M7,
+    CORTEX_M33,
+    CORTEX_R5,
+    CORTEX_R5F,
+    CORTEX_A7,
+    CORTEX_A8,
+    CORTEX_A9,
+    CORTEX_A15,
+    TI925T,
+    SA1100,
+    SA1110,
+    PXA250,
+    PXA255,
+    PXA260,
+    PXA261,
+    PXA262,
+    PXA270,
+    PXA270A0,
+    PXA270A1,
+    PXA270B0,
+    PXA270B1,
+    PXA270C0,
+    PXA270C5,
+    MAX,
+}
+
+impl From<CpuModel> for i32 {
+    fn from(model: CpuModel) -> Self {
+        model as i32
+    }
+}
+
+pub use crate::regs::arm::Regs;
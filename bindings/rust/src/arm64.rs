@@ -323,3 +323,22 @@ impl From<Register> for i32 {
         r as i32
     }
 }
+
+// ARM64 CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel {
+    A57 = 0,
+    A53,
+    A72,
+    MAX,
+}
+
+impl From<CpuModel> for i32 {
+    fn from(model: CpuModel) -> Self {
+        model as i32
+    }
+}
+
+pub use crate::regs::arm64::Regs;
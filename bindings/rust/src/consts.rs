@@ -78,6 +78,8 @@ bitflags! {
         const MEM_READ_AFTER = 0x2000;
 
         const INSN_INVALID = 0x4000;
+        const EDGE_GENERATED = 0x8000;
+        const TCG_OPCODE = 0x10000;
 
         const MEM_READ_INVALID = Self::MEM_READ_UNMAPPED.bits | Self::MEM_READ_PROT.bits;
         const MEM_WRITE_INVALID = Self::MEM_WRITE_UNMAPPED.bits | Self::MEM_WRITE_PROT.bits;
@@ -88,6 +90,24 @@ bitflags! {
     }
 }
 
+/// TCG opcodes that can be hooked with `Unicorn::add_tcg_opcode_hook`.
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum TcgOpCode {
+    SUB = 0,
+}
+
+bitflags! {
+    #[repr(C)]
+    pub struct TcgOpFlag: i32 {
+        /// Only opcodes that set the condition codes, e.g. the ones of a `cmp`.
+        const CMP = 1;
+        /// Only opcodes translated directly from an instruction, e.g. x86 `sub`.
+        const DIRECT = 2;
+    }
+}
+
 #[repr(C)]
 #[derive(PartialEq, Debug, Clone, Copy)]
 #[allow(clippy::upper_case_acronyms)]
@@ -128,7 +148,9 @@ pub enum Arch {
     SPARC = 6,
     M68K = 7,
     RISCV = 8,
-    MAX = 9,
+    S390X = 9,
+    TRICORE = 10,
+    MAX = 11,
 }
 
 bitflags! {
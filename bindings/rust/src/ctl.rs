@@ -0,0 +1,227 @@
+//! Safe wrappers of `uc_ctl`, see `Unicorn::ctl`.
+
+use libc::c_int;
+use std::marker::PhantomData;
+
+use crate::consts::{uc_error, Arch, Mode};
+use crate::ffi::{self, uc_handle};
+use crate::snapshot::arch_from_u32;
+
+pub use crate::ffi::uc_tb as TranslationBlock;
+
+// `uc_control_type` and the request encoding from unicorn.h
+pub(crate) const UC_CTL_IO_WRITE: u32 = 1;
+pub(crate) const UC_CTL_IO_READ: u32 = 2;
+pub(crate) const UC_CTL_UC_MODE: u32 = 0;
+pub(crate) const UC_CTL_UC_PAGE_SIZE: u32 = 1;
+pub(crate) const UC_CTL_UC_ARCH: u32 = 2;
+pub(crate) const UC_CTL_UC_TIMEOUT: u32 = 3;
+pub(crate) const UC_CTL_UC_USE_EXITS: u32 = 4;
+pub(crate) const UC_CTL_UC_EXITS_CNT: u32 = 5;
+pub(crate) const UC_CTL_UC_EXITS: u32 = 6;
+pub(crate) const UC_CTL_CPU_MODEL: u32 = 7;
+pub(crate) const UC_CTL_TB_REQUEST_CACHE: u32 = 8;
+pub(crate) const UC_CTL_TB_REMOVE_CACHE: u32 = 9;
+pub(crate) const UC_CTL_TB_FLUSH: u32 = 10;
+
+pub(crate) const fn uc_ctl_request(control: u32, nr: u32, rw: u32) -> u32 {
+    control | (nr << 26) | (rw << 30)
+}
+
+fn check(err: uc_error) -> Result<(), uc_error> {
+    if err == uc_error::OK {
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
+
+/// Controls of an engine, borrowed from `Unicorn::ctl`.
+pub struct Ctl<'u> {
+    uc: uc_handle,
+    _unicorn: PhantomData<&'u mut ()>,
+}
+
+impl<'u> Ctl<'u> {
+    pub(crate) fn new(uc: uc_handle) -> Self {
+        Ctl {
+            uc,
+            _unicorn: PhantomData,
+        }
+    }
+
+    fn read_int(&self, control: u32) -> Result<c_int, uc_error> {
+        let mut value: c_int = 0;
+        check(unsafe {
+            ffi::uc_ctl(
+                self.uc,
+                uc_ctl_request(control, 1, UC_CTL_IO_READ),
+                &mut value as *mut c_int,
+            )
+        })?;
+        Ok(value)
+    }
+
+    fn write_int(&mut self, control: u32, value: c_int) -> Result<(), uc_error> {
+        check(unsafe { ffi::uc_ctl(self.uc, uc_ctl_request(control, 1, UC_CTL_IO_WRITE), value) })
+    }
+
+    pub fn arch(&self) -> Result<Arch, uc_error> {
+        #[allow(clippy::cast_sign_loss)]
+        arch_from_u32(self.read_int(UC_CTL_UC_ARCH)? as u32).ok_or(uc_error::ARCH)
+    }
+
+    pub fn mode(&self) -> Result<Mode, uc_error> {
+        Ok(Mode::from_bits_truncate(self.read_int(UC_CTL_UC_MODE)?))
+    }
+
+    pub fn page_size(&self) -> Result<u32, uc_error> {
+        let mut page_size: u32 = 0;
+        check(unsafe {
+            ffi::uc_ctl(
+                self.uc,
+                uc_ctl_request(UC_CTL_UC_PAGE_SIZE, 1, UC_CTL_IO_READ),
+                &mut page_size as *mut u32,
+            )
+        })?;
+        Ok(page_size)
+    }
+
+    /// Set the page size, a power of two. Only possible before the first mapping,
+    /// and only on ARM and ARM64.
+    pub fn set_page_size(&mut self, page_size: u32) -> Result<(), uc_error> {
+        check(unsafe {
+            ffi::uc_ctl(
+                self.uc,
+                uc_ctl_request(UC_CTL_UC_PAGE_SIZE, 1, UC_CTL_IO_WRITE),
+                page_size,
+            )
+        })
+    }
+
+    /// The timeout of the current `emu_start`, in microseconds. It is set by
+    /// `emu_start` and can't be changed here.
+    pub fn timeout(&self) -> Result<u64, uc_error> {
+        let mut timeout: u64 = 0;
+        check(unsafe {
+            ffi::uc_ctl(
+                self.uc,
+                uc_ctl_request(UC_CTL_UC_TIMEOUT, 1, UC_CTL_IO_READ),
+                &mut timeout as *mut u64,
+            )
+        })?;
+        Ok(timeout)
+    }
+
+    /// Stop at any of the addresses set with `set_exits` instead of at the `until`
+    /// of `emu_start`, which is then ignored.
+    pub fn exits_enable(&mut self) -> Result<(), uc_error> {
+        self.write_int(UC_CTL_UC_USE_EXITS, 1)
+    }
+
+    pub fn exits_disable(&mut self) -> Result<(), uc_error> {
+        self.write_int(UC_CTL_UC_USE_EXITS, 0)
+    }
+
+    /// The exits set, only available after `exits_enable`.
+    pub fn exits(&self) -> Result<Vec<u64>, uc_error> {
+        let mut count: libc::size_t = 0;
+        check(unsafe {
+            ffi::uc_ctl(
+                self.uc,
+                uc_ctl_request(UC_CTL_UC_EXITS_CNT, 1, UC_CTL_IO_READ),
+                &mut count as *mut libc::size_t,
+            )
+        })?;
+
+        let mut exits = vec![0u64; count];
+        check(unsafe {
+            ffi::uc_ctl(
+                self.uc,
+                uc_ctl_request(UC_CTL_UC_EXITS, 2, UC_CTL_IO_READ),
+                exits.as_mut_ptr(),
+                count,
+            )
+        })?;
+        Ok(exits)
+    }
+
+    /// Replace the exits, see `exits_enable`.
+    pub fn set_exits(&mut self, exits: &[u64]) -> Result<(), uc_error> {
+        check(unsafe {
+            ffi::uc_ctl(
+                self.uc,
+                uc_ctl_request(UC_CTL_UC_EXITS, 2, UC_CTL_IO_WRITE),
+                exits.as_ptr(),
+                exits.len() as libc::size_t,
+            )
+        })
+    }
+
+    /// The CPU model, one of the `*_CPU_*` constants of the arch.
+    pub fn cpu_model(&self) -> Result<i32, uc_error> {
+        self.read_int(UC_CTL_CPU_MODEL)
+    }
+
+    /// Select the CPU model. Only possible before anything is mapped or emulated.
+    pub fn set_cpu_model<T: Into<i32>>(&mut self, model: T) -> Result<(), uc_error> {
+        self.write_int(UC_CTL_CPU_MODEL, model.into())
+    }
+
+    /// Translate the block at `address` now, so it is not translated again in
+    /// every child of a forkserver.
+    pub fn request_cache(&mut self, address: u64) -> Result<TranslationBlock, uc_error> {
+        let mut tb = TranslationBlock {
+            pc: 0,
+            icount: 0,
+            size: 0,
+        };
+        check(unsafe {
+            ffi::uc_ctl(
+                self.uc,
+                uc_ctl_request(UC_CTL_TB_REQUEST_CACHE, 2, UC_CTL_IO_READ | UC_CTL_IO_WRITE),
+                address,
+                &mut tb as *mut TranslationBlock,
+            )
+        })?;
+        Ok(tb)
+    }
+
+    /// Drop the translated blocks in `begin..end`, e.g. after patching code.
+    pub fn remove_cache(&mut self, begin: u64, end: u64) -> Result<(), uc_error> {
+        check(unsafe {
+            ffi::uc_ctl(
+                self.uc,
+                uc_ctl_request(UC_CTL_TB_REMOVE_CACHE, 2, UC_CTL_IO_WRITE),
+                begin,
+                end,
+            )
+        })
+    }
+
+    /// Drop all translated blocks.
+    pub fn flush_tb(&mut self) -> Result<(), uc_error> {
+        check(unsafe { ffi::uc_ctl(self.uc, uc_ctl_request(UC_CTL_TB_FLUSH, 0, UC_CTL_IO_WRITE)) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        uc_ctl_request, UC_CTL_IO_READ, UC_CTL_IO_WRITE, UC_CTL_TB_REQUEST_CACHE, UC_CTL_UC_EXITS,
+    };
+
+    #[test]
+    fn requests_match_unicorn_h() {
+        // UC_CTL_WRITE(UC_CTL_UC_EXITS, 2)
+        assert_eq!(
+            uc_ctl_request(UC_CTL_UC_EXITS, 2, UC_CTL_IO_WRITE),
+            0x4800_0006
+        );
+        // UC_CTL_READ_WRITE(UC_CTL_TB_REQUEST_CACHE, 2)
+        assert_eq!(
+            uc_ctl_request(UC_CTL_TB_REQUEST_CACHE, 2, UC_CTL_IO_READ | UC_CTL_IO_WRITE),
+            0xc800_0008
+        );
+    }
+}
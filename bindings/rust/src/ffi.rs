@@ -1,15 +1,26 @@
 #![allow(non_camel_case_types)]
 #![allow(dead_code)]
 
-use crate::Unicorn;
+use crate::hook::{call_hook, HookCtx};
+use crate::{MemHookAction, UnicornInner};
 use crate::consts::{uc_error, Arch, HookType, MemRegion, MemType, Mode, Query};
 
+use std::cell::UnsafeCell;
 use std::ffi::{c_void, c_char, c_int};
+use std::rc::Weak;
 
 pub type uc_handle = *mut c_void;
 pub type uc_hook = *mut c_void;
 pub type uc_context = *mut c_void;
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct uc_tb {
+    pub pc: u64,
+    pub icount: u16,
+    pub size: u16,
+}
+
 extern "C" {
     pub fn uc_version(major: *mut u32, minor: *mut u32) -> u32;
     pub fn uc_arch_supported(arch: Arch) -> bool;
@@ -20,6 +31,18 @@ extern "C" {
     pub fn uc_strerror(error_code: uc_error) -> *const c_char;
     pub fn uc_reg_write(engine: uc_handle, regid: c_int, value: *const c_void) -> uc_error;
     pub fn uc_reg_read(engine: uc_handle, regid: c_int, value: *mut c_void) -> uc_error;
+    pub fn uc_reg_write_batch(
+        engine: uc_handle,
+        regids: *const c_int,
+        values: *const *const c_void,
+        count: c_int,
+    ) -> uc_error;
+    pub fn uc_reg_read_batch(
+        engine: uc_handle,
+        regids: *const c_int,
+        values: *const *mut c_void,
+        count: c_int,
+    ) -> uc_error;
     pub fn uc_mem_write(
         engine: uc_handle,
         address: u64,
@@ -81,14 +104,23 @@ extern "C" {
     ) -> uc_error;
     pub fn uc_hook_del(engine: uc_handle, hook: uc_hook) -> uc_error;
     pub fn uc_query(engine: uc_handle, query_type: Query, result: *mut libc::size_t) -> uc_error;
+    pub fn uc_ctl(engine: uc_handle, control: u32, ...) -> uc_error;
     pub fn uc_context_alloc(engine: uc_handle, context: *mut uc_context) -> uc_error;
+    pub fn uc_context_size(engine: uc_handle) -> libc::size_t;
     pub fn uc_context_save(engine: uc_handle, context: uc_context) -> uc_error;
     pub fn uc_context_restore(engine: uc_handle, context: uc_context) -> uc_error;
+    pub fn uc_context_reg_write(
+        context: uc_context,
+        regid: c_int,
+        value: *const c_void,
+    ) -> uc_error;
+    pub fn uc_context_reg_read(context: uc_context, regid: c_int, value: *mut c_void) -> uc_error;
 }
 
 pub struct UcHook<'a, D: 'a, F: 'a> {
     pub callback: F,
-    pub uc: Unicorn<'a, D>,
+    /// Weak, the engine owns its hooks
+    pub uc: Weak<UnsafeCell<UnicornInner<'a, D>>>,
 }
 
 pub trait IsUcHook<'a> {}
@@ -102,11 +134,9 @@ pub extern "C" fn mmio_read_callback_proxy<D, F>(
     user_data: *mut UcHook<D, F>,
 ) -> u64
 where
-    F: FnMut(&mut Unicorn<D>, u64, usize) -> u64,
+    F: FnMut(&mut HookCtx<D>, u64, usize) -> u64,
 {
-    let user_data = unsafe { &mut *user_data };
-    debug_assert_eq!(uc, user_data.uc.inner().uc);
-    (user_data.callback)(&mut user_data.uc, offset, size)
+    call_hook(uc, user_data, |callback, ctx| callback(ctx, offset, size))
 }
 
 pub extern "C" fn mmio_write_callback_proxy<D, F>(
@@ -116,11 +146,9 @@ pub extern "C" fn mmio_write_callback_proxy<D, F>(
     value: u64,
     user_data: *mut UcHook<D, F>,
 ) where
-    F: FnMut(&mut Unicorn<D>, u64, usize, u64),
+    F: FnMut(&mut HookCtx<D>, u64, usize, u64),
 {
-    let user_data = unsafe { &mut *user_data };
-    debug_assert_eq!(uc, user_data.uc.inner().uc);
-    (user_data.callback)(&mut user_data.uc, offset, size, value);
+    call_hook(uc, user_data, |callback, ctx| callback(ctx, offset, size, value));
 }
 
 pub extern "C" fn code_hook_proxy<D, F>(
@@ -129,11 +157,9 @@ pub extern "C" fn code_hook_proxy<D, F>(
     size: u32,
     user_data: *mut UcHook<D, F>,
 ) where
-    F: FnMut(&mut Unicorn<D>, u64, u32),
+    F: FnMut(&mut HookCtx<D>, u64, u32),
 {
-    let user_data = unsafe { &mut *user_data };
-    debug_assert_eq!(uc, user_data.uc.inner().uc);
-    (user_data.callback)(&mut user_data.uc, address, size);
+    call_hook(uc, user_data, |callback, ctx| callback(ctx, address, size));
 }
 
 pub extern "C" fn block_hook_proxy<D, F>(
@@ -142,11 +168,34 @@ pub extern "C" fn block_hook_proxy<D, F>(
     size: u32,
     user_data: *mut UcHook<D, F>,
 ) where
-    F: FnMut(&mut Unicorn<D>, u64, u32),
+    F: FnMut(&mut HookCtx<D>, u64, u32),
+{
+    call_hook(uc, user_data, |callback, ctx| callback(ctx, address, size));
+}
+
+pub extern "C" fn tcg_opcode_hook_proxy<D, F>(
+    uc: uc_handle,
+    address: u64,
+    arg1: u64,
+    arg2: u64,
+    size: u32,
+    user_data: *mut UcHook<D, F>,
+) where
+    F: FnMut(&mut HookCtx<D>, u64, u64, u64, u32),
+{
+    call_hook(uc, user_data, |callback, ctx| callback(ctx, address, arg1, arg2, size));
+}
+
+pub extern "C" fn edge_gen_hook_proxy<D, F>(
+    uc: uc_handle,
+    cur_tb: *mut uc_tb,
+    prev_tb: *mut uc_tb,
+    user_data: *mut UcHook<D, F>,
+) where
+    F: FnMut(&mut HookCtx<D>, &uc_tb, &uc_tb),
 {
-    let user_data = unsafe { &mut *user_data };
-    debug_assert_eq!(uc, user_data.uc.inner().uc);
-    (user_data.callback)(&mut user_data.uc, address, size);
+    let (cur_tb, prev_tb) = unsafe { (&*cur_tb, &*prev_tb) };
+    call_hook(uc, user_data, |callback, ctx| callback(ctx, cur_tb, prev_tb));
 }
 
 pub extern "C" fn mem_hook_proxy<D, F>(
@@ -158,20 +207,42 @@ pub extern "C" fn mem_hook_proxy<D, F>(
     user_data: *mut UcHook<D, F>,
 ) -> bool
 where
-    F: FnMut(&mut Unicorn<D>, MemType, u64, usize, i64) -> bool,
+    F: FnMut(&mut HookCtx<D>, MemType, u64, usize, i64) -> bool,
+{
+    call_hook(uc, user_data, |callback, ctx| {
+        callback(ctx, mem_type, address, size as usize, value)
+    })
+}
+
+pub extern "C" fn mem_fault_hook_proxy<D, F>(
+    uc: uc_handle,
+    mem_type: MemType,
+    address: u64,
+    size: u32,
+    value: i64,
+    user_data: *mut UcHook<D, F>,
+) -> bool
+where
+    F: FnMut(&mut HookCtx<D>, MemType, u64, usize, i64) -> MemHookAction,
+{
+    call_hook(uc, user_data, |callback, ctx| {
+        let action = callback(ctx, mem_type, address, size as usize, value);
+        ctx.resolve_mem_fault(action, mem_type, address, u64::from(size))
+    })
+}
+
+pub extern "C" fn insn_invalid_hook_proxy<D, F>(uc: uc_handle, user_data: *mut UcHook<D, F>) -> bool
+where
+    F: FnMut(&mut HookCtx<D>) -> bool,
 {
-    let user_data = unsafe { &mut *user_data };
-    debug_assert_eq!(uc, user_data.uc.inner().uc);
-    (user_data.callback)(&mut user_data.uc, mem_type, address, size as usize, value)
+    call_hook(uc, user_data, |callback, ctx| callback(ctx))
 }
 
 pub extern "C" fn intr_hook_proxy<D, F>(uc: uc_handle, value: u32, user_data: *mut UcHook<D, F>)
 where
-    F: FnMut(&mut Unicorn<D>, u32),
+    F: FnMut(&mut HookCtx<D>, u32),
 {
-    let user_data = unsafe { &mut *user_data };
-    debug_assert_eq!(uc, user_data.uc.inner().uc);
-    (user_data.callback)(&mut user_data.uc, value);
+    call_hook(uc, user_data, |callback, ctx| callback(ctx, value));
 }
 
 pub extern "C" fn insn_in_hook_proxy<D, F>(
@@ -180,11 +251,9 @@ pub extern "C" fn insn_in_hook_proxy<D, F>(
     size: usize,
     user_data: *mut UcHook<D, F>,
 ) where
-    F: FnMut(&mut Unicorn<D>, u32, usize),
+    F: FnMut(&mut HookCtx<D>, u32, usize),
 {
-    let user_data = unsafe { &mut *user_data };
-    debug_assert_eq!(uc, user_data.uc.inner().uc);
-    (user_data.callback)(&mut user_data.uc, port, size);
+    call_hook(uc, user_data, |callback, ctx| callback(ctx, port, size));
 }
 
 pub extern "C" fn insn_out_hook_proxy<D, F>(
@@ -194,18 +263,14 @@ pub extern "C" fn insn_out_hook_proxy<D, F>(
     value: u32,
     user_data: *mut UcHook<D, F>,
 ) where
-    F: FnMut(&mut Unicorn<D>, u32, usize, u32),
+    F: FnMut(&mut HookCtx<D>, u32, usize, u32),
 {
-    let user_data = unsafe { &mut *user_data };
-    debug_assert_eq!(uc, user_data.uc.inner().uc);
-    (user_data.callback)(&mut user_data.uc, port, size, value);
+    call_hook(uc, user_data, |callback, ctx| callback(ctx, port, size, value));
 }
 
 pub extern "C" fn insn_sys_hook_proxy<D, F>(uc: uc_handle, user_data: *mut UcHook<D, F>)
 where
-    F: FnMut(&mut Unicorn<D>),
+    F: FnMut(&mut HookCtx<D>),
 {
-    let user_data = unsafe { &mut *user_data };
-    debug_assert_eq!(uc, user_data.uc.inner().uc);
-    (user_data.callback)(&mut user_data.uc);
+    call_hook(uc, user_data, |callback, ctx| callback(ctx));
 }
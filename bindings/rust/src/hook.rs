@@ -0,0 +1,192 @@
+//! What hook callbacks get to work with while the engine is emulating.
+
+use tinyvec::ArrayVec;
+
+use crate::consts::{uc_error, Arch, MemRegion, MemType, Permission, Query};
+use crate::ffi::{self, UcHook};
+use crate::{Context, MemHookAction, RegisterFile, Unicorn};
+
+/// The engine as seen from a hook or MMIO callback.
+///
+/// Only operations that are safe in the middle of an emulation are available.
+/// Removing hooks and unmapping MMIO regions would free the closure that is
+/// running, so hooks are removed once the outermost callback returned, and MMIO
+/// regions are unmapped once `emu_start` returned. MMIO regions can't be read or
+/// written, that would call their callback, possibly the running one.
+pub struct HookCtx<'h, 'a, D> {
+    pub(crate) uc: &'h mut Unicorn<'a, D>,
+}
+
+impl<'h, 'a, D> HookCtx<'h, 'a, D> {
+    #[must_use]
+    pub fn get_data(&self) -> &D {
+        self.uc.get_data()
+    }
+
+    #[must_use]
+    pub fn get_data_mut(&mut self) -> &mut D {
+        self.uc.get_data_mut()
+    }
+
+    #[must_use]
+    pub fn get_arch(&self) -> Arch {
+        self.uc.get_arch()
+    }
+
+    pub fn query(&self, query: Query) -> Result<usize, uc_error> {
+        self.uc.query(query)
+    }
+
+    pub fn reg_read<T: Into<i32>>(&self, regid: T) -> Result<u64, uc_error> {
+        self.uc.reg_read(regid)
+    }
+
+    pub fn reg_read_i32<T: Into<i32>>(&self, regid: T) -> Result<i32, uc_error> {
+        self.uc.reg_read_i32(regid)
+    }
+
+    pub fn reg_read_long<T: Into<i32>>(&self, regid: T) -> Result<ArrayVec<[u8; 64]>, uc_error> {
+        self.uc.reg_read_long(regid)
+    }
+
+    pub fn reg_read_batch(&self, regids: &[i32]) -> Result<Vec<u64>, uc_error> {
+        self.uc.reg_read_batch(regids)
+    }
+
+    pub fn regs_read<R: RegisterFile>(&self) -> Result<R, uc_error> {
+        self.uc.regs_read()
+    }
+
+    pub fn reg_write<T: Into<i32>>(&mut self, regid: T, value: u64) -> Result<(), uc_error> {
+        self.uc.reg_write(regid, value)
+    }
+
+    pub fn reg_write_long<T: Into<i32>>(&mut self, regid: T, value: &[u8]) -> Result<(), uc_error> {
+        self.uc.reg_write_long(regid, value)
+    }
+
+    pub fn reg_write_batch(&mut self, regs: &[(i32, u64)]) -> Result<(), uc_error> {
+        self.uc.reg_write_batch(regs)
+    }
+
+    pub fn regs_write<R: RegisterFile>(&mut self, regs: &R) -> Result<(), uc_error> {
+        self.uc.regs_write(regs)
+    }
+
+    pub fn pc_read(&self) -> Result<u64, uc_error> {
+        self.uc.pc_read()
+    }
+
+    pub fn pc_write(&mut self, value: u64) -> Result<(), uc_error> {
+        self.uc.pc_write(value)
+    }
+
+    pub fn mem_regions(&self) -> Result<Vec<MemRegion>, uc_error> {
+        self.uc.mem_regions()
+    }
+
+    /// Fails with `uc_error::ARG` on MMIO regions.
+    pub fn mem_read(&self, address: u64, buf: &mut [u8]) -> Result<(), uc_error> {
+        self.check_not_mmio(address, buf.len())?;
+        self.uc.mem_read(address, buf)
+    }
+
+    /// Fails with `uc_error::ARG` on MMIO regions.
+    pub fn mem_read_as_vec(&self, address: u64, size: usize) -> Result<Vec<u8>, uc_error> {
+        self.check_not_mmio(address, size)?;
+        self.uc.mem_read_as_vec(address, size)
+    }
+
+    /// Fails with `uc_error::ARG` on MMIO regions.
+    pub fn mem_write(&mut self, address: u64, bytes: &[u8]) -> Result<(), uc_error> {
+        self.check_not_mmio(address, bytes.len())?;
+        self.uc.mem_write(address, bytes)
+    }
+
+    fn check_not_mmio(&self, address: u64, size: usize) -> Result<(), uc_error> {
+        if self.uc.touches_mmio(address, size) {
+            Err(uc_error::ARG)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn mem_map(
+        &mut self,
+        address: u64,
+        size: libc::size_t,
+        perms: Permission,
+    ) -> Result<(), uc_error> {
+        self.uc.mem_map(address, size, perms)
+    }
+
+    pub fn mem_protect(
+        &mut self,
+        address: u64,
+        size: libc::size_t,
+        perms: Permission,
+    ) -> Result<(), uc_error> {
+        self.uc.mem_protect(address, size, perms)
+    }
+
+    /// Unmap a memory region. A range with MMIO regions in it is only unmapped once
+    /// `emu_start` returned, errors are lost then.
+    pub fn mem_unmap(&mut self, address: u64, size: libc::size_t) -> Result<(), uc_error> {
+        self.uc.mem_unmap(address, size)
+    }
+
+    /// Remove a hook once the callback returned, possibly the running one.
+    pub fn remove_hook(&mut self, hook: ffi::uc_hook) {
+        let _ = self.uc.remove_hook(hook);
+    }
+
+    pub fn context_save(&self, context: &mut Context) -> Result<(), uc_error> {
+        self.uc.context_save(context)
+    }
+
+    pub fn context_init(&self) -> Result<Context, uc_error> {
+        self.uc.context_init()
+    }
+
+    pub fn emu_stop(&mut self) -> Result<(), uc_error> {
+        self.uc.emu_stop()
+    }
+
+    pub(crate) fn resolve_mem_fault(
+        &mut self,
+        action: MemHookAction,
+        mem_type: MemType,
+        address: u64,
+        size: u64,
+    ) -> bool {
+        self.uc.resolve_mem_fault(action, mem_type, address, size)
+    }
+}
+
+/// Calls the callback of a hook, then carries out what it deferred.
+pub(crate) fn call_hook<'a, D, F, R>(
+    uc: ffi::uc_handle,
+    user_data: *mut UcHook<'a, D, F>,
+    call: impl FnOnce(&mut F, &mut HookCtx<'_, 'a, D>) -> R,
+) -> R {
+    let user_data = unsafe { &mut *user_data };
+    let handle = uc;
+
+    // The callback may remove its own hook, which frees `user_data` once the
+    // deferred operations run. The engine is closed before its hooks are dropped,
+    // so it is still alive.
+    let inner = user_data
+        .uc
+        .upgrade()
+        .expect("hook called on a dropped engine");
+    let mut uc = Unicorn { inner };
+    debug_assert_eq!(handle, uc.inner().uc);
+    uc.inner_mut().hook_depth += 1;
+    let result = call(&mut user_data.callback, &mut HookCtx { uc: &mut uc });
+    uc.inner_mut().hook_depth -= 1;
+
+    if uc.inner().hook_depth == 0 {
+        uc.run_deferred(false);
+    }
+    result
+}
@@ -29,24 +29,47 @@ pub mod afl;
 pub mod arm;
 pub mod arm64;
 pub mod consts;
+pub mod ctl;
 pub mod m68k;
 pub mod mips;
 pub mod ppc;
 pub mod riscv;
+pub mod s390x;
 pub mod sparc;
+pub mod tricore;
 pub mod x86;
 
 mod ffi;
-
-use crate::consts::{uc_error, Arch, HookType, MemRegion, MemType, Mode, Permission, Query};
+mod hook;
+mod regs;
+#[cfg(feature = "rust-runtime")]
+mod runtime;
+mod send;
+mod snapshot;
+
+pub use crate::hook::HookCtx;
+pub use crate::regs::{RegisterFile, RegisterValue, RegisterVisitor};
+pub use crate::send::SendUnicorn;
+pub use crate::snapshot::SnapshotError;
+
+use crate::consts::{
+    uc_error, Arch, HookType, MemRegion, MemType, Mode, Permission, Query, TcgOpCode, TcgOpFlag,
+};
+use crate::ctl::{Ctl, TranslationBlock};
 use crate::ffi::uc_handle;
+use crate::snapshot::{ContextFile, ContextFileRegister, SnapshotFile, SnapshotFileRegion};
 
 use std::cell::UnsafeCell;
-use std::ffi::c_void;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{c_int, c_void};
 use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
 use std::mem;
+use std::path::Path;
 use std::ptr;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
 use tinyvec::ArrayVec;
 
 #[derive(Debug)]
@@ -59,8 +82,87 @@ impl Context {
     pub fn is_initialized(&self) -> bool {
         !self.context.is_null()
     }
+
+    /// Read an unsigned value from a register saved in the context, truncated to
+    /// 64 bits like with `Unicorn::reg_read_batch`.
+    pub fn reg_read<T: Into<i32>>(&self, regid: T) -> Result<u64, uc_error> {
+        let mut buf = [0u8; 64];
+        let err =
+            unsafe { ffi::uc_context_reg_read(self.context, regid.into(), buf.as_mut_ptr() as _) };
+        if err == uc_error::OK {
+            Ok(u64::from_ne_bytes(buf[..8].try_into().unwrap()))
+        } else {
+            Err(err)
+        }
+    }
+
+    /// Write an unsigned value to a register saved in the context, to be set when
+    /// the context is restored. Larger registers get the value zero-extended.
+    pub fn reg_write<T: Into<i32>>(&mut self, regid: T, value: u64) -> Result<(), uc_error> {
+        let mut buf = [0u8; 64];
+        buf[..8].copy_from_slice(&value.to_ne_bytes());
+        let err =
+            unsafe { ffi::uc_context_reg_write(self.context, regid.into(), buf.as_ptr() as _) };
+        if err == uc_error::OK {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+
+    /// Read the registers of the register file of `arch` and `mode` for a context
+    /// or snapshot file.
+    fn to_file(&self, arch: Arch, mode: Mode, cpu_model: i32) -> Result<ContextFile, uc_error> {
+        let mut registers = vec![];
+        for (id, size) in register_layout(arch, mode)? {
+            let mut buf = [0u8; 64];
+            let err = unsafe { ffi::uc_context_reg_read(self.context, id, buf.as_mut_ptr() as _) };
+            if err != uc_error::OK {
+                return Err(err);
+            }
+            registers.push(ContextFileRegister {
+                id,
+                value: buf[..size].to_vec(),
+            });
+        }
+        Ok(ContextFile {
+            arch,
+            mode,
+            cpu_model,
+            registers,
+        })
+    }
+
+    /// Write the registers of a context or snapshot file, in the order they were
+    /// saved.
+    fn load_file(&mut self, file: &ContextFile) -> Result<(), SnapshotError> {
+        let layout = register_layout(file.arch, file.mode)?;
+        let same_layout = layout.len() == file.registers.len()
+            && layout
+                .iter()
+                .zip(&file.registers)
+                .all(|(&(id, size), register)| id == register.id && size == register.value.len());
+        if !same_layout {
+            return Err(SnapshotError::Mismatch);
+        }
+
+        for register in &file.registers {
+            let mut buf = [0u8; 64];
+            buf[..register.value.len()].copy_from_slice(&register.value);
+            let err =
+                unsafe { ffi::uc_context_reg_write(self.context, register.id, buf.as_ptr() as _) };
+            if err != uc_error::OK {
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
 }
 
+// A `uc_context` is a plain allocation, it is not tied to the thread or the engine
+// it was saved on.
+unsafe impl Send for Context {}
+
 impl Drop for Context {
     fn drop(&mut self) {
         if self.is_initialized() {
@@ -72,6 +174,68 @@ impl Drop for Context {
     }
 }
 
+/// A mapped region saved in a `Snapshot`, page by page.
+#[derive(Debug)]
+struct SnapshotRegion {
+    begin: u64,
+    end: u64,
+    perms: Permission,
+    pages: Vec<Arc<[u8]>>,
+}
+
+impl SnapshotRegion {
+    fn size(&self) -> usize {
+        (self.end - self.begin + 1) as usize
+    }
+}
+
+/// The whole machine state: the CPU context and all mapped memory with its
+/// permissions, see `Unicorn::snapshot`.
+///
+/// Pages are shared with the previous snapshot of the same engine as long as their
+/// contents did not change, so taking many snapshots is cheap.
+#[derive(Debug)]
+pub struct Snapshot {
+    arch: Arch,
+    mode: Mode,
+    cpu_model: i32,
+    page_size: usize,
+    context: Context,
+    // Size of the whole `uc_context` allocation
+    context_size: usize,
+    regions: Vec<SnapshotRegion>,
+}
+
+impl Snapshot {
+    /// Save the snapshot to `path`, to be loaded with `Unicorn::load_snapshot` or
+    /// `Unicorn::from_snapshot_file`.
+    ///
+    /// Of the CPU state only the registers of the arch's `RegisterFile` are saved,
+    /// e.g. `arm::Regs`. Fails with `uc_error::ARCH` if the mode has none.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        #[allow(clippy::cast_possible_truncation)]
+        let file = SnapshotFile {
+            context: self.context.to_file(self.arch, self.mode, self.cpu_model)?,
+            page_size: self.page_size as u32,
+            regions: self
+                .regions
+                .iter()
+                .map(|region| SnapshotFileRegion {
+                    begin: region.begin,
+                    end: region.end,
+                    perms: region.perms,
+                    data: region.pages.concat(),
+                })
+                .collect(),
+        };
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        file.write(&mut writer)?;
+        writer.into_inner().map_err(|err| err.into_error())?;
+        Ok(())
+    }
+}
+
 pub struct MmioCallbackScope<'a> {
     pub regions: Vec<(u64, usize)>,
     pub read_callback: Option<Box<dyn ffi::IsUcHook<'a> + 'a>>,
@@ -124,18 +288,183 @@ impl<'a> MmioCallbackScope<'a> {
     }
 }
 
-pub struct UnicornInner<'a, D> {
+/// How emulation goes on after an unmapped or protection fault, see
+/// `Unicorn::add_mem_fault_hook`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum MemHookAction {
+    /// Retry the access, the hook fixed the fault itself.
+    Continue,
+    /// Map the faulting pages, zero filled, with these permissions, or set the
+    /// permissions of the pages on a protection fault, then retry the access.
+    MapAndRetry(Permission),
+    /// Stop emulation, `emu_start` fails with the error of the fault, e.g.
+    /// `uc_error::READ_UNMAPPED`.
+    Stop,
+}
+
+/// Pages written since tracking started or the last `clear_dirty`.
+struct DirtyPages {
+    page_size: u64,
+    pages: BTreeSet<u64>,
+    /// Pages written since the last `snapshot` or `restore`, `None` before the
+    /// first one while tracking
+    since_snapshot: Option<BTreeSet<u64>>,
+}
+
+impl DirtyPages {
+    fn mark(&mut self, address: u64, size: u64) {
+        let pages = touched_pages(address, size, self.page_size);
+        match self.since_snapshot.as_mut() {
+            Some(since_snapshot) => {
+                for page in pages {
+                    self.pages.insert(page);
+                    since_snapshot.insert(page);
+                }
+            }
+            None => self.pages.extend(pages),
+        }
+    }
+}
+
+/// The ids of the registers in a register file, in visiting order.
+fn register_ids<R: RegisterFile>(regs: &mut R) -> Result<Vec<i32>, uc_error> {
+    let mut regids = vec![];
+    regs.for_each_register(&mut |regid, _| {
+        regids.push(regid);
+        Ok(())
+    })?;
+    Ok(regids)
+}
+
+/// The ids and sizes of the registers of the register file of `arch` and `mode`,
+/// the ones saved in context and snapshot files.
+fn register_layout(arch: Arch, mode: Mode) -> Result<Vec<(i32, usize)>, uc_error> {
+    fn layout<R: RegisterFile>() -> Result<Vec<(i32, usize)>, uc_error> {
+        let mut layout = vec![];
+        R::default().for_each_register(&mut |regid, value| {
+            layout.push((regid, value.len()));
+            Ok(())
+        })?;
+        Ok(layout)
+    }
+
+    let layout = match arch {
+        Arch::ARM => layout::<arm::Regs>,
+        Arch::ARM64 => layout::<arm64::Regs>,
+        Arch::X86 if x86::Regs::supports(arch, mode) => layout::<x86::Regs>,
+        Arch::X86 if x86::Regs32::supports(arch, mode) => layout::<x86::Regs32>,
+        Arch::MIPS => layout::<mips::Regs>,
+        Arch::PPC => layout::<ppc::Regs>,
+        Arch::RISCV => layout::<riscv::Regs>,
+        Arch::SPARC => layout::<sparc::Regs>,
+        Arch::M68K => layout::<m68k::Regs>,
+        Arch::S390X => layout::<s390x::Regs>,
+        Arch::TRICORE => layout::<tricore::Regs>,
+        _ => return Err(uc_error::ARCH),
+    };
+    layout()
+}
+
+/// The pages touched by an access of `size` bytes at `address`.
+pub(crate) fn touched_pages(address: u64, size: u64, page_size: u64) -> impl Iterator<Item = u64> {
+    let page_mask = !(page_size - 1);
+    let last = address.wrapping_add(size.max(1) - 1) & page_mask;
+    (address & page_mask..=last).step_by(page_size as usize)
+}
+
+/// A hook added with one of the `add_*_hook` methods, see `Unicorn::hooks`.
+#[derive(Debug, Clone, Copy)]
+pub struct HookInfo {
+    pub hook: ffi::uc_hook,
+    pub hook_type: HookType,
+    /// The hooked range, the whole address space if `begin > end`.
+    pub begin: u64,
+    pub end: u64,
+}
+
+struct HookEntry<'a> {
+    info: HookInfo,
+    /// Tells apart hooks that Unicorn gave the same `uc_hook`
+    serial: u64,
+    // Only owned here, Unicorn calls it through the pointer passed to uc_hook_add
+    #[allow(dead_code)]
+    callback: Box<dyn ffi::IsUcHook<'a> + 'a>,
+}
+
+/// Owns a hook added with one of the `add_*_hook` methods and removes it when
+/// dropped.
+#[must_use = "the hook is removed when its handle is dropped"]
+pub struct HookHandle<'a, D: 'a> {
+    inner: Weak<UnsafeCell<UnicornInner<'a, D>>>,
+    hook: ffi::uc_hook,
+    serial: u64,
+}
+
+impl<'a, D: 'a> HookHandle<'a, D> {
+    #[must_use]
+    pub fn raw(&self) -> ffi::uc_hook {
+        self.hook
+    }
+
+    /// Keep the hook as long as the engine lives. It can still be removed with
+    /// `Unicorn::remove_hook`.
+    pub fn keep(mut self) -> ffi::uc_hook {
+        self.inner = Weak::new();
+        self.hook
+    }
+
+    /// Remove the hook now, unlike dropping the handle this reports errors.
+    pub fn remove(mut self) -> Result<(), uc_error> {
+        match mem::take(&mut self.inner).upgrade() {
+            Some(inner) => Unicorn { inner }.remove_hook_serial(self.serial),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a, D: 'a> Drop for HookHandle<'a, D> {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.upgrade() {
+            let _ = Unicorn { inner }.remove_hook_serial(self.serial);
+        }
+    }
+}
+
+impl<'a, D: 'a> fmt::Debug for HookHandle<'a, D> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "HookHandle {{ hook: {:p} }}", self.hook)
+    }
+}
+
+pub struct UnicornInner<'a, D: 'a> {
     pub uc: uc_handle,
     pub arch: Arch,
     /// to keep ownership over the hook for this uc instance's lifetime
-    pub hooks: Vec<(ffi::uc_hook, Box<dyn ffi::IsUcHook<'a> + 'a>)>,
+    hooks: Vec<HookEntry<'a>>,
     /// To keep ownership over the mmio callbacks for this uc instance's lifetime
     pub mmio_callbacks: Vec<MmioCallbackScope<'a>>,
     pub data: D,
+    /// Set by `track_dirty_pages`
+    dirty_pages: Option<DirtyPages>,
+    /// The write hook of `track_dirty_pages`, dropped by `untrack_dirty_pages`
+    dirty_pages_hook: Option<HookHandle<'a, D>>,
+    /// The pages of the last `snapshot` or `restore`, shared with the next snapshot
+    snapshot_pages: HashMap<u64, Arc<[u8]>>,
+    /// How many hook callbacks are running, see `HookCtx`
+    hook_depth: u32,
+    /// What callbacks asked for that would free a running callback
+    deferred: Vec<Deferred>,
+    /// The serial of the next hook added
+    next_hook_serial: u64,
+}
+
+enum Deferred {
+    RemoveHook(ffi::uc_hook),
+    Unmap(u64, libc::size_t),
 }
 
 /// Drop UC
-impl<'a, D> Drop for UnicornInner<'a, D> {
+impl<'a, D: 'a> Drop for UnicornInner<'a, D> {
     fn drop(&mut self) {
         if !self.uc.is_null() {
             unsafe { ffi::uc_close(self.uc) };
@@ -149,12 +478,90 @@ pub struct Unicorn<'a, D: 'a> {
     inner: Rc<UnsafeCell<UnicornInner<'a, D>>>,
 }
 
+/// Creates an engine with a CPU model or page size, see `Unicorn::builder`.
+#[derive(Debug, Clone, Copy)]
+pub struct UnicornBuilder {
+    arch: Arch,
+    mode: Mode,
+    cpu_model: Option<i32>,
+    page_size: Option<u32>,
+}
+
+impl UnicornBuilder {
+    #[must_use]
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Select the CPU model, e.g. `arm::CpuModel::CORTEX_M33`, instead of the
+    /// default one of the arch and mode.
+    #[must_use]
+    pub fn cpu_model<T: Into<i32>>(mut self, model: T) -> Self {
+        self.cpu_model = Some(model.into());
+        self
+    }
+
+    /// Set the page size, a power of two. Only supported on ARM and ARM64.
+    #[must_use]
+    pub fn page_size(mut self, page_size: u32) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    pub fn build<'a>(self) -> Result<Unicorn<'a, ()>, uc_error> {
+        self.build_with_data(())
+    }
+
+    pub fn build_with_data<'a, D: 'a>(self, data: D) -> Result<Unicorn<'a, D>, uc_error> {
+        let mut uc = Unicorn::new_with_data(self.arch, self.mode, data)?;
+        if let Some(model) = self.cpu_model {
+            uc.ctl().set_cpu_model(model)?;
+        }
+        if let Some(page_size) = self.page_size {
+            uc.ctl().set_page_size(page_size)?;
+        }
+        Ok(uc)
+    }
+}
+
 impl<'a> Unicorn<'a, ()> {
     /// Create a new instance of the unicorn engine for the specified architecture
     /// and hardware mode.
     pub fn new(arch: Arch, mode: Mode) -> Result<Unicorn<'a, ()>, uc_error> {
         Self::new_with_data(arch, mode, ())
     }
+
+    /// Configure a new instance of the unicorn engine, e.g.
+    /// `Unicorn::builder(Arch::ARM).mode(Mode::THUMB).cpu_model(arm::CpuModel::CORTEX_M33).build()`.
+    ///
+    /// The mode defaults to `Mode::LITTLE_ENDIAN`.
+    #[must_use]
+    pub fn builder(arch: Arch) -> UnicornBuilder {
+        UnicornBuilder {
+            arch,
+            mode: Mode::LITTLE_ENDIAN,
+            cpu_model: None,
+            page_size: None,
+        }
+    }
+
+    /// Create a new instance of the unicorn engine in the state saved with
+    /// `Snapshot::save`.
+    pub fn from_snapshot_file<P: AsRef<Path>>(path: P) -> Result<Unicorn<'a, ()>, SnapshotError> {
+        let file = SnapshotFile::read(&mut BufReader::new(File::open(path)?))?;
+        let mut uc = Self::builder(file.context.arch)
+            .mode(file.context.mode)
+            .cpu_model(file.context.cpu_model)
+            .build()?;
+        // Only set when needed, most arches don't support setting it at all.
+        if uc.query(Query::PAGE_SIZE)? != file.page_size as usize {
+            uc.ctl().set_page_size(file.page_size)?;
+        }
+        let snapshot = uc.snapshot_from_file(file)?;
+        uc.restore(&snapshot)?;
+        Ok(uc)
+    }
 }
 
 impl<'a, D> Unicorn<'a, D>
@@ -174,6 +581,12 @@ where
                     data,
                     hooks: vec![],
                     mmio_callbacks: vec![],
+                    dirty_pages: None,
+                    dirty_pages_hook: None,
+                    snapshot_pages: HashMap::new(),
+                    hook_depth: 0,
+                    deferred: vec![],
+                    next_hook_serial: 0,
                 })),
             })
         } else {
@@ -259,6 +672,9 @@ impl<'a, D> Unicorn<'a, D> {
         let err =
             unsafe { ffi::uc_mem_write(self.inner().uc, address, bytes.as_ptr(), bytes.len()) };
         if err == uc_error::OK {
+            if let Some(dirty_pages) = self.inner_mut().dirty_pages.as_mut() {
+                dirty_pages.mark(address, bytes.len() as u64);
+            }
             Ok(())
         } else {
             Err(err)
@@ -323,23 +739,19 @@ impl<'a, D> Unicorn<'a, D> {
         write_callback: Option<W>,
     ) -> Result<(), uc_error>
     where
-        R: FnMut(&mut Unicorn<D>, u64, usize) -> u64,
-        W: FnMut(&mut Unicorn<D>, u64, usize, u64),
+        R: FnMut(&mut HookCtx<D>, u64, usize) -> u64,
+        W: FnMut(&mut HookCtx<D>, u64, usize, u64),
     {
         let mut read_data = read_callback.map(|c| {
             Box::new(ffi::UcHook {
                 callback: c,
-                uc: Unicorn {
-                    inner: self.inner.clone(),
-                },
+                uc: Rc::downgrade(&self.inner),
             })
         });
         let mut write_data = write_callback.map(|c| {
             Box::new(ffi::UcHook {
                 callback: c,
-                uc: Unicorn {
-                    inner: self.inner.clone(),
-                },
+                uc: Rc::downgrade(&self.inner),
             })
         });
 
@@ -387,13 +799,13 @@ impl<'a, D> Unicorn<'a, D> {
         callback: F,
     ) -> Result<(), uc_error>
     where
-        F: FnMut(&mut Unicorn<D>, u64, usize) -> u64,
+        F: FnMut(&mut HookCtx<D>, u64, usize) -> u64,
     {
         self.mmio_map(
             address,
             size,
             Some(callback),
-            None::<fn(&mut Unicorn<D>, u64, usize, u64)>,
+            None::<fn(&mut HookCtx<D>, u64, usize, u64)>,
         )
     }
 
@@ -408,12 +820,12 @@ impl<'a, D> Unicorn<'a, D> {
         callback: F,
     ) -> Result<(), uc_error>
     where
-        F: FnMut(&mut Unicorn<D>, u64, usize, u64),
+        F: FnMut(&mut HookCtx<D>, u64, usize, u64),
     {
         self.mmio_map(
             address,
             size,
-            None::<fn(&mut Unicorn<D>, u64, usize) -> u64>,
+            None::<fn(&mut HookCtx<D>, u64, usize) -> u64>,
             Some(callback),
         )
     }
@@ -422,10 +834,22 @@ impl<'a, D> Unicorn<'a, D> {
     ///
     /// `address` must be aligned to 4kb or this will return `Error::ARG`.
     /// `size` must be a multiple of 4kb or this will return `Error::ARG`.
+    ///
+    /// Inside a hook, a range with MMIO regions in it is only unmapped once
+    /// `emu_start` returned, as their callback may be running. This returns `Ok(())`
+    /// without doing anything then and errors are lost.
     pub fn mem_unmap(&mut self, address: u64, size: libc::size_t) -> Result<(), uc_error> {
+        if self.inner().hook_depth > 0 && self.touches_mmio(address, size) {
+            self.inner_mut().deferred.push(Deferred::Unmap(address, size));
+            return Ok(());
+        }
         let err = unsafe { ffi::uc_mem_unmap(self.inner().uc, address, size) };
 
         self.mmio_unmap(address, size);
+        // Mapped again, the pages start out zeroed and not as last snapshotted.
+        self.inner_mut()
+            .snapshot_pages
+            .retain(|&page, _| page < address || page - address >= size as u64);
 
         if err == uc_error::OK {
             Ok(())
@@ -461,6 +885,59 @@ impl<'a, D> Unicorn<'a, D> {
         }
     }
 
+    /// Start recording which pages are written to, by the emulated code and by
+    /// `mem_write`.
+    ///
+    /// Memory changed through a pointer passed to `mem_map_ptr` is not seen. Calling
+    /// this again while tracking is a no-op.
+    pub fn track_dirty_pages(&mut self) -> Result<(), uc_error> {
+        if self.inner().dirty_pages.is_some() {
+            return Ok(());
+        }
+
+        let page_size = self.query(Query::PAGE_SIZE)? as u64;
+        let hook = self.add_mem_hook(HookType::MEM_WRITE, 1, 0, |ctx, _, address, size, _| {
+            if let Some(dirty_pages) = ctx.uc.inner_mut().dirty_pages.as_mut() {
+                dirty_pages.mark(address, size as u64);
+            }
+            true
+        })?;
+        self.inner_mut().dirty_pages_hook = Some(hook);
+        self.inner_mut().dirty_pages = Some(DirtyPages {
+            page_size,
+            pages: BTreeSet::new(),
+            since_snapshot: None,
+        });
+        Ok(())
+    }
+
+    /// Stop recording written pages and remove the write hook of
+    /// `track_dirty_pages`. The pages recorded so far are forgotten.
+    pub fn untrack_dirty_pages(&mut self) {
+        let hook = self.inner_mut().dirty_pages_hook.take();
+        self.inner_mut().dirty_pages = None;
+        drop(hook);
+    }
+
+    /// The page-aligned addresses of all pages written since `track_dirty_pages` or
+    /// the last `clear_dirty`, in ascending order.
+    #[must_use]
+    pub fn dirty_pages(&self) -> Vec<u64> {
+        self.inner()
+            .dirty_pages
+            .as_ref()
+            .map_or_else(Vec::new, |dirty_pages| {
+                dirty_pages.pages.iter().copied().collect()
+            })
+    }
+
+    /// Forget the pages written so far, e.g. once they have been restored.
+    pub fn clear_dirty(&mut self) {
+        if let Some(dirty_pages) = self.inner_mut().dirty_pages.as_mut() {
+            dirty_pages.pages.clear();
+        }
+    }
+
     /// Write an unsigned value from a register.
     pub fn reg_write<T: Into<i32>>(&mut self, regid: T, value: u64) -> Result<(), uc_error> {
         let err =
@@ -564,22 +1041,136 @@ impl<'a, D> Unicorn<'a, D> {
         }
     }
 
+    /// Read unsigned values from several registers in one call.
+    ///
+    /// Registers larger than 64 bit are truncated to their low 64 bits.
+    pub fn reg_read_batch(&self, regids: &[i32]) -> Result<Vec<u64>, uc_error> {
+        // Like `regs_read`, Unicorn writes as much as the register holds.
+        let mut bufs = vec![[0u8; 64]; regids.len()];
+        let ptrs: Vec<*mut c_void> = bufs.iter_mut().map(|buf| buf.as_mut_ptr() as _).collect();
+        self.reg_read_batch_raw(regids, &ptrs)?;
+        Ok(bufs
+            .iter()
+            .map(|buf| u64::from_ne_bytes(buf[..8].try_into().unwrap()))
+            .collect())
+    }
+
+    /// Write unsigned values to several registers in one call, in order.
+    ///
+    /// Registers larger than 64 bit get the value zero-extended.
+    pub fn reg_write_batch(&mut self, regs: &[(i32, u64)]) -> Result<(), uc_error> {
+        let regids: Vec<i32> = regs.iter().map(|(regid, _)| *regid).collect();
+        let bufs: Vec<[u8; 64]> = regs
+            .iter()
+            .map(|(_, value)| {
+                let mut buf = [0u8; 64];
+                buf[..8].copy_from_slice(&value.to_ne_bytes());
+                buf
+            })
+            .collect();
+        let ptrs: Vec<*const c_void> = bufs.iter().map(|buf| buf.as_ptr() as _).collect();
+        self.reg_write_batch_raw(&regids, &ptrs)
+    }
+
+    fn reg_read_batch_raw(&self, regids: &[i32], values: &[*mut c_void]) -> Result<(), uc_error> {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        let err = unsafe {
+            ffi::uc_reg_read_batch(
+                self.inner().uc,
+                regids.as_ptr(),
+                values.as_ptr(),
+                regids.len() as i32,
+            )
+        };
+        if err == uc_error::OK {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+
+    fn reg_write_batch_raw(
+        &self,
+        regids: &[i32],
+        values: &[*const c_void],
+    ) -> Result<(), uc_error> {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        let err = unsafe {
+            ffi::uc_reg_write_batch(
+                self.inner().uc,
+                regids.as_ptr(),
+                values.as_ptr(),
+                regids.len() as i32,
+            )
+        };
+        if err == uc_error::OK {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+
+    /// Read all registers of a register file, e.g. `uc.regs_read::<x86::Regs>()`.
+    ///
+    /// Fails with `uc_error::ARCH` if the register file doesn't fit the engine's arch
+    /// and mode.
+    pub fn regs_read<R: RegisterFile>(&self) -> Result<R, uc_error> {
+        self.check_register_file::<R>()?;
+        let mut regs = R::default();
+        let regids = register_ids(&mut regs)?;
+        // Unicorn may store more than a field holds, e.g. a 64-bit PC, so it reads
+        // into buffers that are copied afterwards.
+        let mut bufs = vec![[0u8; 64]; regids.len()];
+        let ptrs: Vec<*mut c_void> = bufs.iter_mut().map(|buf| buf.as_mut_ptr() as _).collect();
+        self.reg_read_batch_raw(&regids, &ptrs)?;
+        let mut bufs = bufs.iter();
+        regs.for_each_register(&mut |_, value| {
+            let buf = bufs.next().ok_or(uc_error::ARG)?;
+            value.copy_from_slice(&buf[..value.len()]);
+            Ok(())
+        })?;
+        Ok(regs)
+    }
+
+    /// Write all registers of a register file, see `regs_read`.
+    pub fn regs_write<R: RegisterFile>(&mut self, regs: &R) -> Result<(), uc_error> {
+        self.check_register_file::<R>()?;
+        let mut regs = regs.clone();
+        let mut regids = vec![];
+        let mut bufs = vec![];
+        regs.for_each_register(&mut |regid, value| {
+            let mut buf = [0u8; 64];
+            buf[..value.len()].copy_from_slice(value);
+            regids.push(regid);
+            bufs.push(buf);
+            Ok(())
+        })?;
+        let ptrs: Vec<*const c_void> = bufs.iter().map(|buf| buf.as_ptr() as _).collect();
+        self.reg_write_batch_raw(&regids, &ptrs)
+    }
+
+    fn check_register_file<R: RegisterFile>(&self) -> Result<(), uc_error> {
+        if R::supports(self.get_arch(), self.get_mode()?) {
+            Ok(())
+        } else {
+            Err(uc_error::ARCH)
+        }
+    }
+
     /// Add a code hook.
     pub fn add_code_hook<F: 'a>(
         &mut self,
         begin: u64,
         end: u64,
         callback: F,
-    ) -> Result<ffi::uc_hook, uc_error>
+    ) -> Result<HookHandle<'a, D>, uc_error>
     where
-        F: FnMut(&mut Unicorn<D>, u64, u32) + 'a,
+        F: FnMut(&mut HookCtx<D>, u64, u32) + 'a,
     {
         let mut hook_ptr = ptr::null_mut();
         let mut user_data = Box::new(ffi::UcHook {
             callback,
-            uc: Unicorn {
-                inner: self.inner.clone(),
-            },
+            uc: Rc::downgrade(&self.inner),
         });
 
         let err = unsafe {
@@ -594,24 +1185,21 @@ impl<'a, D> Unicorn<'a, D> {
             )
         };
         if err == uc_error::OK {
-            self.inner_mut().hooks.push((hook_ptr, user_data));
-            Ok(hook_ptr)
+            Ok(self.keep_hook(hook_ptr, HookType::CODE, begin, end, user_data))
         } else {
             Err(err)
         }
     }
 
     /// Add a block hook.
-    pub fn add_block_hook<F: 'a>(&mut self, callback: F) -> Result<ffi::uc_hook, uc_error>
+    pub fn add_block_hook<F: 'a>(&mut self, callback: F) -> Result<HookHandle<'a, D>, uc_error>
     where
-        F: FnMut(&mut Unicorn<D>, u64, u32),
+        F: FnMut(&mut HookCtx<D>, u64, u32),
     {
         let mut hook_ptr = ptr::null_mut();
         let mut user_data = Box::new(ffi::UcHook {
             callback,
-            uc: Unicorn {
-                inner: self.inner.clone(),
-            },
+            uc: Rc::downgrade(&self.inner),
         });
 
         let err = unsafe {
@@ -626,9 +1214,86 @@ impl<'a, D> Unicorn<'a, D> {
             )
         };
         if err == uc_error::OK {
-            self.inner_mut().hooks.push((hook_ptr, user_data));
+            Ok(self.keep_hook(hook_ptr, HookType::BLOCK, 1, 0, user_data))
+        } else {
+            Err(err)
+        }
+    }
 
-            Ok(hook_ptr)
+    /// Add a hook on a TCG opcode in the code at `begin..=end`, e.g. the `SUB` that
+    /// implements comparisons. `flags` narrows down which of them are hooked.
+    ///
+    /// The callback gets the address of the instruction, both operands and their
+    /// size in bits.
+    pub fn add_tcg_opcode_hook<F>(
+        &mut self,
+        begin: u64,
+        end: u64,
+        op: TcgOpCode,
+        flags: TcgOpFlag,
+        callback: F,
+    ) -> Result<HookHandle<'a, D>, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, u64, u64, u64, u32) + 'a,
+    {
+        let mut hook_ptr = ptr::null_mut();
+        let mut user_data = Box::new(ffi::UcHook {
+            callback,
+            uc: Rc::downgrade(&self.inner),
+        });
+
+        let err = unsafe {
+            ffi::uc_hook_add(
+                self.inner().uc,
+                &mut hook_ptr,
+                HookType::TCG_OPCODE,
+                ffi::tcg_opcode_hook_proxy::<D, F> as _,
+                user_data.as_mut() as *mut _ as _,
+                begin,
+                end,
+                op as c_int,
+                flags.bits(),
+            )
+        };
+        if err == uc_error::OK {
+            Ok(self.keep_hook(hook_ptr, HookType::TCG_OPCODE, begin, end, user_data))
+        } else {
+            Err(err)
+        }
+    }
+
+    /// Add a hook called whenever a new edge is translated, i.e. a block is
+    /// reached from a previous one for the first time. The callback gets the
+    /// current and the previous block.
+    ///
+    /// Blocks that are already chained are not reported again, so this is not a
+    /// replacement for a block hook.
+    pub fn add_edge_generated_hook<F>(
+        &mut self,
+        callback: F,
+    ) -> Result<HookHandle<'a, D>, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, &TranslationBlock, &TranslationBlock) + 'a,
+    {
+        let mut hook_ptr = ptr::null_mut();
+        let mut user_data = Box::new(ffi::UcHook {
+            callback,
+            uc: Rc::downgrade(&self.inner),
+        });
+
+        let err = unsafe {
+            ffi::uc_hook_add(
+                self.inner().uc,
+                &mut hook_ptr,
+                HookType::EDGE_GENERATED,
+                ffi::edge_gen_hook_proxy::<D, F> as _,
+                user_data.as_mut() as *mut _ as _,
+                1,
+                0,
+            )
+        };
+        if err == uc_error::OK {
+            Ok(self.keep_hook(hook_ptr, HookType::EDGE_GENERATED, 1, 0, user_data))
         } else {
             Err(err)
         }
@@ -641,9 +1306,9 @@ impl<'a, D> Unicorn<'a, D> {
         begin: u64,
         end: u64,
         callback: F,
-    ) -> Result<ffi::uc_hook, uc_error>
+    ) -> Result<HookHandle<'a, D>, uc_error>
     where
-        F: FnMut(&mut Unicorn<D>, MemType, u64, usize, i64) -> bool,
+        F: FnMut(&mut HookCtx<D>, MemType, u64, usize, i64) -> bool,
     {
         if !(HookType::MEM_ALL | HookType::MEM_READ_AFTER).contains(hook_type) {
             return Err(uc_error::ARG);
@@ -652,9 +1317,7 @@ impl<'a, D> Unicorn<'a, D> {
         let mut hook_ptr = ptr::null_mut();
         let mut user_data = Box::new(ffi::UcHook {
             callback,
-            uc: Unicorn {
-                inner: self.inner.clone(),
-            },
+            uc: Rc::downgrade(&self.inner),
         });
 
         let err = unsafe {
@@ -669,25 +1332,142 @@ impl<'a, D> Unicorn<'a, D> {
             )
         };
         if err == uc_error::OK {
-            self.inner_mut().hooks.push((hook_ptr, user_data));
+            Ok(self.keep_hook(hook_ptr, hook_type, begin, end, user_data))
+        } else {
+            Err(err)
+        }
+    }
+
+    /// Add a hook on unmapped or protection faults in `begin..=end`, whose callback
+    /// decides how emulation goes on, see `MemHookAction`.
+    ///
+    /// `hook_type` has to be a subset of `HookType::MEM_INVALID`.
+    pub fn add_mem_fault_hook<F>(
+        &mut self,
+        hook_type: HookType,
+        begin: u64,
+        end: u64,
+        callback: F,
+    ) -> Result<HookHandle<'a, D>, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, MemType, u64, usize, i64) -> MemHookAction + 'a,
+    {
+        if hook_type.is_empty() || !HookType::MEM_INVALID.contains(hook_type) {
+            return Err(uc_error::ARG);
+        }
 
-            Ok(hook_ptr)
+        let mut hook_ptr = ptr::null_mut();
+        let mut user_data = Box::new(ffi::UcHook {
+            callback,
+            uc: Rc::downgrade(&self.inner),
+        });
+
+        let err = unsafe {
+            ffi::uc_hook_add(
+                self.inner().uc,
+                &mut hook_ptr,
+                hook_type,
+                ffi::mem_fault_hook_proxy::<D, F> as _,
+                user_data.as_mut() as *mut _ as _,
+                begin,
+                end,
+            )
+        };
+        if err == uc_error::OK {
+            Ok(self.keep_hook(hook_ptr, hook_type, begin, end, user_data))
+        } else {
+            Err(err)
+        }
+    }
+
+    /// Carry out what a fault hook decided, returning whether the access is retried.
+    fn resolve_mem_fault(
+        &mut self,
+        action: MemHookAction,
+        mem_type: MemType,
+        address: u64,
+        size: u64,
+    ) -> bool {
+        let perms = match action {
+            MemHookAction::Continue => return true,
+            MemHookAction::Stop => return false,
+            MemHookAction::MapAndRetry(perms) => perms,
+        };
+        let page_size = match self.query(Query::PAGE_SIZE) {
+            Ok(page_size) => page_size,
+            Err(_) => return false,
+        };
+        let unmapped = matches!(
+            mem_type,
+            MemType::READ_UNMAPPED | MemType::WRITE_UNMAPPED | MemType::FETCH_UNMAPPED
+        );
+
+        let regions = match self.mem_regions() {
+            Ok(regions) => regions,
+            Err(_) => return false,
+        };
+
+        for page in touched_pages(address, size, page_size as u64) {
+            let is_mapped = regions
+                .iter()
+                .any(|region| region.begin <= page && page <= region.end);
+            let result = if unmapped && !is_mapped {
+                self.mem_map(page, page_size, perms)
+            } else if !unmapped {
+                self.mem_protect(page, page_size, perms)
+            } else {
+                Ok(())
+            };
+            if result.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Add a hook on invalid instructions. The callback returns `true` once it has
+    /// emulated the instruction, i.e. moved the PC past it, and `false` to stop with
+    /// `uc_error::INSN_INVALID`.
+    pub fn add_insn_invalid_hook<F>(
+        &mut self,
+        callback: F,
+    ) -> Result<HookHandle<'a, D>, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>) -> bool + 'a,
+    {
+        let mut hook_ptr = ptr::null_mut();
+        let mut user_data = Box::new(ffi::UcHook {
+            callback,
+            uc: Rc::downgrade(&self.inner),
+        });
+
+        let err = unsafe {
+            ffi::uc_hook_add(
+                self.inner().uc,
+                &mut hook_ptr,
+                HookType::INSN_INVALID,
+                ffi::insn_invalid_hook_proxy::<D, F> as _,
+                user_data.as_mut() as *mut _ as _,
+                1,
+                0,
+            )
+        };
+        if err == uc_error::OK {
+            Ok(self.keep_hook(hook_ptr, HookType::INSN_INVALID, 1, 0, user_data))
         } else {
             Err(err)
         }
     }
 
     /// Add an interrupt hook.
-    pub fn add_intr_hook<F: 'a>(&mut self, callback: F) -> Result<ffi::uc_hook, uc_error>
+    pub fn add_intr_hook<F: 'a>(&mut self, callback: F) -> Result<HookHandle<'a, D>, uc_error>
     where
-        F: FnMut(&mut Unicorn<D>, u32),
+        F: FnMut(&mut HookCtx<D>, u32),
     {
         let mut hook_ptr = ptr::null_mut();
         let mut user_data = Box::new(ffi::UcHook {
             callback,
-            uc: Unicorn {
-                inner: self.inner.clone(),
-            },
+            uc: Rc::downgrade(&self.inner),
         });
 
         let err = unsafe {
@@ -702,25 +1482,21 @@ impl<'a, D> Unicorn<'a, D> {
             )
         };
         if err == uc_error::OK {
-            self.inner_mut().hooks.push((hook_ptr, user_data));
-
-            Ok(hook_ptr)
+            Ok(self.keep_hook(hook_ptr, HookType::INTR, 0, 0, user_data))
         } else {
             Err(err)
         }
     }
 
     /// Add hook for x86 IN instruction.
-    pub fn add_insn_in_hook<F: 'a>(&mut self, callback: F) -> Result<ffi::uc_hook, uc_error>
+    pub fn add_insn_in_hook<F: 'a>(&mut self, callback: F) -> Result<HookHandle<'a, D>, uc_error>
     where
-        F: FnMut(&mut Unicorn<D>, u32, usize),
+        F: FnMut(&mut HookCtx<D>, u32, usize),
     {
         let mut hook_ptr = ptr::null_mut();
         let mut user_data = Box::new(ffi::UcHook {
             callback,
-            uc: Unicorn {
-                inner: self.inner.clone(),
-            },
+            uc: Rc::downgrade(&self.inner),
         });
 
         let err = unsafe {
@@ -736,25 +1512,21 @@ impl<'a, D> Unicorn<'a, D> {
             )
         };
         if err == uc_error::OK {
-            self.inner_mut().hooks.push((hook_ptr, user_data));
-
-            Ok(hook_ptr)
+            Ok(self.keep_hook(hook_ptr, HookType::INSN, 0, 0, user_data))
         } else {
             Err(err)
         }
     }
 
     /// Add hook for x86 OUT instruction.
-    pub fn add_insn_out_hook<F: 'a>(&mut self, callback: F) -> Result<ffi::uc_hook, uc_error>
+    pub fn add_insn_out_hook<F: 'a>(&mut self, callback: F) -> Result<HookHandle<'a, D>, uc_error>
     where
-        F: FnMut(&mut Unicorn<D>, u32, usize, u32),
+        F: FnMut(&mut HookCtx<D>, u32, usize, u32),
     {
         let mut hook_ptr = ptr::null_mut();
         let mut user_data = Box::new(ffi::UcHook {
             callback,
-            uc: Unicorn {
-                inner: self.inner.clone(),
-            },
+            uc: Rc::downgrade(&self.inner),
         });
 
         let err = unsafe {
@@ -770,9 +1542,7 @@ impl<'a, D> Unicorn<'a, D> {
             )
         };
         if err == uc_error::OK {
-            self.inner_mut().hooks.push((hook_ptr, user_data));
-
-            Ok(hook_ptr)
+            Ok(self.keep_hook(hook_ptr, HookType::INSN, 0, 0, user_data))
         } else {
             Err(err)
         }
@@ -785,16 +1555,14 @@ impl<'a, D> Unicorn<'a, D> {
         begin: u64,
         end: u64,
         callback: F,
-    ) -> Result<ffi::uc_hook, uc_error>
+    ) -> Result<HookHandle<'a, D>, uc_error>
     where
-        F: FnMut(&mut Unicorn<D>) + 'a,
+        F: FnMut(&mut HookCtx<D>) + 'a,
     {
         let mut hook_ptr = ptr::null_mut();
         let mut user_data = Box::new(ffi::UcHook {
             callback,
-            uc: Unicorn {
-                inner: self.inner.clone(),
-            },
+            uc: Rc::downgrade(&self.inner),
         });
 
         let err = unsafe {
@@ -810,26 +1578,27 @@ impl<'a, D> Unicorn<'a, D> {
             )
         };
         if err == uc_error::OK {
-            self.inner_mut().hooks.push((hook_ptr, user_data));
-
-            Ok(hook_ptr)
+            Ok(self.keep_hook(hook_ptr, HookType::INSN, begin, end, user_data))
         } else {
             Err(err)
         }
     }
 
-    /// Remove a hook.
+    /// Remove a hook kept with `HookHandle::keep`. A `HookHandle` of a hook removed
+    /// this way does nothing once dropped.
     ///
-    /// `hook` is the value returned by `add_*_hook` functions.
+    /// Inside a hook it is only removed when the callback returned.
     pub fn remove_hook(&mut self, hook: ffi::uc_hook) -> Result<(), uc_error> {
-        let err: uc_error;
-
-        // drop the hook
+        if self.inner().hook_depth > 0 {
+            self.inner_mut().deferred.push(Deferred::RemoveHook(hook));
+            return Ok(());
+        }
+        // Unicorn may still call the hook until it is deleted, so the callback
+        // goes afterwards.
+        let err = unsafe { ffi::uc_hook_del(self.inner().uc, hook) };
         self.inner_mut()
             .hooks
-            .retain(|(hook_ptr, _hook_impl)| hook_ptr != &hook);
-
-        err = unsafe { ffi::uc_hook_del(self.inner().uc, hook) };
+            .retain(|entry| entry.info.hook != hook);
 
         if err == uc_error::OK {
             Ok(())
@@ -838,6 +1607,74 @@ impl<'a, D> Unicorn<'a, D> {
         }
     }
 
+    /// Carry out what hook callbacks deferred, their errors have nowhere to go.
+    ///
+    /// Hooks are removed once no callback runs. MMIO regions are only unmapped once
+    /// `emu_start` returned, `with_unmaps`, because QEMU still uses them after their
+    /// callback returned.
+    fn run_deferred(&mut self, with_unmaps: bool) {
+        let (run, keep): (Vec<Deferred>, Vec<Deferred>) = mem::take(&mut self.inner_mut().deferred)
+            .into_iter()
+            .partition(|deferred| with_unmaps || matches!(deferred, Deferred::RemoveHook(_)));
+        self.inner_mut().deferred = keep;
+
+        for deferred in run {
+            let _ = match deferred {
+                Deferred::RemoveHook(hook) => self.remove_hook(hook),
+                Deferred::Unmap(address, size) => self.mem_unmap(address, size),
+            };
+        }
+    }
+
+    /// Remove the hook of a `HookHandle`, unless it is gone already and Unicorn
+    /// reused its `uc_hook` for another one.
+    fn remove_hook_serial(&mut self, serial: u64) -> Result<(), uc_error> {
+        let hook = self
+            .inner()
+            .hooks
+            .iter()
+            .find(|entry| entry.serial == serial)
+            .map(|entry| entry.info.hook);
+        match hook {
+            Some(hook) => self.remove_hook(hook),
+            None => Ok(()),
+        }
+    }
+
+    /// The hooks added with the `add_*_hook` methods that were not removed yet.
+    #[must_use]
+    pub fn hooks(&self) -> Vec<HookInfo> {
+        self.inner().hooks.iter().map(|entry| entry.info).collect()
+    }
+
+    fn keep_hook(
+        &mut self,
+        hook: ffi::uc_hook,
+        hook_type: HookType,
+        begin: u64,
+        end: u64,
+        callback: Box<dyn ffi::IsUcHook<'a> + 'a>,
+    ) -> HookHandle<'a, D> {
+        let inner = self.inner_mut();
+        let serial = inner.next_hook_serial;
+        inner.next_hook_serial += 1;
+        inner.hooks.push(HookEntry {
+            info: HookInfo {
+                hook,
+                hook_type,
+                begin,
+                end,
+            },
+            serial,
+            callback,
+        });
+        HookHandle {
+            inner: Rc::downgrade(&self.inner),
+            hook,
+            serial,
+        }
+    }
+
     /// Allocate and return an empty Unicorn context.
     ///
     /// To be populated via `context_save`.
@@ -889,7 +1726,8 @@ impl<'a, D> Unicorn<'a, D> {
     ///
     /// Perform a quick rollback of the CPU context, including registers and some
     /// internal metadata. Contexts may not be shared across engine instances with
-    /// differing arches or modes. Memory has to be restored manually, if needed.
+    /// differing arches or modes. Memory has to be restored manually, if needed, or
+    /// use `snapshot` and `restore` instead.
     pub fn context_restore(&self, context: &Context) -> Result<(), uc_error> {
         let err = unsafe { ffi::uc_context_restore(self.inner().uc, context.context) };
         if err == uc_error::OK {
@@ -899,6 +1737,262 @@ impl<'a, D> Unicorn<'a, D> {
         }
     }
 
+    /// Take a snapshot of the CPU context and of all mapped memory, including the
+    /// memory layout and permissions.
+    ///
+    /// MMIO regions are left out, reading them would call back into the harness.
+    /// Pages that did not change are shared with the last snapshot or restore.
+    /// With `track_dirty_pages` only the pages written since then are read again,
+    /// which makes taking many snapshots cheap, but memory changed through a
+    /// pointer passed to `mem_map_ptr` is not seen.
+    pub fn snapshot(&mut self) -> Result<Snapshot, uc_error> {
+        let context = self.context_init()?;
+        let page_size = self.query(Query::PAGE_SIZE)?;
+        let mut buf = vec![0; page_size];
+        let mut snapshot_pages = HashMap::new();
+        let mut regions = vec![];
+
+        for region in self.mem_regions()? {
+            if self.is_mmio(region.begin) {
+                continue;
+            }
+
+            let mut pages = vec![];
+            for page in (region.begin..=region.end).step_by(page_size) {
+                let known = self.inner().snapshot_pages.get(&page).cloned();
+                let data = match known {
+                    Some(data) if self.unchanged_since_snapshot(page) => data,
+                    known => {
+                        self.mem_read(page, &mut buf)?;
+                        match known {
+                            Some(data) if *data == *buf => data,
+                            _ => Arc::from(buf.as_slice()),
+                        }
+                    }
+                };
+                snapshot_pages.insert(page, data.clone());
+                pages.push(data);
+            }
+
+            regions.push(SnapshotRegion {
+                begin: region.begin,
+                end: region.end,
+                perms: region.perms,
+                pages,
+            });
+        }
+
+        self.reset_snapshot_pages(snapshot_pages);
+        Ok(Snapshot {
+            arch: self.get_arch(),
+            mode: self.get_mode()?,
+            cpu_model: self.get_cpu_model()?,
+            page_size,
+            context,
+            context_size: unsafe { ffi::uc_context_size(self.inner().uc) },
+            regions,
+        })
+    }
+
+    /// Load a snapshot saved with `Snapshot::save`, to be restored with `restore`.
+    ///
+    /// Fails with `SnapshotError::Mismatch` if it was taken with another arch, mode,
+    /// CPU model or page size.
+    pub fn load_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<Snapshot, SnapshotError> {
+        let file = SnapshotFile::read(&mut BufReader::new(File::open(path)?))?;
+        self.snapshot_from_file(file)
+    }
+
+    fn snapshot_from_file(&self, file: SnapshotFile) -> Result<Snapshot, SnapshotError> {
+        let page_size = self.query(Query::PAGE_SIZE)?;
+        if file.page_size as usize != page_size {
+            return Err(SnapshotError::Mismatch);
+        }
+
+        let mut snapshot = Snapshot {
+            arch: file.context.arch,
+            mode: file.context.mode,
+            cpu_model: file.context.cpu_model,
+            page_size,
+            context: self.context_from_file(&file.context)?,
+            context_size: unsafe { ffi::uc_context_size(self.inner().uc) },
+            regions: vec![],
+        };
+
+        for region in file.regions {
+            if region.begin % page_size as u64 != 0 || region.data.len() % page_size != 0 {
+                return Err(SnapshotError::Format);
+            }
+            snapshot.regions.push(SnapshotRegion {
+                begin: region.begin,
+                end: region.end,
+                perms: region.perms,
+                pages: region.data.chunks(page_size).map(Arc::from).collect(),
+            });
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Save the registers of a context to `path`, to be loaded with `load_context`.
+    ///
+    /// Only the registers of the arch's `RegisterFile` are saved, e.g. `arm::Regs`.
+    /// Fails with `uc_error::ARCH` if the mode has none.
+    pub fn save_context<P: AsRef<Path>>(
+        &self,
+        context: &Context,
+        path: P,
+    ) -> Result<(), SnapshotError> {
+        let file = context.to_file(self.get_arch(), self.get_mode()?, self.get_cpu_model()?)?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        file.write(&mut writer)?;
+        writer.into_inner().map_err(|err| err.into_error())?;
+        Ok(())
+    }
+
+    /// Load a context saved with `save_context`, to be restored with
+    /// `context_restore`. The registers that were not saved keep their current
+    /// values.
+    ///
+    /// Fails with `SnapshotError::Mismatch` if it was saved with another arch, mode
+    /// or CPU model.
+    pub fn load_context<P: AsRef<Path>>(&self, path: P) -> Result<Context, SnapshotError> {
+        let file = ContextFile::read(&mut BufReader::new(File::open(path)?))?;
+        self.context_from_file(&file)
+    }
+
+    fn context_from_file(&self, file: &ContextFile) -> Result<Context, SnapshotError> {
+        if file.arch != self.get_arch()
+            || file.mode != self.get_mode()?
+            || file.cpu_model != self.get_cpu_model()?
+        {
+            return Err(SnapshotError::Mismatch);
+        }
+        let mut context = self.context_init()?;
+        context.load_file(file)?;
+        Ok(context)
+    }
+
+    fn get_mode(&self) -> Result<Mode, uc_error> {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        Ok(Mode::from_bits_truncate(self.query(Query::MODE)? as i32))
+    }
+
+    fn get_cpu_model(&self) -> Result<i32, uc_error> {
+        Ctl::new(self.inner().uc).cpu_model()
+    }
+
+    /// Restore a snapshot taken with `snapshot`.
+    ///
+    /// Regions mapped since are unmapped, unmapped ones are mapped again and
+    /// permissions are reset. Only pages whose contents changed are written, so code
+    /// that did not change stays translated. MMIO regions are not touched. With
+    /// `track_dirty_pages` only the pages written since the last snapshot or
+    /// restore are compared.
+    ///
+    /// Fails with `uc_error::ARG` if the snapshot was taken with another arch, mode,
+    /// CPU model or page size.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), uc_error> {
+        let page_size = self.query(Query::PAGE_SIZE)?;
+        // Unicorn copies the saved CPU state as is, it has to fit this engine.
+        if snapshot.arch != self.get_arch()
+            || snapshot.mode != self.get_mode()?
+            || snapshot.cpu_model != self.get_cpu_model()?
+            || snapshot.page_size != page_size
+            || snapshot.context_size != unsafe { ffi::uc_context_size(self.inner().uc) }
+        {
+            return Err(uc_error::ARG);
+        }
+        let mut buf = vec![0; page_size];
+        let mut snapshot_pages = HashMap::new();
+
+        let current: Vec<MemRegion> = self
+            .mem_regions()?
+            .into_iter()
+            .filter(|region| !self.is_mmio(region.begin))
+            .collect();
+        let same_range = |a: &MemRegion, b: &SnapshotRegion| a.begin == b.begin && a.end == b.end;
+
+        for region in &current {
+            if !snapshot.regions.iter().any(|saved| same_range(region, saved)) {
+                self.mem_unmap(region.begin, (region.end - region.begin + 1) as usize)?;
+            }
+        }
+
+        for saved in &snapshot.regions {
+            let mapped = match current.iter().find(|region| same_range(region, saved)) {
+                Some(region) => {
+                    if region.perms != saved.perms {
+                        self.mem_protect(saved.begin, saved.size(), saved.perms)?;
+                    }
+                    true
+                }
+                None => {
+                    self.mem_map(saved.begin, saved.size(), saved.perms)?;
+                    false
+                }
+            };
+
+            for (page, data) in (saved.begin..=saved.end).step_by(page_size).zip(&saved.pages) {
+                let unchanged = mapped
+                    && self.unchanged_since_snapshot(page)
+                    && self
+                        .inner()
+                        .snapshot_pages
+                        .get(&page)
+                        .is_some_and(|known| Arc::ptr_eq(known, data));
+                if unchanged {
+                    snapshot_pages.insert(page, data.clone());
+                    continue;
+                }
+
+                if mapped {
+                    self.mem_read(page, &mut buf)?;
+                }
+                if !mapped || buf[..] != data[..] {
+                    self.mem_write(page, data)?;
+                }
+                snapshot_pages.insert(page, data.clone());
+            }
+        }
+
+        self.context_restore(&snapshot.context)?;
+        self.reset_snapshot_pages(snapshot_pages);
+        Ok(())
+    }
+
+    /// Whether `page` still holds what it held at the last snapshot or restore,
+    /// known only while tracking dirty pages.
+    fn unchanged_since_snapshot(&self, page: u64) -> bool {
+        self.inner()
+            .dirty_pages
+            .as_ref()
+            .and_then(|dirty_pages| dirty_pages.since_snapshot.as_ref())
+            .is_some_and(|since_snapshot| !since_snapshot.contains(&page))
+    }
+
+    fn reset_snapshot_pages(&mut self, snapshot_pages: HashMap<u64, Arc<[u8]>>) {
+        let inner = self.inner_mut();
+        inner.snapshot_pages = snapshot_pages;
+        if let Some(dirty_pages) = inner.dirty_pages.as_mut() {
+            dirty_pages.since_snapshot = Some(BTreeSet::new());
+        }
+    }
+
+    fn is_mmio(&self, address: u64) -> bool {
+        self.touches_mmio(address, 1)
+    }
+
+    /// Whether any of the `size` bytes at `address` is in an MMIO region.
+    fn touches_mmio(&self, address: u64, size: usize) -> bool {
+        let last = address.saturating_add(size.max(1) as u64 - 1);
+        self.inner().mmio_callbacks.iter().any(|scope| {
+            scope.regions.iter().any(|&(begin, size)| {
+                begin <= last && (address <= begin || address - begin < size as u64)
+            })
+        })
+    }
+
     /// Emulate machine code for a specified duration.
     ///
     /// `begin` is the address where to start the emulation. The emulation stops if `until`
@@ -912,13 +2006,12 @@ impl<'a, D> Unicorn<'a, D> {
         timeout: u64,
         count: usize,
     ) -> Result<(), uc_error> {
-        unsafe {
-            let err = ffi::uc_emu_start(self.inner().uc, begin, until, timeout, count as _);
-            if err == uc_error::OK {
-                Ok(())
-            } else {
-                Err(err)
-            }
+        let err = unsafe { ffi::uc_emu_start(self.inner().uc, begin, until, timeout, count as _) };
+        self.run_deferred(true);
+        if err == uc_error::OK {
+            Ok(())
+        } else {
+            Err(err)
         }
     }
 
@@ -948,6 +2041,12 @@ impl<'a, D> Unicorn<'a, D> {
         }
     }
 
+    /// Configure the engine through `uc_ctl`: exits, CPU model, translation cache,
+    /// page size.
+    pub fn ctl(&mut self) -> Ctl<'_> {
+        Ctl::new(self.inner().uc)
+    }
+
     /// Gets the current program counter for this `unicorn` instance.
     #[inline]
     pub fn pc_read(&self) -> Result<u64, uc_error> {
@@ -961,6 +2060,8 @@ impl<'a, D> Unicorn<'a, D> {
             Arch::M68K => m68k::Register::PC as i32,
             Arch::PPC => ppc::Register::PC as i32,
             Arch::RISCV => riscv::Register::PC as i32,
+            Arch::S390X => s390x::Register::PC as i32,
+            Arch::TRICORE => tricore::Register::PC as i32,
             Arch::MAX => panic!("Illegal Arch specified"),
         };
         self.reg_read(reg)
@@ -979,6 +2080,8 @@ impl<'a, D> Unicorn<'a, D> {
             Arch::M68K => m68k::Register::PC as i32,
             Arch::PPC => ppc::Register::PC as i32,
             Arch::RISCV => riscv::Register::PC as i32,
+            Arch::S390X => s390x::Register::PC as i32,
+            Arch::TRICORE => tricore::Register::PC as i32,
             Arch::MAX => panic!("Illegal Arch specified"),
         };
         self.reg_write(reg, value)
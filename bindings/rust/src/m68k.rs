@@ -28,3 +28,27 @@ impl From<Register> for i32 {
         r as i32
     }
 }
+
+// M68K CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel {
+    M5206 = 0,
+    M68000,
+    M68020,
+    M68030,
+    M68040,
+    M68060,
+    M5208,
+    CFV4E,
+    ANY,
+}
+
+impl From<CpuModel> for i32 {
+    fn from(model: CpuModel) -> Self {
+        model as i32
+    }
+}
+
+pub use crate::regs::m68k::Regs;
@@ -251,3 +251,60 @@ impl From<Register> for i32 {
         r as i32
     }
 }
+
+// MIPS32 CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel32 {
+    MIPS_4KC = 0,
+    MIPS_4KM,
+    MIPS_4KECR1,
+    MIPS_4KEMR1,
+    MIPS_4KEC,
+    MIPS_4KEM,
+    MIPS_24KC,
+    MIPS_24KEC,
+    MIPS_24KF,
+    MIPS_34K,
+    MIPS_74K,
+    M14K,
+    M14KC,
+    P5600,
+    MIPS32R6_GENERIC,
+    I7200,
+}
+
+impl From<CpuModel32> for i32 {
+    fn from(model: CpuModel32) -> Self {
+        model as i32
+    }
+}
+
+// MIPS64 CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel64 {
+    R4000 = 0,
+    VR5432,
+    MIPS_5KC,
+    MIPS_5KF,
+    MIPS_20KC,
+    MIPS64R2_GENERIC,
+    MIPS_5KEC,
+    MIPS_5KEF,
+    I6400,
+    I6500,
+    LOONGSON_2E,
+    LOONGSON_2F,
+    MIPS64DSPR2,
+}
+
+impl From<CpuModel64> for i32 {
+    fn from(model: CpuModel64) -> Self {
+        model as i32
+    }
+}
+
+pub use crate::regs::mips::Regs;
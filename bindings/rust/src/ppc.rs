@@ -46,3 +46,340 @@ impl From<Register> for i32 {
         r as i32
     }
 }
+
+// PPC CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel32 {
+    PPC_401 = 0,
+    PPC_401A1,
+    PPC_401B2,
+    PPC_401C2,
+    PPC_401D2,
+    PPC_401E2,
+    PPC_401F2,
+    PPC_401G2,
+    IOP480,
+    COBRA,
+    PPC_403GA,
+    PPC_403GB,
+    PPC_403GC,
+    PPC_403GCX,
+    PPC_405D2,
+    PPC_405D4,
+    PPC_405CRA,
+    PPC_405CRB,
+    PPC_405CRC,
+    PPC_405EP,
+    PPC_405EZ,
+    PPC_405GPA,
+    PPC_405GPB,
+    PPC_405GPC,
+    PPC_405GPD,
+    PPC_405GPR,
+    PPC_405LP,
+    NPE405H,
+    NPE405H2,
+    NPE405L,
+    NPE4GS3,
+    STB03,
+    STB04,
+    STB25,
+    X2VP4,
+    X2VP20,
+    PPC_440_XILINX,
+    PPC_440_XILINX_W_DFPU,
+    PPC_440EPA,
+    PPC_440EPB,
+    PPC_440EPX,
+    PPC_460EXB,
+    G2,
+    G2H4,
+    G2GP,
+    G2LS,
+    G2HIP3,
+    G2HIP4,
+    MPC603,
+    G2LE,
+    G2LEGP,
+    G2LELS,
+    G2LEGP1,
+    G2LEGP3,
+    MPC5200_V10,
+    MPC5200_V11,
+    MPC5200_V12,
+    MPC5200B_V20,
+    MPC5200B_V21,
+    E200Z5,
+    E200Z6,
+    E300C1,
+    E300C2,
+    E300C3,
+    E300C4,
+    MPC8343,
+    MPC8343A,
+    MPC8343E,
+    MPC8343EA,
+    MPC8347T,
+    MPC8347P,
+    MPC8347AT,
+    MPC8347AP,
+    MPC8347ET,
+    MPC8347EP,
+    MPC8347EAT,
+    MPC8347EAP,
+    MPC8349,
+    MPC8349A,
+    MPC8349E,
+    MPC8349EA,
+    MPC8377,
+    MPC8377E,
+    MPC8378,
+    MPC8378E,
+    MPC8379,
+    MPC8379E,
+    E500_V10,
+    E500_V20,
+    E500V2_V10,
+    E500V2_V20,
+    E500V2_V21,
+    E500V2_V22,
+    E500V2_V30,
+    E500MC,
+    MPC8533_V10,
+    MPC8533_V11,
+    MPC8533E_V10,
+    MPC8533E_V11,
+    MPC8540_V10,
+    MPC8540_V20,
+    MPC8540_V21,
+    MPC8541_V10,
+    MPC8541_V11,
+    MPC8541E_V10,
+    MPC8541E_V11,
+    MPC8543_V10,
+    MPC8543_V11,
+    MPC8543_V20,
+    MPC8543_V21,
+    MPC8543E_V10,
+    MPC8543E_V11,
+    MPC8543E_V20,
+    MPC8543E_V21,
+    MPC8544_V10,
+    MPC8544_V11,
+    MPC8544E_V10,
+    MPC8544E_V11,
+    MPC8545_V20,
+    MPC8545_V21,
+    MPC8545E_V20,
+    MPC8545E_V21,
+    MPC8547E_V20,
+    MPC8547E_V21,
+    MPC8548_V10,
+    MPC8548_V11,
+    MPC8548_V20,
+    MPC8548_V21,
+    MPC8548E_V10,
+    MPC8548E_V11,
+    MPC8548E_V20,
+    MPC8548E_V21,
+    MPC8555_V10,
+    MPC8555_V11,
+    MPC8555E_V10,
+    MPC8555E_V11,
+    MPC8560_V10,
+    MPC8560_V20,
+    MPC8560_V21,
+    MPC8567,
+    MPC8567E,
+    MPC8568,
+    MPC8568E,
+    MPC8572,
+    MPC8572E,
+    E600,
+    MPC8610,
+    MPC8641,
+    MPC8641D,
+    PPC_601_V0,
+    PPC_601_V1,
+    PPC_601_V2,
+    PPC_602,
+    PPC_603,
+    PPC_603E_V1_1,
+    PPC_603E_V1_2,
+    PPC_603E_V1_3,
+    PPC_603E_V1_4,
+    PPC_603E_V2_2,
+    PPC_603E_V3,
+    PPC_603E_V4,
+    PPC_603E_V4_1,
+    PPC_603E7,
+    PPC_603E7T,
+    PPC_603E7V,
+    PPC_603E7V1,
+    PPC_603E7V2,
+    PPC_603P,
+    PPC_604,
+    PPC_604E_V1_0,
+    PPC_604E_V2_2,
+    PPC_604E_V2_4,
+    PPC_604R,
+    PPC_740_V1_0,
+    PPC_750_V1_0,
+    PPC_740_V2_0,
+    PPC_750_V2_0,
+    PPC_740_V2_1,
+    PPC_750_V2_1,
+    PPC_740_V2_2,
+    PPC_750_V2_2,
+    PPC_740_V3_0,
+    PPC_750_V3_0,
+    PPC_740_V3_1,
+    PPC_750_V3_1,
+    PPC_740E,
+    PPC_750E,
+    PPC_740P,
+    PPC_750P,
+    PPC_750CL_V1_0,
+    PPC_750CL_V2_0,
+    PPC_750CX_V1_0,
+    PPC_750CX_V2_0,
+    PPC_750CX_V2_1,
+    PPC_750CX_V2_2,
+    PPC_750CXE_V2_1,
+    PPC_750CXE_V2_2,
+    PPC_750CXE_V2_3,
+    PPC_750CXE_V2_4,
+    PPC_750CXE_V2_4B,
+    PPC_750CXE_V3_0,
+    PPC_750CXE_V3_1,
+    PPC_750CXE_V3_1B,
+    PPC_750CXR,
+    PPC_750FL,
+    PPC_750FX_V1_0,
+    PPC_750FX_V2_0,
+    PPC_750FX_V2_1,
+    PPC_750FX_V2_2,
+    PPC_750FX_V2_3,
+    PPC_750GL,
+    PPC_750GX_V1_0,
+    PPC_750GX_V1_1,
+    PPC_750GX_V1_2,
+    PPC_750L_V2_0,
+    PPC_750L_V2_1,
+    PPC_750L_V2_2,
+    PPC_750L_V3_0,
+    PPC_750L_V3_2,
+    PPC_745_V1_0,
+    PPC_755_V1_0,
+    PPC_745_V1_1,
+    PPC_755_V1_1,
+    PPC_745_V2_0,
+    PPC_755_V2_0,
+    PPC_745_V2_1,
+    PPC_755_V2_1,
+    PPC_745_V2_2,
+    PPC_755_V2_2,
+    PPC_745_V2_3,
+    PPC_755_V2_3,
+    PPC_745_V2_4,
+    PPC_755_V2_4,
+    PPC_745_V2_5,
+    PPC_755_V2_5,
+    PPC_745_V2_6,
+    PPC_755_V2_6,
+    PPC_745_V2_7,
+    PPC_755_V2_7,
+    PPC_745_V2_8,
+    PPC_755_V2_8,
+    PPC_7400_V1_0,
+    PPC_7400_V1_1,
+    PPC_7400_V2_0,
+    PPC_7400_V2_1,
+    PPC_7400_V2_2,
+    PPC_7400_V2_6,
+    PPC_7400_V2_7,
+    PPC_7400_V2_8,
+    PPC_7400_V2_9,
+    PPC_7410_V1_0,
+    PPC_7410_V1_1,
+    PPC_7410_V1_2,
+    PPC_7410_V1_3,
+    PPC_7410_V1_4,
+    PPC_7448_V1_0,
+    PPC_7448_V1_1,
+    PPC_7448_V2_0,
+    PPC_7448_V2_1,
+    PPC_7450_V1_0,
+    PPC_7450_V1_1,
+    PPC_7450_V1_2,
+    PPC_7450_V2_0,
+    PPC_7450_V2_1,
+    PPC_7441_V2_1,
+    PPC_7441_V2_3,
+    PPC_7451_V2_3,
+    PPC_7441_V2_10,
+    PPC_7451_V2_10,
+    PPC_7445_V1_0,
+    PPC_7455_V1_0,
+    PPC_7445_V2_1,
+    PPC_7455_V2_1,
+    PPC_7445_V3_2,
+    PPC_7455_V3_2,
+    PPC_7445_V3_3,
+    PPC_7455_V3_3,
+    PPC_7445_V3_4,
+    PPC_7455_V3_4,
+    PPC_7447_V1_0,
+    PPC_7457_V1_0,
+    PPC_7447_V1_1,
+    PPC_7457_V1_1,
+    PPC_7457_V1_2,
+    PPC_7447A_V1_0,
+    PPC_7457A_V1_0,
+    PPC_7447A_V1_1,
+    PPC_7457A_V1_1,
+    PPC_7447A_V1_2,
+    PPC_7457A_V1_2,
+}
+
+impl From<CpuModel32> for i32 {
+    fn from(model: CpuModel32) -> Self {
+        model as i32
+    }
+}
+
+// PPC64 CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel64 {
+    E5500 = 0,
+    E6500,
+    PPC_970_V2_2,
+    PPC_970FX_V1_0,
+    PPC_970FX_V2_0,
+    PPC_970FX_V2_1,
+    PPC_970FX_V3_0,
+    PPC_970FX_V3_1,
+    PPC_970MP_V1_0,
+    PPC_970MP_V1_1,
+    POWER5_V2_1,
+    POWER7_V2_3,
+    POWER7_V2_1,
+    POWER8E_V2_1,
+    POWER8_V2_0,
+    POWER8NVL_V1_0,
+    POWER9_V1_0,
+    POWER9_V2_0,
+    POWER10_V1_0,
+}
+
+impl From<CpuModel64> for i32 {
+    fn from(model: CpuModel64) -> Self {
+        model as i32
+    }
+}
+
+pub use crate::regs::ppc::Regs;
@@ -0,0 +1,689 @@
+//! Register files: the registers of an arch, read and written in one call with
+//! `Unicorn::regs_read` and `Unicorn::regs_write`.
+//!
+//! Registers whose width depends on the mode, e.g. the MIPS GPRs, are stored as
+//! `u64` and only use the low half in 32-bit modes.
+
+use std::mem;
+use std::slice;
+
+use crate::consts::{uc_error, Arch, Mode};
+
+/// Called with the id and the value of a register.
+pub type RegisterVisitor<'a> = dyn FnMut(i32, &mut [u8]) -> Result<(), uc_error> + 'a;
+
+/// The registers of an arch, see `Unicorn::regs_read`.
+pub trait RegisterFile: Default + Clone {
+    /// Whether these registers exist in an engine of `arch` in `mode`.
+    fn supports(arch: Arch, mode: Mode) -> bool;
+
+    /// Calls `f` with the id and the value of every register, in the order they
+    /// have to be written. The width of a register is the length of its value.
+    fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error>;
+}
+
+/// A register value in host byte order, the way Unicorn expects it.
+pub trait RegisterValue: Copy + Default {
+    fn as_bytes_mut(&mut self) -> &mut [u8];
+}
+
+macro_rules! impl_register_value {
+    ($($ty:ty),*) => {
+        $(
+            impl RegisterValue for $ty {
+                fn as_bytes_mut(&mut self) -> &mut [u8] {
+                    unsafe { slice::from_raw_parts_mut(self as *mut $ty as *mut u8, mem::size_of::<$ty>()) }
+                }
+            }
+        )*
+    };
+}
+
+impl_register_value!(u16, u32, u64, u128);
+
+/// x87 registers
+impl RegisterValue for [u8; 10] {
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+fn each<R, T>(f: &mut RegisterVisitor, regs: &[R], values: &mut [T]) -> Result<(), uc_error>
+where
+    R: Into<i32> + Copy,
+    T: RegisterValue,
+{
+    debug_assert_eq!(regs.len(), values.len());
+    for (reg, value) in regs.iter().zip(values) {
+        f((*reg).into(), value.as_bytes_mut())?;
+    }
+    Ok(())
+}
+
+pub mod arm {
+    use super::{each, RegisterFile, RegisterValue, RegisterVisitor};
+    use crate::arm::Register;
+    use crate::consts::{uc_error, Arch, Mode};
+
+    /// ARM core and VFP registers. The banked registers are the ones of the
+    /// current mode.
+    #[derive(PartialEq, Eq, Debug, Clone, Default)]
+    pub struct Regs {
+        pub r: [u32; 13],
+        pub sp: u32,
+        pub lr: u32,
+        pub pc: u32,
+        pub cpsr: u32,
+        pub fpscr: u32,
+        pub d: [u64; 32],
+    }
+
+    #[rustfmt::skip]
+    const R: [Register; 13] = [
+        Register::R0, Register::R1, Register::R2, Register::R3, Register::R4, Register::R5,
+        Register::R6, Register::R7, Register::R8, Register::R9, Register::R10, Register::R11,
+        Register::R12,
+    ];
+
+    #[rustfmt::skip]
+    const D: [Register; 32] = [
+        Register::D0, Register::D1, Register::D2, Register::D3, Register::D4, Register::D5,
+        Register::D6, Register::D7, Register::D8, Register::D9, Register::D10, Register::D11,
+        Register::D12, Register::D13, Register::D14, Register::D15, Register::D16, Register::D17,
+        Register::D18, Register::D19, Register::D20, Register::D21, Register::D22, Register::D23,
+        Register::D24, Register::D25, Register::D26, Register::D27, Register::D28, Register::D29,
+        Register::D30, Register::D31,
+    ];
+
+    impl RegisterFile for Regs {
+        fn supports(arch: Arch, _mode: Mode) -> bool {
+            arch == Arch::ARM
+        }
+
+        fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error> {
+            // Writing PC clears the Thumb bit and writing CPSR switches the banked
+            // registers, so PC goes first and the banked registers last.
+            f(Register::PC.into(), self.pc.as_bytes_mut())?;
+            f(Register::CPSR.into(), self.cpsr.as_bytes_mut())?;
+            each(f, &R, &mut self.r)?;
+            f(Register::SP.into(), self.sp.as_bytes_mut())?;
+            f(Register::LR.into(), self.lr.as_bytes_mut())?;
+            f(Register::FPSCR.into(), self.fpscr.as_bytes_mut())?;
+            each(f, &D, &mut self.d)
+        }
+    }
+}
+
+pub mod arm64 {
+    use super::{each, RegisterFile, RegisterValue, RegisterVisitor};
+    use crate::arm64::Register;
+    use crate::consts::{uc_error, Arch, Mode};
+
+    /// ARM64 general purpose and SIMD registers.
+    #[derive(PartialEq, Eq, Debug, Clone, Default)]
+    pub struct Regs {
+        pub x: [u64; 29],
+        pub fp: u64,
+        pub lr: u64,
+        pub sp: u64,
+        pub pc: u64,
+        pub nzcv: u32,
+        pub q: [u128; 32],
+    }
+
+    #[rustfmt::skip]
+    const X: [Register; 29] = [
+        Register::X0, Register::X1, Register::X2, Register::X3, Register::X4, Register::X5,
+        Register::X6, Register::X7, Register::X8, Register::X9, Register::X10, Register::X11,
+        Register::X12, Register::X13, Register::X14, Register::X15, Register::X16, Register::X17,
+        Register::X18, Register::X19, Register::X20, Register::X21, Register::X22, Register::X23,
+        Register::X24, Register::X25, Register::X26, Register::X27, Register::X28,
+    ];
+
+    #[rustfmt::skip]
+    const Q: [Register; 32] = [
+        Register::Q0, Register::Q1, Register::Q2, Register::Q3, Register::Q4, Register::Q5,
+        Register::Q6, Register::Q7, Register::Q8, Register::Q9, Register::Q10, Register::Q11,
+        Register::Q12, Register::Q13, Register::Q14, Register::Q15, Register::Q16, Register::Q17,
+        Register::Q18, Register::Q19, Register::Q20, Register::Q21, Register::Q22, Register::Q23,
+        Register::Q24, Register::Q25, Register::Q26, Register::Q27, Register::Q28, Register::Q29,
+        Register::Q30, Register::Q31,
+    ];
+
+    impl RegisterFile for Regs {
+        fn supports(arch: Arch, _mode: Mode) -> bool {
+            arch == Arch::ARM64
+        }
+
+        fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error> {
+            each(f, &X, &mut self.x)?;
+            f(Register::X29.into(), self.fp.as_bytes_mut())?;
+            f(Register::X30.into(), self.lr.as_bytes_mut())?;
+            f(Register::SP.into(), self.sp.as_bytes_mut())?;
+            f(Register::PC.into(), self.pc.as_bytes_mut())?;
+            f(Register::NZCV.into(), self.nzcv.as_bytes_mut())?;
+            each(f, &Q, &mut self.q)
+        }
+    }
+}
+
+pub mod x86 {
+    use super::{each, RegisterFile, RegisterValue, RegisterVisitor};
+    use crate::consts::{uc_error, Arch, Mode};
+    use crate::x86::Register;
+
+    /// x86-64 general purpose, segment, x87 and SSE registers.
+    #[derive(PartialEq, Eq, Debug, Clone, Default)]
+    pub struct Regs {
+        pub rax: u64,
+        pub rbx: u64,
+        pub rcx: u64,
+        pub rdx: u64,
+        pub rsi: u64,
+        pub rdi: u64,
+        pub rbp: u64,
+        pub rsp: u64,
+        pub r8: u64,
+        pub r9: u64,
+        pub r10: u64,
+        pub r11: u64,
+        pub r12: u64,
+        pub r13: u64,
+        pub r14: u64,
+        pub r15: u64,
+        pub rip: u64,
+        pub rflags: u64,
+        pub cs: u16,
+        pub ss: u16,
+        pub ds: u16,
+        pub es: u16,
+        pub fs: u16,
+        pub gs: u16,
+        pub fs_base: u64,
+        pub gs_base: u64,
+        pub fpcw: u16,
+        pub fpsw: u16,
+        pub fptag: u16,
+        pub st: [[u8; 10]; 8],
+        pub mxcsr: u32,
+        pub xmm: [u128; 16],
+    }
+
+    /// 32-bit x86 general purpose, segment, x87 and SSE registers.
+    #[derive(PartialEq, Eq, Debug, Clone, Default)]
+    pub struct Regs32 {
+        pub eax: u32,
+        pub ebx: u32,
+        pub ecx: u32,
+        pub edx: u32,
+        pub esi: u32,
+        pub edi: u32,
+        pub ebp: u32,
+        pub esp: u32,
+        pub eip: u32,
+        pub eflags: u32,
+        pub cs: u16,
+        pub ss: u16,
+        pub ds: u16,
+        pub es: u16,
+        pub fs: u16,
+        pub gs: u16,
+        pub fpcw: u16,
+        pub fpsw: u16,
+        pub fptag: u16,
+        pub st: [[u8; 10]; 8],
+        pub mxcsr: u32,
+        pub xmm: [u128; 8],
+    }
+
+    #[rustfmt::skip]
+    const ST: [Register; 8] = [
+        Register::ST0, Register::ST1, Register::ST2, Register::ST3, Register::ST4, Register::ST5,
+        Register::ST6, Register::ST7,
+    ];
+
+    #[rustfmt::skip]
+    const XMM: [Register; 16] = [
+        Register::XMM0, Register::XMM1, Register::XMM2, Register::XMM3, Register::XMM4,
+        Register::XMM5, Register::XMM6, Register::XMM7, Register::XMM8, Register::XMM9,
+        Register::XMM10, Register::XMM11, Register::XMM12, Register::XMM13, Register::XMM14,
+        Register::XMM15,
+    ];
+
+    impl RegisterFile for Regs {
+        fn supports(arch: Arch, mode: Mode) -> bool {
+            arch == Arch::X86 && mode.contains(Mode::MODE_64)
+        }
+
+        fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error> {
+            #[rustfmt::skip]
+            let gprs: [(Register, &mut u64); 18] = [
+                (Register::RAX, &mut self.rax), (Register::RBX, &mut self.rbx),
+                (Register::RCX, &mut self.rcx), (Register::RDX, &mut self.rdx),
+                (Register::RSI, &mut self.rsi), (Register::RDI, &mut self.rdi),
+                (Register::RBP, &mut self.rbp), (Register::RSP, &mut self.rsp),
+                (Register::R8, &mut self.r8), (Register::R9, &mut self.r9),
+                (Register::R10, &mut self.r10), (Register::R11, &mut self.r11),
+                (Register::R12, &mut self.r12), (Register::R13, &mut self.r13),
+                (Register::R14, &mut self.r14), (Register::R15, &mut self.r15),
+                (Register::RIP, &mut self.rip), (Register::RFLAGS, &mut self.rflags),
+            ];
+            for (reg, value) in gprs {
+                f(reg.into(), value.as_bytes_mut())?;
+            }
+
+            #[rustfmt::skip]
+            let segs: [(Register, &mut u16); 6] = [
+                (Register::CS, &mut self.cs), (Register::SS, &mut self.ss),
+                (Register::DS, &mut self.ds), (Register::ES, &mut self.es),
+                (Register::FS, &mut self.fs), (Register::GS, &mut self.gs),
+            ];
+            for (reg, value) in segs {
+                f(reg.into(), value.as_bytes_mut())?;
+            }
+
+            // The bases after the selectors, loading a selector resets them.
+            f(Register::FS_BASE.into(), self.fs_base.as_bytes_mut())?;
+            f(Register::GS_BASE.into(), self.gs_base.as_bytes_mut())?;
+            f(Register::FPCW.into(), self.fpcw.as_bytes_mut())?;
+            f(Register::FPSW.into(), self.fpsw.as_bytes_mut())?;
+            f(Register::FPTAG.into(), self.fptag.as_bytes_mut())?;
+            each(f, &ST, &mut self.st)?;
+            f(Register::MXCSR.into(), self.mxcsr.as_bytes_mut())?;
+            each(f, &XMM, &mut self.xmm)
+        }
+    }
+
+    impl RegisterFile for Regs32 {
+        fn supports(arch: Arch, mode: Mode) -> bool {
+            arch == Arch::X86 && mode.contains(Mode::MODE_32)
+        }
+
+        fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error> {
+            #[rustfmt::skip]
+            let gprs: [(Register, &mut u32); 10] = [
+                (Register::EAX, &mut self.eax), (Register::EBX, &mut self.ebx),
+                (Register::ECX, &mut self.ecx), (Register::EDX, &mut self.edx),
+                (Register::ESI, &mut self.esi), (Register::EDI, &mut self.edi),
+                (Register::EBP, &mut self.ebp), (Register::ESP, &mut self.esp),
+                (Register::EIP, &mut self.eip), (Register::EFLAGS, &mut self.eflags),
+            ];
+            for (reg, value) in gprs {
+                f(reg.into(), value.as_bytes_mut())?;
+            }
+
+            #[rustfmt::skip]
+            let segs: [(Register, &mut u16); 6] = [
+                (Register::CS, &mut self.cs), (Register::SS, &mut self.ss),
+                (Register::DS, &mut self.ds), (Register::ES, &mut self.es),
+                (Register::FS, &mut self.fs), (Register::GS, &mut self.gs),
+            ];
+            for (reg, value) in segs {
+                f(reg.into(), value.as_bytes_mut())?;
+            }
+
+            f(Register::FPCW.into(), self.fpcw.as_bytes_mut())?;
+            f(Register::FPSW.into(), self.fpsw.as_bytes_mut())?;
+            f(Register::FPTAG.into(), self.fptag.as_bytes_mut())?;
+            each(f, &ST, &mut self.st)?;
+            f(Register::MXCSR.into(), self.mxcsr.as_bytes_mut())?;
+            each(f, &XMM[..8], &mut self.xmm)
+        }
+    }
+}
+
+pub mod mips {
+    use super::{each, RegisterFile, RegisterValue, RegisterVisitor};
+    use crate::consts::{uc_error, Arch, Mode};
+    use crate::mips::Register;
+
+    /// MIPS general purpose registers. Unicorn has no access to the FPU registers.
+    #[derive(PartialEq, Eq, Debug, Clone, Default)]
+    pub struct Regs {
+        pub gpr: [u64; 32],
+        pub pc: u64,
+        pub hi: u64,
+        pub lo: u64,
+    }
+
+    #[rustfmt::skip]
+    const GPR: [Register; 32] = [
+        Register::GPR0, Register::GPR1, Register::GPR2, Register::GPR3, Register::GPR4,
+        Register::GPR5, Register::GPR6, Register::GPR7, Register::GPR8, Register::GPR9,
+        Register::GPR10, Register::GPR11, Register::GPR12, Register::GPR13, Register::GPR14,
+        Register::GPR15, Register::GPR16, Register::GPR17, Register::GPR18, Register::GPR19,
+        Register::GPR20, Register::GPR21, Register::GPR22, Register::GPR23, Register::GPR24,
+        Register::GPR25, Register::GPR26, Register::GPR27, Register::GPR28, Register::GPR29,
+        Register::GPR30, Register::GPR31,
+    ];
+
+    impl RegisterFile for Regs {
+        fn supports(arch: Arch, _mode: Mode) -> bool {
+            arch == Arch::MIPS
+        }
+
+        fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error> {
+            each(f, &GPR, &mut self.gpr)?;
+            f(Register::PC.into(), self.pc.as_bytes_mut())?;
+            f(Register::HI.into(), self.hi.as_bytes_mut())?;
+            f(Register::LO.into(), self.lo.as_bytes_mut())
+        }
+    }
+}
+
+pub mod ppc {
+    use super::{each, RegisterFile, RegisterValue, RegisterVisitor};
+    use crate::consts::{uc_error, Arch, Mode};
+    use crate::ppc::Register;
+
+    /// PowerPC general purpose registers.
+    #[derive(PartialEq, Eq, Debug, Clone, Default)]
+    pub struct Regs {
+        pub gpr: [u64; 32],
+        pub pc: u64,
+    }
+
+    #[rustfmt::skip]
+    const GPR: [Register; 32] = [
+        Register::GPR0, Register::GPR1, Register::GPR2, Register::GPR3, Register::GPR4,
+        Register::GPR5, Register::GPR6, Register::GPR7, Register::GPR8, Register::GPR9,
+        Register::GPR10, Register::GPR11, Register::GPR12, Register::GPR13, Register::GPR14,
+        Register::GPR15, Register::GPR16, Register::GPR17, Register::GPR18, Register::GPR19,
+        Register::GPR20, Register::GPR21, Register::GPR22, Register::GPR23, Register::GPR24,
+        Register::GPR25, Register::GPR26, Register::GPR27, Register::GPR28, Register::GPR29,
+        Register::GPR30, Register::GPR31,
+    ];
+
+    impl RegisterFile for Regs {
+        fn supports(arch: Arch, _mode: Mode) -> bool {
+            arch == Arch::PPC
+        }
+
+        fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error> {
+            each(f, &GPR, &mut self.gpr)?;
+            f(Register::PC.into(), self.pc.as_bytes_mut())
+        }
+    }
+}
+
+pub mod riscv {
+    use super::{each, RegisterFile, RegisterValue, RegisterVisitor};
+    use crate::consts::{uc_error, Arch, Mode};
+    use crate::riscv::Register;
+
+    /// RISC-V integer and floating point registers.
+    #[derive(PartialEq, Eq, Debug, Clone, Default)]
+    pub struct Regs {
+        pub x: [u64; 32],
+        pub pc: u64,
+        pub f: [u64; 32],
+    }
+
+    #[rustfmt::skip]
+    const X: [Register; 32] = [
+        Register::X0, Register::X1, Register::X2, Register::X3, Register::X4, Register::X5,
+        Register::X6, Register::X7, Register::X8, Register::X9, Register::X10, Register::X11,
+        Register::X12, Register::X13, Register::X14, Register::X15, Register::X16, Register::X17,
+        Register::X18, Register::X19, Register::X20, Register::X21, Register::X22, Register::X23,
+        Register::X24, Register::X25, Register::X26, Register::X27, Register::X28, Register::X29,
+        Register::X30, Register::X31,
+    ];
+
+    #[rustfmt::skip]
+    const F: [Register; 32] = [
+        Register::F0, Register::F1, Register::F2, Register::F3, Register::F4, Register::F5,
+        Register::F6, Register::F7, Register::F8, Register::F9, Register::F10, Register::F11,
+        Register::F12, Register::F13, Register::F14, Register::F15, Register::F16, Register::F17,
+        Register::F18, Register::F19, Register::F20, Register::F21, Register::F22, Register::F23,
+        Register::F24, Register::F25, Register::F26, Register::F27, Register::F28, Register::F29,
+        Register::F30, Register::F31,
+    ];
+
+    impl RegisterFile for Regs {
+        fn supports(arch: Arch, _mode: Mode) -> bool {
+            arch == Arch::RISCV
+        }
+
+        fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error> {
+            each(f, &X, &mut self.x)?;
+            f(Register::PC.into(), self.pc.as_bytes_mut())?;
+            each(f, &F, &mut self.f)
+        }
+    }
+}
+
+pub mod sparc {
+    use super::{each, RegisterFile, RegisterValue, RegisterVisitor};
+    use crate::consts::{uc_error, Arch, Mode};
+    use crate::sparc::Register;
+
+    /// SPARC registers of the current window. Unicorn has no access to the FPU
+    /// registers.
+    #[derive(PartialEq, Eq, Debug, Clone, Default)]
+    pub struct Regs {
+        pub g: [u64; 8],
+        pub o: [u64; 8],
+        pub l: [u64; 8],
+        pub i: [u64; 8],
+        pub pc: u64,
+    }
+
+    #[rustfmt::skip]
+    const G: [Register; 8] = [
+        Register::G0, Register::G1, Register::G2, Register::G3, Register::G4, Register::G5,
+        Register::G6, Register::G7,
+    ];
+
+    #[rustfmt::skip]
+    const O: [Register; 8] = [
+        Register::O0, Register::O1, Register::O2, Register::O3, Register::O4, Register::O5,
+        Register::SP, Register::O7,
+    ];
+
+    #[rustfmt::skip]
+    const L: [Register; 8] = [
+        Register::L0, Register::L1, Register::L2, Register::L3, Register::L4, Register::L5,
+        Register::L6, Register::L7,
+    ];
+
+    #[rustfmt::skip]
+    const I: [Register; 8] = [
+        Register::I0, Register::I1, Register::I2, Register::I3, Register::I4, Register::I5,
+        Register::FP, Register::I7,
+    ];
+
+    impl RegisterFile for Regs {
+        fn supports(arch: Arch, _mode: Mode) -> bool {
+            arch == Arch::SPARC
+        }
+
+        fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error> {
+            each(f, &G, &mut self.g)?;
+            each(f, &O, &mut self.o)?;
+            each(f, &L, &mut self.l)?;
+            each(f, &I, &mut self.i)?;
+            f(Register::PC.into(), self.pc.as_bytes_mut())
+        }
+    }
+}
+
+pub mod m68k {
+    use super::{each, RegisterFile, RegisterValue, RegisterVisitor};
+    use crate::consts::{uc_error, Arch, Mode};
+    use crate::m68k::Register;
+
+    /// M68K address, data and status registers.
+    #[derive(PartialEq, Eq, Debug, Clone, Default)]
+    pub struct Regs {
+        pub a: [u32; 8],
+        pub d: [u32; 8],
+        pub sr: u32,
+        pub pc: u32,
+    }
+
+    #[rustfmt::skip]
+    const A: [Register; 8] = [
+        Register::A0, Register::A1, Register::A2, Register::A3, Register::A4, Register::A5,
+        Register::A6, Register::A7,
+    ];
+
+    #[rustfmt::skip]
+    const D: [Register; 8] = [
+        Register::D0, Register::D1, Register::D2, Register::D3, Register::D4, Register::D5,
+        Register::D6, Register::D7,
+    ];
+
+    impl RegisterFile for Regs {
+        fn supports(arch: Arch, _mode: Mode) -> bool {
+            arch == Arch::M68K
+        }
+
+        fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error> {
+            each(f, &A, &mut self.a)?;
+            each(f, &D, &mut self.d)?;
+            f(Register::SR.into(), self.sr.as_bytes_mut())?;
+            f(Register::PC.into(), self.pc.as_bytes_mut())
+        }
+    }
+}
+
+pub mod s390x {
+    use super::{each, RegisterFile, RegisterValue, RegisterVisitor};
+    use crate::consts::{uc_error, Arch, Mode};
+    use crate::s390x::Register;
+
+    /// S390X general purpose, floating point and access registers.
+    #[derive(PartialEq, Eq, Debug, Clone, Default)]
+    pub struct Regs {
+        pub r: [u64; 16],
+        pub f: [u64; 32],
+        pub a: [u32; 16],
+        pub pc: u64,
+        pub pswm: u64,
+    }
+
+    #[rustfmt::skip]
+    const R: [Register; 16] = [
+        Register::R0, Register::R1, Register::R2, Register::R3, Register::R4, Register::R5,
+        Register::R6, Register::R7, Register::R8, Register::R9, Register::R10, Register::R11,
+        Register::R12, Register::R13, Register::R14, Register::R15,
+    ];
+
+    #[rustfmt::skip]
+    const F: [Register; 32] = [
+        Register::F0, Register::F1, Register::F2, Register::F3, Register::F4, Register::F5,
+        Register::F6, Register::F7, Register::F8, Register::F9, Register::F10, Register::F11,
+        Register::F12, Register::F13, Register::F14, Register::F15, Register::F16, Register::F17,
+        Register::F18, Register::F19, Register::F20, Register::F21, Register::F22, Register::F23,
+        Register::F24, Register::F25, Register::F26, Register::F27, Register::F28, Register::F29,
+        Register::F30, Register::F31,
+    ];
+
+    #[rustfmt::skip]
+    const A: [Register; 16] = [
+        Register::A0, Register::A1, Register::A2, Register::A3, Register::A4, Register::A5,
+        Register::A6, Register::A7, Register::A8, Register::A9, Register::A10, Register::A11,
+        Register::A12, Register::A13, Register::A14, Register::A15,
+    ];
+
+    impl RegisterFile for Regs {
+        fn supports(arch: Arch, _mode: Mode) -> bool {
+            arch == Arch::S390X
+        }
+
+        fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error> {
+            each(f, &R, &mut self.r)?;
+            each(f, &F, &mut self.f)?;
+            each(f, &A, &mut self.a)?;
+            f(Register::PSWM.into(), self.pswm.as_bytes_mut())?;
+            f(Register::PC.into(), self.pc.as_bytes_mut())
+        }
+    }
+}
+
+pub mod tricore {
+    use super::{each, RegisterFile, RegisterValue, RegisterVisitor};
+    use crate::consts::{uc_error, Arch, Mode};
+    use crate::tricore::Register;
+
+    /// TriCore address, data and core status registers.
+    #[derive(PartialEq, Eq, Debug, Clone, Default)]
+    pub struct Regs {
+        pub a: [u32; 16],
+        pub d: [u32; 16],
+        pub pcxi: u32,
+        pub psw: u32,
+        pub pc: u32,
+    }
+
+    #[rustfmt::skip]
+    const A: [Register; 16] = [
+        Register::A0, Register::A1, Register::A2, Register::A3, Register::A4, Register::A5,
+        Register::A6, Register::A7, Register::A8, Register::A9, Register::A10, Register::A11,
+        Register::A12, Register::A13, Register::A14, Register::A15,
+    ];
+
+    #[rustfmt::skip]
+    const D: [Register; 16] = [
+        Register::D0, Register::D1, Register::D2, Register::D3, Register::D4, Register::D5,
+        Register::D6, Register::D7, Register::D8, Register::D9, Register::D10, Register::D11,
+        Register::D12, Register::D13, Register::D14, Register::D15,
+    ];
+
+    impl RegisterFile for Regs {
+        fn supports(arch: Arch, _mode: Mode) -> bool {
+            arch == Arch::TRICORE
+        }
+
+        fn for_each_register(&mut self, f: &mut RegisterVisitor) -> Result<(), uc_error> {
+            each(f, &A, &mut self.a)?;
+            each(f, &D, &mut self.d)?;
+            f(Register::PCXI.into(), self.pcxi.as_bytes_mut())?;
+            f(Register::PSW.into(), self.psw.as_bytes_mut())?;
+            f(Register::PC.into(), self.pc.as_bytes_mut())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegisterFile;
+    use crate::{arm, x86};
+
+    fn registers<R: RegisterFile>(regs: &mut R) -> Vec<(i32, usize)> {
+        let mut registers = vec![];
+        regs.for_each_register(&mut |regid, value| {
+            registers.push((regid, value.len()));
+            Ok(())
+        })
+        .unwrap();
+        registers
+    }
+
+    #[test]
+    fn arm_pc_goes_first() {
+        let registers = registers(&mut arm::Regs::default());
+        assert_eq!(registers.len(), 13 + 3 + 2 + 32);
+        assert_eq!(registers[0], (arm::Register::PC.into(), 4));
+        assert_eq!(registers[1], (arm::Register::CPSR.into(), 4));
+        assert_eq!(registers.last(), Some(&(arm::Register::D31.into(), 8)));
+    }
+
+    #[test]
+    fn values_are_visible_as_bytes() {
+        let mut regs = x86::Regs {
+            rax: 0x1122_3344_5566_7788,
+            ..Default::default()
+        };
+        regs.for_each_register(&mut |regid, value| {
+            if regid == i32::from(x86::Register::RAX) {
+                assert_eq!(value, &0x1122_3344_5566_7788u64.to_ne_bytes());
+                value.copy_from_slice(&1u64.to_ne_bytes());
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(regs.rax, 1);
+        assert!(registers(&mut regs).contains(&(x86::Register::ST0.into(), 10)));
+    }
+}
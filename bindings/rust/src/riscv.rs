@@ -217,3 +217,39 @@ impl From<Register> for i32 {
         r as i32
     }
 }
+
+// RISCV32 CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel32 {
+    ANY = 0,
+    BASE32,
+    SIFIVE_E31,
+    SIFIVE_U34,
+}
+
+impl From<CpuModel32> for i32 {
+    fn from(model: CpuModel32) -> Self {
+        model as i32
+    }
+}
+
+// RISCV64 CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel64 {
+    ANY = 0,
+    BASE64,
+    SIFIVE_E51,
+    SIFIVE_U54,
+}
+
+impl From<CpuModel64> for i32 {
+    fn from(model: CpuModel64) -> Self {
+        model as i32
+    }
+}
+
+pub use crate::regs::riscv::Regs;
@@ -0,0 +1,2614 @@
+//! Pure Rust port of the `UCAFL` runtime in `unicornafl.cpp`.
+//!
+//! Enabled by the `rust-runtime` feature. `afl.rs` then calls into this module
+//! instead of `libunicornafl`, so only the Unicorn static libraries need to be linked.
+//! The forkserver protocol, coverage hooks and testcase delivery follow the C++
+//! implementation, see there for the details.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{c_char, c_int, c_void, CStr, OsStr};
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::process;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::time::Instant;
+
+use crate::afl::{
+    uc_afl_cov_mode, uc_afl_options, AddressRange, AflRet, CmpLogRoutine, CmpLogRoutineKind,
+};
+use crate::consts::{uc_error, Arch, HookType, MemType, Mode, Query};
+use crate::ctl::{
+    uc_ctl_request, UC_CTL_IO_READ, UC_CTL_IO_WRITE, UC_CTL_TB_REQUEST_CACHE, UC_CTL_UC_EXITS,
+    UC_CTL_UC_USE_EXITS,
+};
+use crate::ffi::{self, uc_handle, uc_hook};
+use crate::touched_pages;
+use crate::{arm, arm64, m68k, mips, ppc, riscv, s390x, sparc, tricore, x86};
+
+/// Unicorn 2.0.0rc6, the first version with exits and TB cache control.
+const MIN_UC_VERSION: u32 = 0x0200_0006;
+
+const MAP_SIZE: u32 = 1 << 16;
+// Bounds for a map size set with `AFL_MAP_SIZE` or `uc_afl_options`
+const MAP_SIZE_MIN: u32 = 64;
+const MAP_SIZE_MAX: u32 = 1 << 30;
+
+const FORKSRV_FD: c_int = 198;
+const SHM_ENV_VAR: &str = "__AFL_SHM_ID";
+const SHM_FUZZ_ENV_VAR: &str = "__AFL_SHM_FUZZ_ID";
+const CMPLOG_SHM_ENV_VAR: &str = "__AFL_CMPLOG_SHM_ID";
+
+const FS_OPT_ENABLED: u32 = 0x8000_0001;
+const FS_OPT_MAPSIZE: u32 = 0x4000_0000;
+const FS_OPT_AUTODICT: u32 = 0x1000_0000;
+const FS_OPT_SHDMEM_FUZZ: u32 = 0x0100_0000;
+const FS_OPT_ERROR: u32 = 0xf800_008f;
+const FS_OPT_MAX_MAPSIZE: u32 = (0x00ff_fffe >> 1) + 1;
+
+const fn fs_opt_set_mapsize(x: u32) -> u32 {
+    if x <= 1 || x > FS_OPT_MAX_MAPSIZE {
+        0
+    } else {
+        (x - 1) << 1
+    }
+}
+
+const fn fs_opt_set_error(x: u32) -> u32 {
+    (x & 0x0000_ffff) << 8
+}
+
+// Reason codes for `FS_OPT_ERROR` and `FS_NEW_ERROR`
+const FS_ERROR_MAP_SIZE: u32 = 1;
+const FS_ERROR_SHMAT: u32 = 8;
+
+// The versioned forkserver handshake of AFL++ >= 4.20
+const FS_NEW_VERSION_MSG: u32 = 0x4146_4c00 + 1;
+const FS_NEW_ERROR: u32 = 0xeffe_0000;
+const FS_NEW_OPT_MAPSIZE: u32 = 0x0000_0001;
+const FS_NEW_OPT_SHDMEM_FUZZ: u32 = 0x0000_0002;
+const FS_NEW_OPT_AUTODICT: u32 = 0x0000_0800;
+
+// The CmpLog map from aflpp/cmplog.h
+const CMP_MAP_W: usize = 65536;
+const CMP_MAP_H: usize = 32;
+const CMP_MAP_RTN_H: usize = CMP_MAP_H / 2;
+
+const CMP_TYPE_INS: u16 = 0;
+const CMP_TYPE_RTN: u16 = 1;
+
+/// Longest buffer logged for a compare routine
+const CMPLOG_RTN_LEN: usize = 32;
+
+/// `struct cmp_header`, a C bitfield of `hits:6 shape:5 type:1 attribute:4`.
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+struct CmpHeader(u16);
+
+impl CmpHeader {
+    fn hits(self) -> u16 {
+        self.0 & 0x3f
+    }
+
+    fn set_hits(&mut self, hits: u16) {
+        self.0 = (self.0 & !0x3f) | (hits & 0x3f);
+    }
+
+    fn shape(self) -> u16 {
+        (self.0 >> 6) & 0x1f
+    }
+
+    fn set_shape(&mut self, shape: u16) {
+        self.0 = (self.0 & !(0x1f << 6)) | ((shape & 0x1f) << 6);
+    }
+
+    fn cmp_type(self) -> u16 {
+        (self.0 >> 11) & 1
+    }
+
+    fn set_cmp_type(&mut self, cmp_type: u16) {
+        self.0 = (self.0 & !(1 << 11)) | ((cmp_type & 1) << 11);
+    }
+}
+
+#[repr(C, packed)]
+struct CmpOperands {
+    v0: u64,
+    v0_128: u64,
+    v0_256_0: u64,
+    v0_256_1: u64,
+    v1: u64,
+    v1_128: u64,
+    v1_256_0: u64,
+    v1_256_1: u64,
+    unused: [u8; 8],
+}
+
+#[repr(C, packed)]
+struct CmpFnOperands {
+    v0: [u8; 32],
+    v1: [u8; 32],
+    v0_len: u8,
+    v1_len: u8,
+    unused: [u8; 6],
+}
+
+#[repr(C)]
+struct CmpMap {
+    headers: [CmpHeader; CMP_MAP_W],
+    log: [[CmpOperands; CMP_MAP_H]; CMP_MAP_W],
+}
+
+fn cmplog_key(address: u64) -> usize {
+    (((address >> 4) ^ (address << 8)) as usize) & (CMP_MAP_W - 1)
+}
+
+// Bounds for `UC_AFL_COV_NGRAM`, like `AFL_LLVM_NGRAM_SIZE`
+const NGRAM_SIZE_MIN: u32 = 2;
+const NGRAM_SIZE_MAX: u32 = 16;
+
+// `afl_child_ret` in priv.h
+const AFL_CHILD_NEXT: u32 = 0;
+const AFL_CHILD_FOUND_CRASH: u32 = 1;
+const AFL_CHILD_TSL_REQUEST: u32 = 2;
+const AFL_CHILD_EDGE_ID: u32 = 3;
+const AFL_CHILD_EXITED: u32 = 4;
+
+const UC_TCG_OP_SUB: c_int = 0;
+const UC_TCG_OP_FLAG_CMP: c_int = 1;
+const UC_TCG_OP_FLAG_DIRECT: c_int = 2;
+
+type PlaceInputCallback =
+    unsafe extern "C" fn(uc_handle, *mut u8, libc::size_t, u32, *mut c_void) -> bool;
+type ValidateCrashCallback =
+    unsafe extern "C" fn(uc_handle, uc_error, *mut u8, c_int, c_int, *mut c_void) -> bool;
+type FuzzCallback = unsafe extern "C" fn(uc_handle, *mut c_void) -> uc_error;
+
+/// The forkserver started by `uc_afl_forkserver_start`, it has to outlive the call
+/// because the child keeps running our hooks after it returns.
+static FORKSERVER_UCAFL: AtomicPtr<UcAfl> = AtomicPtr::new(ptr::null_mut());
+
+/// Debug output, enabled by `AFL_DEBUG` and `AFL_DEBUG_UNICORN` like in the C++ runtime.
+struct Logger {
+    debug: bool,
+    debug_unicorn: bool,
+    t0: Instant,
+}
+
+impl Logger {
+    fn new() -> Self {
+        Logger {
+            debug: env::var_os("AFL_DEBUG").is_some(),
+            debug_unicorn: env::var_os("AFL_DEBUG_UNICORN").is_some(),
+            t0: Instant::now(),
+        }
+    }
+
+    fn log(&self, in_child: bool, args: fmt::Arguments) {
+        if (in_child && !self.debug_unicorn) || (!in_child && !self.debug) {
+            return;
+        }
+
+        let mut line = String::from("[u] ");
+        if self.debug {
+            line.push_str(&format!("[{:04.6}] ", self.t0.elapsed().as_secs_f64()));
+        }
+        if in_child {
+            line.push_str(&format!("[{:04}] ", process::id()));
+        }
+        eprint!("{}{}", line, args);
+    }
+}
+
+macro_rules! err {
+    ($ucafl:expr, $($arg:tt)*) => {
+        $ucafl.logger.log(false, format_args!($($arg)*))
+    };
+}
+
+macro_rules! err_child {
+    ($ucafl:expr, $($arg:tt)*) => {
+        $ucafl.logger.log(true, format_args!($($arg)*))
+    };
+}
+
+fn read_u32(fd: c_int) -> Option<u32> {
+    let mut value: u32 = 0;
+    let len = unsafe { libc::read(fd, &mut value as *mut u32 as _, 4) };
+    if len == 4 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+fn write_u32(fd: c_int, value: u32) -> bool {
+    unsafe { libc::write(fd, &value as *const u32 as _, 4) == 4 }
+}
+
+fn write_all(fd: c_int, mut buf: &[u8]) -> bool {
+    while !buf.is_empty() {
+        let ret = unsafe { libc::write(fd, buf.as_ptr() as *const c_void, buf.len()) };
+        if ret <= 0 {
+            return false;
+        }
+        buf = &buf[ret as usize..];
+    }
+    true
+}
+
+fn read_u64(fd: c_int) -> Option<u64> {
+    let mut value: u64 = 0;
+    let len = unsafe { libc::read(fd, &mut value as *mut u64 as _, 8) };
+    if len == 8 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+fn write_u64(fd: c_int, value: u64) -> bool {
+    unsafe { libc::write(fd, &value as *const u64 as _, 8) == 8 }
+}
+
+/// Tell afl-fuzz why we could not start, it only listens while it waits for the
+/// forkserver handshake.
+fn send_forkserver_error(error: u32) {
+    if error == 0 || error > 0xffff {
+        return;
+    }
+
+    let status = if env::var_os("AFL_OLD_FORKSERVER").is_some() {
+        FS_OPT_ERROR | fs_opt_set_error(error)
+    } else {
+        FS_NEW_ERROR | error
+    };
+
+    write_u32(FORKSRV_FD + 1, status);
+}
+
+/// Just need a value that makes `WIFSIGNALED` true to inform AFL we crashed.
+fn valid_wifsignaled() -> c_int {
+    let mut ret = 0;
+    while !libc::WIFSIGNALED(ret) {
+        ret += 1;
+    }
+    ret
+}
+
+/// The coverage map size, in order of precedence: the explicit option,
+/// `AFL_MAP_SIZE` and `MAP_SIZE`. Rounded up to 64 bytes like afl-fuzz does,
+/// `None` if the requested size is invalid.
+fn afl_map_size(requested: u32, env_map_size: Option<&str>) -> Option<u32> {
+    let map_size = match (requested, env_map_size) {
+        (0, None) => return Some(MAP_SIZE),
+        // Like strtoull, which gives 0 if there is no number.
+        (0, Some(map_size)) => strtoull(map_size).map_or(0, |(map_size, _)| map_size),
+        (requested, _) => u64::from(requested),
+    };
+
+    if !(u64::from(MAP_SIZE_MIN)..=u64::from(MAP_SIZE_MAX)).contains(&map_size) {
+        return None;
+    }
+
+    Some(((map_size + 63) & !63) as u32)
+}
+
+/// Mask for coverage indices, based on the largest power of 2 that fits into `map_size`.
+fn afl_map_mask(map_size: u32) -> u64 {
+    (1u64 << (31 - map_size.leading_zeros())) - 7
+}
+
+/// Leaves room for the up to 6 extra split compare entries behind `cur_loc`.
+fn afl_cur_loc(address: u64, map_mask: u64) -> u64 {
+    ((address >> 4) ^ (address << 8)) & map_mask
+}
+
+/// Parses an `AFL_UNICORN_INST_RANGES` style list of ranges like
+/// `0x1000-0x1fff,0x4000-0x4fff`. Like in the C++ runtime, only a trailing comma
+/// is allowed besides the ranges.
+fn afl_parse_ranges(ranges: &str) -> Option<Vec<AddressRange>> {
+    let mut parsed = vec![];
+    let mut rest = ranges;
+
+    while !rest.is_empty() {
+        let (begin, after) = strtoull(rest)?;
+        let (end, after) = strtoull(after.strip_prefix('-')?)?;
+        if begin > end {
+            return None;
+        }
+        parsed.push(AddressRange { begin, end });
+
+        rest = match after.strip_prefix(',') {
+            Some(after) => after,
+            None if after.is_empty() => after,
+            None => return None,
+        };
+    }
+
+    Some(parsed)
+}
+
+/// Parses the number at the start of `number` like `strtoull(.., &end, 0)` does
+/// and returns it with the rest of the string, `None` if there is no number.
+///
+/// Leading whitespace and a sign are skipped. The number is hex with `0x`, octal
+/// with a leading `0` and decimal otherwise. It saturates at `u64::MAX`.
+fn strtoull(number: &str) -> Option<(u64, &str)> {
+    let number = number.trim_start_matches(|c| c == ' ' || ('\t'..='\r').contains(&c));
+    let (negative, number) = match number.strip_prefix('-') {
+        Some(number) => (true, number),
+        None => (false, number.strip_prefix('+').unwrap_or(number)),
+    };
+
+    let hex = number
+        .strip_prefix("0x")
+        .or_else(|| number.strip_prefix("0X"));
+    let (radix, digits) = match hex {
+        Some(hex) if hex.starts_with(|c: char| c.is_ascii_hexdigit()) => (16, hex),
+        _ if number.starts_with('0') => (8, number),
+        _ => (10, number),
+    };
+    let len = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    if len == 0 {
+        return None;
+    }
+
+    let value = match u64::from_str_radix(&digits[..len], radix) {
+        Ok(value) if negative => value.wrapping_neg(),
+        Ok(value) => value,
+        Err(_) => u64::MAX,
+    };
+    Some((value, &digits[len..]))
+}
+
+/// The ranges to instrument: the included ones, or the whole address space if
+/// there are none, minus the excluded ones. Overlapping includes are merged, so
+/// no code gets hooked twice.
+fn afl_inst_ranges(include: &[AddressRange], exclude: &[AddressRange]) -> Vec<AddressRange> {
+    let mut sorted = include.to_vec();
+    sorted.sort_by_key(|r| r.begin);
+
+    let mut ranges: Vec<AddressRange> = vec![];
+    for r in sorted {
+        match ranges.last_mut() {
+            Some(last) if last.end == u64::MAX || r.begin <= last.end + 1 => {
+                last.end = last.end.max(r.end);
+            }
+            _ => ranges.push(r),
+        }
+    }
+
+    if ranges.is_empty() {
+        ranges.push(AddressRange {
+            begin: 0,
+            end: u64::MAX,
+        });
+    }
+
+    for ex in exclude {
+        let mut left = vec![];
+        for r in ranges {
+            if ex.end < r.begin || ex.begin > r.end {
+                left.push(r);
+                continue;
+            }
+            if ex.begin > r.begin {
+                left.push(AddressRange {
+                    begin: r.begin,
+                    end: ex.begin - 1,
+                });
+            }
+            if ex.end < r.end {
+                left.push(AddressRange {
+                    begin: ex.end + 1,
+                    end: r.end,
+                });
+            }
+        }
+        ranges = left;
+    }
+
+    ranges
+}
+
+/// Numbers edges in the order they are first hit, for `UC_AFL_COV_EDGE_ID`.
+///
+/// The forkserver parent repeats every numbering of its children, so the IDs
+/// survive across them.
+struct EdgeIds {
+    ids: HashMap<(u64, u64), u32>,
+    next_id: u32,
+}
+
+impl EdgeIds {
+    fn new() -> Self {
+        // 0 is the "we are alive" entry of the map.
+        EdgeIds {
+            ids: HashMap::new(),
+            next_id: 1,
+        }
+    }
+
+    /// The ID of the edge and whether it is new, `None` once the map is full.
+    fn get(&mut self, prev_pc: u64, cur_pc: u64, map_size: u32) -> (Option<u32>, bool) {
+        if let Some(&id) = self.ids.get(&(prev_pc, cur_pc)) {
+            return (Some(id), false);
+        }
+        if self.next_id >= map_size {
+            return (None, false);
+        }
+
+        let id = self.next_id;
+        self.ids.insert((prev_pc, cur_pc), id);
+        self.next_id += 1;
+        (Some(id), true)
+    }
+}
+
+/// The registers and pages restored before every iteration with
+/// `persistent_restore`.
+struct PersistentSnapshot {
+    context: ffi::uc_context,
+    page_size: u64,
+    // The contents before the first write, and whether the page was written to
+    // since the last restore
+    pages: HashMap<u64, (Vec<u8>, bool)>,
+    dirty: Vec<u64>,
+}
+
+impl PersistentSnapshot {
+    /// The pages touched by a write of `size` bytes at `address`.
+    fn pages(&self, address: u64, size: u64) -> impl Iterator<Item = u64> {
+        touched_pages(address, size, self.page_size)
+    }
+
+    /// Marks `page` dirty, `read` saves its contents the first time.
+    fn dirty(&mut self, page: u64, read: impl FnOnce(&mut [u8]) -> bool) -> bool {
+        if let Some((_, dirty)) = self.pages.get_mut(&page) {
+            if !*dirty {
+                *dirty = true;
+                self.dirty.push(page);
+            }
+            return true;
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        let mut data = vec![0; self.page_size as usize];
+        if !read(&mut data) {
+            return false;
+        }
+
+        self.pages.insert(page, (data, true));
+        self.dirty.push(page);
+        true
+    }
+}
+
+/// Our copy of `uc_afl_options`.
+struct Options {
+    // Length prefixed tokens
+    autodict: Vec<u8>,
+    // 0 to pick it up from the environment, see `afl_map_size`
+    map_size: u32,
+    cmplog_rtns: Vec<CmpLogRoutine>,
+    cov_mode: uc_afl_cov_mode,
+    cov_ngram_size: u32,
+    cov_ranges: Vec<AddressRange>,
+    cov_exclude_ranges: Vec<AddressRange>,
+    persistent_restore: bool,
+}
+
+impl Options {
+    /// Copies and validates `options`, which may be NULL for the defaults.
+    unsafe fn from_c(options: *const uc_afl_options) -> Result<Self, &'static str> {
+        let options = match options.as_ref() {
+            Some(options) => options,
+            None => return Ok(Options::default()),
+        };
+
+        if options.size as usize != mem::size_of::<uc_afl_options>() {
+            return Err("Unknown uc_afl_options size.\n");
+        }
+        if options.autodict_len != 0 && options.autodict.is_null() {
+            return Err("autodict_len set but autodict is missing.\n");
+        }
+        if options.cov_mode == uc_afl_cov_mode::UC_AFL_COV_NGRAM
+            && !(NGRAM_SIZE_MIN..=NGRAM_SIZE_MAX).contains(&options.cov_ngram_size)
+        {
+            return Err("cov_ngram_size has to be between 2 and 16.\n");
+        }
+        if options.cmplog_rtn_count != 0 && options.cmplog_rtns.is_null() {
+            return Err("cmplog_rtn_count set but cmplog_rtns is missing.\n");
+        }
+
+        let autodict = if options.autodict_len != 0 {
+            std::slice::from_raw_parts(options.autodict, options.autodict_len).to_vec()
+        } else {
+            vec![]
+        };
+        let cmplog_rtns = if options.cmplog_rtn_count != 0 {
+            std::slice::from_raw_parts(options.cmplog_rtns, options.cmplog_rtn_count).to_vec()
+        } else {
+            vec![]
+        };
+        let cov_ranges = copy_ranges(options.cov_ranges, options.cov_range_count)?;
+        let cov_exclude_ranges =
+            copy_ranges(options.cov_exclude_ranges, options.cov_exclude_range_count)?;
+
+        Ok(Options {
+            autodict,
+            map_size: options.map_size,
+            cmplog_rtns,
+            cov_mode: options.cov_mode,
+            cov_ngram_size: options.cov_ngram_size,
+            cov_ranges,
+            cov_exclude_ranges,
+            persistent_restore: options.persistent_restore,
+        })
+    }
+}
+
+unsafe fn copy_ranges(
+    ranges: *const AddressRange,
+    count: usize,
+) -> Result<Vec<AddressRange>, &'static str> {
+    if count == 0 {
+        return Ok(vec![]);
+    }
+    if ranges.is_null() {
+        return Err("Invalid cov_ranges or cov_exclude_ranges.\n");
+    }
+
+    let ranges = std::slice::from_raw_parts(ranges, count).to_vec();
+    if ranges.iter().any(|range| range.begin > range.end) {
+        return Err("Invalid cov_ranges or cov_exclude_ranges.\n");
+    }
+    Ok(ranges)
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            autodict: vec![],
+            map_size: 0,
+            cmplog_rtns: vec![],
+            cov_mode: uc_afl_cov_mode::UC_AFL_COV_EDGE,
+            cov_ngram_size: 0,
+            cov_ranges: vec![],
+            cov_exclude_ranges: vec![],
+            persistent_restore: false,
+        }
+    }
+}
+
+pub(crate) struct UcAfl {
+    uc: uc_handle,
+    input_file: Option<PathBuf>,
+    place_input_callback: Option<PlaceInputCallback>,
+    validate_crash_callback: Option<ValidateCrashCallback>,
+    fuzz_callback: Option<FuzzCallback>,
+    always_validate: bool,
+    persistent_iters: u32,
+    options: Options,
+    data: *mut c_void,
+    // Used both in shm and file testcases
+    afl_testcase_ptr: *mut u8,
+    afl_testcase_len_p: *mut u32,
+    afl_use_shm_testcase: bool,
+    // Only used for file testcases
+    afl_testcase_buf: Vec<u8>,
+    afl_testcase_len: u32,
+    // Core mapping for coverage
+    afl_area_ptr: *mut u8,
+    // Backs `afl_area_ptr` if we did not get a mapping from AFL
+    afl_local_area: Vec<u8>,
+    // This variable checks whether we get a mapping from AFL.
+    has_afl: bool,
+    // Size of `afl_area_ptr`
+    map_size: u32,
+    afl_map_mask: u64,
+    // CmpLog mapping, only there if afl-fuzz runs us for CmpLog
+    afl_cmp_map: *mut CmpMap,
+    afl_inst_rms: u64,
+    afl_prev_loc: u64,
+    // Only used for `UC_AFL_COV_NGRAM`, the most recent block first.
+    afl_ngram_prev_locs: [u64; NGRAM_SIZE_MAX as usize - 1],
+    // Only used for `UC_AFL_COV_EDGE_ID`
+    afl_prev_pc: u64,
+    afl_edge_ids: EdgeIds,
+    // Only used for `UC_AFL_COV_CTX`
+    sp_reg: i32,
+    // Fake signal value
+    wifsignaled: c_int,
+    // Communication pipes
+    afl_child_pipe: [c_int; 2],
+    afl_parent_pipe: [c_int; 2],
+    // Only used with `persistent_restore`
+    persistent: Option<PersistentSnapshot>,
+    // Our hooks
+    hooks: Vec<uc_hook>,
+    logger: Logger,
+}
+
+impl UcAfl {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        uc: uc_handle,
+        input_file: Option<PathBuf>,
+        place_input_callback: Option<PlaceInputCallback>,
+        validate_crash_callback: Option<ValidateCrashCallback>,
+        fuzz_callback: Option<FuzzCallback>,
+        always_validate: bool,
+        persistent_iters: u32,
+        options: Options,
+        data: *mut c_void,
+        logger: Logger,
+    ) -> Box<Self> {
+        Box::new(UcAfl {
+            uc,
+            input_file,
+            place_input_callback,
+            validate_crash_callback,
+            fuzz_callback,
+            always_validate,
+            persistent_iters,
+            options,
+            data,
+            afl_testcase_ptr: ptr::null_mut(),
+            afl_testcase_len_p: ptr::null_mut(),
+            afl_use_shm_testcase: false,
+            afl_testcase_buf: vec![],
+            afl_testcase_len: 0,
+            afl_area_ptr: ptr::null_mut(),
+            afl_local_area: vec![],
+            has_afl: false,
+            map_size: MAP_SIZE,
+            afl_map_mask: afl_map_mask(MAP_SIZE),
+            afl_cmp_map: ptr::null_mut(),
+            afl_inst_rms: u64::from(MAP_SIZE),
+            afl_prev_loc: 0,
+            afl_ngram_prev_locs: [0; NGRAM_SIZE_MAX as usize - 1],
+            afl_prev_pc: 0,
+            afl_edge_ids: EdgeIds::new(),
+            sp_reg: 0,
+            wifsignaled: 0,
+            afl_child_pipe: [0; 2],
+            afl_parent_pipe: [0; 2],
+            persistent: None,
+            hooks: vec![],
+            logger,
+        })
+    }
+
+    fn set_exits(&mut self, exits: &[u64]) -> AflRet {
+        let err = unsafe {
+            ffi::uc_ctl(
+                self.uc,
+                uc_ctl_request(UC_CTL_UC_USE_EXITS, 1, UC_CTL_IO_WRITE),
+                1 as c_int,
+            )
+        };
+        if err != uc_error::OK {
+            err!(self, "Fail to enable exits for Unicorn Engine.\n");
+            return AflRet::Error;
+        }
+
+        let err = unsafe {
+            ffi::uc_ctl(
+                self.uc,
+                uc_ctl_request(UC_CTL_UC_EXITS, 2, UC_CTL_IO_WRITE),
+                exits.as_ptr(),
+                exits.len() as libc::size_t,
+            )
+        };
+        if err != uc_error::OK {
+            err!(self, "Fail to set exits.\n");
+            return AflRet::Error;
+        }
+
+        AflRet::Ok
+    }
+
+    fn fsrv_run(&mut self) -> AflRet {
+        self.may_use_shm_testcase();
+        self.afl_setup();
+        if self.has_afl {
+            self.uc_setup();
+        }
+
+        let ret = self.fksrv_start();
+
+        err!(self, "fksrv_start returns {:?}\n", ret);
+
+        match ret {
+            // We are the child.
+            AflRet::Child => self.child_fuzz(true),
+            AflRet::NoAfl => {
+                // Only run once.
+                self.persistent_iters = 1;
+                self.child_fuzz(false)
+            }
+            AflRet::Finished | AflRet::Error | AflRet::CalledTwice => ret,
+            AflRet::Ok => AflRet::Error,
+        }
+    }
+
+    fn fsrv_start(&mut self) -> AflRet {
+        self.afl_setup();
+        if self.has_afl {
+            self.uc_setup();
+        }
+
+        self.fksrv_start()
+    }
+
+    fn afl_next(&mut self, crash_found: bool) -> AflRet {
+        let msg = if crash_found {
+            AFL_CHILD_FOUND_CRASH
+        } else {
+            AFL_CHILD_NEXT
+        };
+
+        // Make very sure everything has been written to the map at this point
+        std::sync::atomic::compiler_fence(Ordering::SeqCst);
+
+        if !write_u32(self.afl_child_pipe[1], msg) {
+            err!(self, "[!] Error writing to parent pipe. Parent dead?\n");
+            return AflRet::Error;
+        }
+
+        // Once the parent has written something, the next persistent loop
+        // starts. This blocks until the next testcase is ready.
+        if read_u32(self.afl_parent_pipe[0]).is_none() {
+            err!(self, "[!] Error reading from parent pipe. Parent dead?\n");
+            return AflRet::Error;
+        }
+
+        unsafe { ptr::write_bytes(self.afl_area_ptr, 0, self.map_size as usize) };
+        // Also make sure nothing read before this point.
+        std::sync::atomic::compiler_fence(Ordering::SeqCst);
+
+        // Start with a clean slate.
+        self.afl_reset_prev_loc();
+        unsafe { *self.afl_area_ptr = 1 };
+
+        AflRet::Ok
+    }
+
+    /// Returns the current testcase, reading it from `input_file` if it is not
+    /// delivered through shared memory.
+    fn testcase(&mut self) -> (*mut u8, usize) {
+        if !self.afl_use_shm_testcase {
+            let input_file = self.input_file.as_ref().expect("no input file");
+            match fs::read(input_file) {
+                Ok(buf) => self.afl_testcase_buf = buf,
+                Err(e) => {
+                    eprintln!("[!] Fail to read testcase: {}", e);
+                    process::exit(1);
+                }
+            }
+            self.afl_testcase_len = self.afl_testcase_buf.len() as u32;
+            self.afl_testcase_ptr = self.afl_testcase_buf.as_mut_ptr();
+            self.afl_testcase_len_p = &mut self.afl_testcase_len;
+        }
+
+        (self.afl_testcase_ptr, unsafe { *self.afl_testcase_len_p } as usize)
+    }
+
+    fn child_fuzz(&mut self, afl_exist: bool) -> AflRet {
+        let place_input_callback = self
+            .place_input_callback
+            .expect("no place_input_callback set");
+        let fuzz_callback = self.fuzz_callback.expect("no fuzz_callback set");
+        let mut crash_found = false;
+        let mut first_round = true;
+        let mut i: u32 = 0;
+
+        if self.options.persistent_restore && self.persistent_iters != 1 {
+            self.persistent_setup();
+        }
+
+        while self.persistent_iters == 0 || i < self.persistent_iters {
+            let round = i;
+            i = i.wrapping_add(1);
+
+            if first_round {
+                first_round = false;
+            } else {
+                if self.afl_next(crash_found) != AflRet::Ok {
+                    process::exit(1);
+                }
+
+                crash_found = false;
+
+                if self.persistent.is_some() {
+                    self.persistent_restore();
+                }
+            }
+
+            let (input, input_len) = self.testcase();
+
+            let input_accepted =
+                unsafe { place_input_callback(self.uc, input, input_len, round, self.data) };
+
+            if !input_accepted {
+                err_child!(self, "Input is not accepted.\n");
+                continue;
+            }
+
+            let uc_ret = unsafe { fuzz_callback(self.uc, self.data) };
+
+            err_child!(
+                self,
+                "We are stopping for uc_err={:?} ({})\n",
+                uc_ret,
+                uc_strerror(uc_ret)
+            );
+
+            if uc_ret != uc_error::OK
+                || (self.always_validate && self.validate_crash_callback.is_some())
+            {
+                if let Some(validate_crash_callback) = self.validate_crash_callback {
+                    #[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
+                    let crashed = unsafe {
+                        validate_crash_callback(
+                            self.uc,
+                            uc_ret,
+                            input,
+                            input_len as c_int,
+                            round as c_int,
+                            self.data,
+                        )
+                    };
+                    if !crashed {
+                        continue;
+                    }
+                }
+
+                if self.persistent_iters != 1 {
+                    crash_found = true;
+                    continue;
+                }
+
+                err_child!(
+                    self,
+                    "UC returned Error: '{}' - let's abort().\n",
+                    uc_strerror(uc_ret)
+                );
+
+                process::abort();
+            }
+        }
+
+        // We are still in the child, nothing good will come after this.
+        // Exit and let the next generation run.
+        if afl_exist {
+            process::exit(0);
+        }
+
+        // Just run once.
+        AflRet::NoAfl
+    }
+
+    fn persistent_setup(&mut self) {
+        let mut page_size: libc::size_t = 0;
+        let err = unsafe { ffi::uc_query(self.uc, Query::PAGE_SIZE, &mut page_size) };
+        if err != uc_error::OK || page_size == 0 {
+            err_child!(self, "Fail to get the page size.\n");
+            process::exit(1);
+        }
+
+        let mut context: ffi::uc_context = ptr::null_mut();
+        let err = unsafe { ffi::uc_context_alloc(self.uc, &mut context) };
+        if err != uc_error::OK || unsafe { ffi::uc_context_save(self.uc, context) } != uc_error::OK
+        {
+            err_child!(self, "Fail to save the context for persistent mode.\n");
+            process::exit(1);
+        }
+
+        self.persistent = Some(PersistentSnapshot {
+            context,
+            page_size: page_size as u64,
+            pages: HashMap::new(),
+            dirty: vec![],
+        });
+
+        let user_data = self as *mut UcAfl as *mut c_void;
+        if self.hook_add(
+            HookType::MEM_WRITE,
+            Self::uc_hook_persistent_write as _,
+            user_data,
+            None,
+            1,
+            0,
+        ) != uc_error::OK
+        {
+            err_child!(self, "Failed to setup persistent write hook.\n");
+            process::exit(1);
+        }
+    }
+
+    /// Saves a page the first time it is written to, the hook runs before the
+    /// write lands.
+    unsafe extern "C" fn uc_hook_persistent_write(
+        uc: uc_handle,
+        _mem_type: MemType,
+        address: u64,
+        size: c_int,
+        _value: i64,
+        user_data: *mut UcAfl,
+    ) {
+        let ucafl = &mut *user_data;
+        let snapshot = match ucafl.persistent.as_mut() {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+
+        #[allow(clippy::cast_sign_loss)]
+        let pages: Vec<u64> = snapshot.pages(address, size as u64).collect();
+        for page in pages {
+            let saved = snapshot.dirty(page, |data| {
+                ffi::uc_mem_read(uc, page, data.as_mut_ptr() as _, data.len()) == uc_error::OK
+            });
+            if !saved {
+                err_child!(ucafl, "Fail to save the page at {:#x}.\n", page);
+                return;
+            }
+        }
+    }
+
+    fn persistent_restore(&mut self) {
+        let snapshot = match self.persistent.as_mut() {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+
+        let mut failed = vec![];
+        for page in snapshot.dirty.drain(..) {
+            let (data, dirty) = snapshot.pages.get_mut(&page).expect("dirty page not saved");
+            *dirty = false;
+            let err =
+                unsafe { ffi::uc_mem_write(self.uc, page, data.as_ptr() as _, data.len()) };
+            if err != uc_error::OK {
+                failed.push(page);
+            }
+        }
+        let context = snapshot.context;
+
+        for page in failed {
+            err_child!(self, "Fail to restore the page at {:#x}.\n", page);
+        }
+
+        if unsafe { ffi::uc_context_restore(self.uc, context) } != uc_error::OK {
+            err_child!(self, "Fail to restore the context for persistent mode.\n");
+            process::exit(1);
+        }
+    }
+
+    unsafe extern "C" fn uc_hook_new_tb(
+        _uc: uc_handle,
+        cur_tb: *mut ffi::uc_tb,
+        _prev_tb: *mut ffi::uc_tb,
+        user_data: *mut UcAfl,
+    ) {
+        let ucafl = &mut *user_data;
+        let pc = (*cur_tb).pc;
+
+        // Edge IDs are not handed out here but by the block hook, it also sees
+        // the indirect edges which are never chained and only the blocks in
+        // the instrumented ranges.
+        if !write_u32(ucafl.afl_child_pipe[1], AFL_CHILD_TSL_REQUEST)
+            || !write_u64(ucafl.afl_child_pipe[1], pc)
+        {
+            err_child!(ucafl, "Fail to request cache at {:#x}\n", pc);
+            return;
+        }
+
+        err_child!(ucafl, "A new TB is generated at {:#x}\n", pc);
+    }
+
+    fn afl_reset_prev_loc(&mut self) {
+        self.afl_prev_loc = 0;
+        self.afl_prev_pc = 0;
+        self.afl_ngram_prev_locs = [0; NGRAM_SIZE_MAX as usize - 1];
+    }
+
+    /// The N-gram of the last `cov_ngram_size - 1` blocks, like `AFL_LLVM_NGRAM_SIZE`.
+    fn afl_ngram_loc(&mut self, cur_loc: u64) -> u64 {
+        let n = self.options.cov_ngram_size as usize - 1;
+        let prev_locs = &mut self.afl_ngram_prev_locs[..n];
+        let prev_loc = prev_locs.iter().fold(0, |acc, loc| acc ^ loc);
+
+        prev_locs.rotate_right(1);
+        prev_locs[0] = cur_loc >> 1;
+
+        cur_loc ^ prev_loc
+    }
+
+    /// Unicorn has no notion of calls, so the calling context is approximated
+    /// by the stack pointer.
+    fn afl_ctx_loc(&mut self, cur_loc: u64) -> u64 {
+        let mut sp: u64 = 0;
+        unsafe { ffi::uc_reg_read(self.uc, self.sp_reg, &mut sp as *mut u64 as _) };
+
+        cur_loc ^ self.afl_prev_loc ^ (((sp >> 2) ^ (sp << 6)) & self.afl_map_mask)
+    }
+
+    fn afl_edge_loc(&mut self, address: u64, cur_loc: u64) -> u64 {
+        let prev_pc = mem::replace(&mut self.afl_prev_pc, address);
+        let (id, is_new) = self.afl_edge_ids.get(prev_pc, address, self.map_size);
+
+        if is_new
+            && !(write_u32(self.afl_child_pipe[1], AFL_CHILD_EDGE_ID)
+                && write_u64(self.afl_child_pipe[1], prev_pc)
+                && write_u64(self.afl_child_pipe[1], address))
+        {
+            err_child!(self, "Fail to report the ID of the edge to {:#x}\n", address);
+        }
+
+        id.map_or(cur_loc ^ self.afl_prev_loc, u64::from)
+    }
+
+    unsafe extern "C" fn uc_hook_block(
+        _uc: uc_handle,
+        address: u64,
+        _size: u32,
+        user_data: *mut UcAfl,
+    ) {
+        let ucafl = &mut *user_data;
+        let cur_loc = afl_cur_loc(address, ucafl.afl_map_mask);
+
+        let idx = match ucafl.options.cov_mode {
+            uc_afl_cov_mode::UC_AFL_COV_BLOCK => {
+                ucafl.afl_area_hit(cur_loc);
+                return;
+            }
+            uc_afl_cov_mode::UC_AFL_COV_NGRAM => ucafl.afl_ngram_loc(cur_loc),
+            uc_afl_cov_mode::UC_AFL_COV_CTX => ucafl.afl_ctx_loc(cur_loc),
+            uc_afl_cov_mode::UC_AFL_COV_EDGE_ID => ucafl.afl_edge_loc(address, cur_loc),
+            uc_afl_cov_mode::UC_AFL_COV_EDGE => cur_loc ^ ucafl.afl_prev_loc,
+        };
+
+        ucafl.afl_area_hit(idx);
+        ucafl.afl_prev_loc = cur_loc >> 1;
+
+        err_child!(
+            ucafl,
+            "uc_hook_block address={:#x} cur_loc={} prev_loc={}\n",
+            address,
+            cur_loc,
+            ucafl.afl_prev_loc
+        );
+    }
+
+    #[inline]
+    fn afl_area_inc(&mut self, loc: u64) {
+        self.afl_area_hit(loc ^ self.afl_prev_loc);
+    }
+
+    #[inline]
+    fn afl_area_hit(&mut self, idx: u64) {
+        #[allow(clippy::cast_possible_truncation)]
+        let idx = idx as usize;
+        unsafe {
+            let entry = self.afl_area_ptr.add(idx);
+            *entry = (*entry).wrapping_add(1);
+        }
+    }
+
+    /// Split-compare coverage: one map entry per matching byte, from the most
+    /// significant byte downwards.
+    fn uc_hook_sub_impl(&mut self, cur_loc: u64, arg1: u64, arg2: u64, size: u32) {
+        let bytes = if size >= 64 {
+            8
+        } else if size >= 32 {
+            4
+        } else {
+            2
+        };
+
+        for i in (1..bytes).rev() {
+            let mask = 0xffu64 << (i * 8);
+            if arg1 & mask != arg2 & mask {
+                break;
+            }
+            self.afl_area_inc(cur_loc + i - 1);
+        }
+    }
+
+    unsafe extern "C" fn uc_hook_sub_cmp(
+        uc: uc_handle,
+        address: u64,
+        arg1: u64,
+        arg2: u64,
+        size: u32,
+        user_data: *mut UcAfl,
+    ) {
+        let ucafl = &mut *user_data;
+
+        // Only compares are interesting for CmpLog, not every subtraction.
+        if !ucafl.afl_cmp_map.is_null() {
+            ucafl.cmplog_ins(address, arg1, arg2, size);
+        }
+
+        Self::uc_hook_sub(uc, address, arg1, arg2, size, user_data);
+    }
+
+    unsafe extern "C" fn uc_hook_sub(
+        _uc: uc_handle,
+        address: u64,
+        arg1: u64,
+        arg2: u64,
+        size: u32,
+        user_data: *mut UcAfl,
+    ) {
+        let ucafl = &mut *user_data;
+        let cur_loc = afl_cur_loc(address, ucafl.afl_map_mask);
+
+        if cur_loc >= ucafl.afl_inst_rms {
+            return;
+        }
+
+        ucafl.uc_hook_sub_impl(cur_loc, arg1, arg2, size);
+    }
+
+    fn cmplog_ins(&mut self, address: u64, arg1: u64, arg2: u64, size: u32) {
+        if size < 8 {
+            return;
+        }
+
+        let k = cmplog_key(address);
+        let cmp_map = unsafe { &mut *self.afl_cmp_map };
+        let hdr = &mut cmp_map.headers[k];
+
+        if hdr.cmp_type() != CMP_TYPE_INS {
+            hdr.set_cmp_type(CMP_TYPE_INS);
+            hdr.set_hits(0);
+        }
+
+        let hits = hdr.hits();
+        hdr.set_hits(hits + 1);
+        hdr.set_shape((size / 8 - 1) as u16);
+
+        let operands = &mut cmp_map.log[k][hits as usize & (CMP_MAP_H - 1)];
+        operands.v0 = arg1;
+        operands.v1 = arg2;
+    }
+
+    fn cmplog_rtn(&mut self, address: u64, v0: &[u8], v1: &[u8]) {
+        let k = cmplog_key(address);
+        let len = v0.len() as u16;
+        let cmp_map = unsafe { &mut *self.afl_cmp_map };
+        let hdr = &mut cmp_map.headers[k];
+
+        let hits = if hdr.cmp_type() != CMP_TYPE_RTN {
+            hdr.set_cmp_type(CMP_TYPE_RTN);
+            hdr.set_hits(1);
+            hdr.set_shape(len - 1);
+            0
+        } else {
+            let hits = hdr.hits();
+            hdr.set_hits(hits + 1);
+            if hdr.shape() < len - 1 {
+                hdr.set_shape(len - 1);
+            }
+            hits
+        };
+
+        let cmpfn = unsafe {
+            &mut *(cmp_map.log[k].as_mut_ptr() as *mut [CmpFnOperands; CMP_MAP_RTN_H])
+        };
+        let operands = &mut cmpfn[hits as usize & (CMP_MAP_RTN_H - 1)];
+        operands.v0_len = len as u8;
+        operands.v1_len = len as u8;
+        operands.v0[..v0.len()].copy_from_slice(v0);
+        operands.v1[..v1.len()].copy_from_slice(v1);
+    }
+
+    /// Reads as much of `buf.len()` bytes at `address` as is mapped.
+    fn cmplog_mem_read(&self, address: u64, buf: &mut [u8]) -> usize {
+        let len = buf.len();
+        if unsafe { ffi::uc_mem_read(self.uc, address, buf.as_mut_ptr(), len) } == uc_error::OK {
+            return len;
+        }
+
+        (0..len)
+            .take_while(|&i| unsafe {
+                ffi::uc_mem_read(self.uc, address + i as u64, buf[i..].as_mut_ptr(), 1)
+                    == uc_error::OK
+            })
+            .count()
+    }
+
+    unsafe extern "C" fn uc_hook_cmplog_rtn(
+        uc: uc_handle,
+        address: u64,
+        _size: u32,
+        user_data: *mut UcAfl,
+    ) {
+        let ucafl = &mut *user_data;
+        let rtn = match ucafl.options.cmplog_rtns.iter().find(|rtn| rtn.address == address) {
+            Some(rtn) => *rtn,
+            None => return,
+        };
+        let kind = rtn.kind;
+
+        let mut args = [0u64; 3];
+        if !cmplog_rtn_args(uc, &rtn, &mut args) {
+            err_child!(
+                ucafl,
+                "Can't get the arguments of the routine at {:#x}\n",
+                address
+            );
+            return;
+        }
+
+        let mut len = CMPLOG_RTN_LEN;
+        if kind != CmpLogRoutineKind::Strcmp && args[2] < len as u64 {
+            len = args[2] as usize;
+        }
+
+        let mut v0 = [0u8; CMPLOG_RTN_LEN];
+        let mut v1 = [0u8; CMPLOG_RTN_LEN];
+        let l0 = ucafl.cmplog_mem_read(args[0], &mut v0[..len]);
+        let l1 = ucafl.cmplog_mem_read(args[1], &mut v1[..len]);
+
+        if l0 == 0 || l1 == 0 {
+            return;
+        }
+
+        if kind != CmpLogRoutineKind::Memcmp {
+            // Up to and including the terminator of the longer string.
+            len = cstr_len(&v0[..l0], len).max(cstr_len(&v1[..l1], len));
+        }
+
+        ucafl.cmplog_rtn(address, &v0[..len], &v1[..len]);
+    }
+
+    fn uc_setup(&mut self) {
+        let ver = unsafe { ffi::uc_version(ptr::null_mut(), ptr::null_mut()) };
+
+        // We need at least Unicorn 2.0.0rc6
+        if ver < MIN_UC_VERSION {
+            err!(self, "You Unicorn Version {:#x} is not supported!\n", ver);
+            process::exit(1);
+        }
+
+        if self.options.cov_mode == uc_afl_cov_mode::UC_AFL_COV_CTX {
+            self.sp_reg = unsafe { uc_sp_reg(self.uc) };
+            if self.sp_reg == 0 {
+                err!(self, "Context sensitive coverage is not supported for this arch.\n");
+                process::exit(1);
+            }
+        }
+
+        if let Ok(ranges) = env::var("AFL_UNICORN_INST_RANGES") {
+            match afl_parse_ranges(&ranges) {
+                Some(ranges) => self.options.cov_ranges.extend(ranges),
+                None => {
+                    err!(self, "Invalid AFL_UNICORN_INST_RANGES.\n");
+                    process::exit(1);
+                }
+            }
+        }
+
+        if let Ok(ranges) = env::var("AFL_UNICORN_EXCLUDE_RANGES") {
+            match afl_parse_ranges(&ranges) {
+                Some(ranges) => self.options.cov_exclude_ranges.extend(ranges),
+                None => {
+                    err!(self, "Invalid AFL_UNICORN_EXCLUDE_RANGES.\n");
+                    process::exit(1);
+                }
+            }
+        }
+
+        let user_data = self as *mut UcAfl as *mut c_void;
+
+        // For TB caching.
+        if self.hook_add(
+            HookType::EDGE_GENERATED,
+            Self::uc_hook_new_tb as _,
+            user_data,
+            None,
+            1,
+            0,
+        ) != uc_error::OK
+        {
+            err!(self, "Failed to setup new edge hook.\n");
+            process::exit(1);
+        }
+
+        let ranges = afl_inst_ranges(&self.options.cov_ranges, &self.options.cov_exclude_ranges);
+        if ranges.is_empty() {
+            err!(self, "All code is excluded from instrumentation.\n");
+        }
+
+        // Unicorn does the bound checks, so the hooks themselves never see an
+        // address outside of the ranges.
+        for range in ranges {
+            self.uc_setup_cov_hooks(user_data, range.begin, range.end);
+        }
+
+        if self.afl_cmp_map.is_null() {
+            return;
+        }
+
+        for i in 0..self.options.cmplog_rtns.len() {
+            let address = self.options.cmplog_rtns[i].address;
+            let mut hook: uc_hook = ptr::null_mut();
+            let err = unsafe {
+                ffi::uc_hook_add(
+                    self.uc,
+                    &mut hook,
+                    HookType::CODE,
+                    Self::uc_hook_cmplog_rtn as _,
+                    user_data,
+                    address,
+                    address,
+                )
+            };
+            if err != uc_error::OK {
+                err!(
+                    self,
+                    "Failed to setup CmpLog hook for the routine at {:#x}.\n",
+                    address
+                );
+                process::exit(1);
+            }
+            self.hooks.push(hook);
+        }
+    }
+
+    fn uc_setup_cov_hooks(&mut self, user_data: *mut c_void, begin: u64, end: u64) {
+        // For coverage.
+        if self.hook_add(
+            HookType::BLOCK,
+            Self::uc_hook_block as _,
+            user_data,
+            None,
+            begin,
+            end,
+        ) != uc_error::OK
+        {
+            err!(self, "Failed to setup block hook.\n");
+            process::exit(1);
+        }
+
+        // These two hooks are for compcov and may not be supported by the arch.
+        if self.hook_add(
+            HookType::TCG_OPCODE,
+            Self::uc_hook_sub as _,
+            user_data,
+            Some(UC_TCG_OP_FLAG_DIRECT),
+            begin,
+            end,
+        ) != uc_error::OK
+        {
+            err!(self, "Failed to setup UC_TCG_OP_SUB direct hook.\n");
+            process::exit(1);
+        }
+
+        if self.hook_add(
+            HookType::TCG_OPCODE,
+            Self::uc_hook_sub_cmp as _,
+            user_data,
+            Some(UC_TCG_OP_FLAG_CMP),
+            begin,
+            end,
+        ) != uc_error::OK
+        {
+            err!(self, "Failed to setup UC_TCG_OP_SUB cmp hook.\n");
+            process::exit(1);
+        }
+    }
+
+    /// Adds a hook from `begin` to `end`, or over the whole address space if
+    /// `begin > end`. `sub_flag` selects a `UC_TCG_OP_SUB` opcode hook.
+    fn hook_add(
+        &mut self,
+        hook_type: HookType,
+        callback: *mut c_void,
+        user_data: *mut c_void,
+        sub_flag: Option<c_int>,
+        begin: u64,
+        end: u64,
+    ) -> uc_error {
+        let mut hook: uc_hook = ptr::null_mut();
+        let err = unsafe {
+            match sub_flag {
+                Some(flag) => ffi::uc_hook_add(
+                    self.uc,
+                    &mut hook,
+                    hook_type,
+                    callback,
+                    user_data,
+                    begin,
+                    end,
+                    UC_TCG_OP_SUB,
+                    flag,
+                ),
+                None => {
+                    ffi::uc_hook_add(self.uc, &mut hook, hook_type, callback, user_data, begin, end)
+                }
+            }
+        };
+        if err == uc_error::OK {
+            self.hooks.push(hook);
+        }
+        err
+    }
+
+    fn afl_setup(&mut self) {
+        self.wifsignaled = valid_wifsignaled();
+
+        let env_map_size = env::var("AFL_MAP_SIZE").ok();
+        self.map_size = match afl_map_size(self.options.map_size, env_map_size.as_deref()) {
+            Some(map_size) => map_size,
+            None => {
+                err!(
+                    self,
+                    "Invalid map size, it has to be between {} and {}.\n",
+                    MAP_SIZE_MIN,
+                    MAP_SIZE_MAX
+                );
+                send_forkserver_error(FS_ERROR_MAP_SIZE);
+                process::exit(1);
+            }
+        };
+        self.afl_map_mask = afl_map_mask(self.map_size);
+
+        self.afl_inst_rms = match env::var("AFL_INST_RATIO") {
+            Ok(inst_r) => {
+                let r = inst_r.parse::<u64>().unwrap_or(0).clamp(1, 100);
+                u64::from(self.map_size) * r / 100
+            }
+            Err(_) => u64::from(self.map_size),
+        };
+
+        if let Ok(map_id) = env::var(SHM_ENV_VAR) {
+            let map_id = map_id.parse::<c_int>().unwrap_or(-1);
+
+            // afl-fuzz sizes the map from the same AFL_MAP_SIZE, unless the
+            // harness asked for something else.
+            let mut map_ds: libc::shmid_ds = unsafe { mem::zeroed() };
+            if unsafe { libc::shmctl(map_id, libc::IPC_STAT, &mut map_ds) } == 0
+                && map_ds.shm_segsz < self.map_size as usize
+            {
+                err!(
+                    self,
+                    "The afl mapping area has {} bytes but the map size is {}, set AFL_MAP_SIZE={} for afl-fuzz.\n",
+                    map_ds.shm_segsz,
+                    self.map_size,
+                    self.map_size
+                );
+                send_forkserver_error(FS_ERROR_MAP_SIZE);
+                process::exit(1);
+            }
+
+            let area = unsafe { libc::shmat(map_id, ptr::null(), 0) };
+
+            if area as isize == -1 {
+                err!(self, "Can't get the afl mapping area.\n");
+                send_forkserver_error(FS_ERROR_SHMAT);
+                process::exit(1);
+            }
+
+            self.afl_area_ptr = area as *mut u8;
+            self.has_afl = true;
+
+            // Don't let our parent give up on us.
+            unsafe { *self.afl_area_ptr = 1 };
+
+            // afl-fuzz only sets this for the CmpLog instance of the target.
+            if let Ok(cmplog_id) = env::var(CMPLOG_SHM_ENV_VAR) {
+                let cmplog_id = cmplog_id.parse::<c_int>().unwrap_or(-1);
+                let cmp_map = unsafe { libc::shmat(cmplog_id, ptr::null(), 0) };
+
+                if cmp_map as isize == -1 {
+                    err!(self, "Can't get the afl CmpLog area.\n");
+                    send_forkserver_error(FS_ERROR_SHMAT);
+                    process::exit(1);
+                }
+
+                self.afl_cmp_map = cmp_map as *mut CmpMap;
+            }
+        } else {
+            self.afl_local_area = vec![0; self.map_size as usize];
+            self.afl_area_ptr = self.afl_local_area.as_mut_ptr();
+            self.has_afl = false;
+        }
+    }
+
+    fn fksrv_start(&mut self) -> AflRet {
+        if !self.has_afl {
+            return AflRet::NoAfl;
+        }
+
+        let ret = self.fksrv_handshake();
+        if ret != AflRet::Ok {
+            return ret;
+        }
+
+        self.fksrv_loop()
+    }
+
+    fn fksrv_handshake(&mut self) -> AflRet {
+        if env::var_os("AFL_OLD_FORKSERVER").is_some() {
+            self.fksrv_handshake_old()
+        } else {
+            self.fksrv_handshake_new()
+        }
+    }
+
+    /// The versioned handshake of AFL++ >= 4.20.
+    fn fksrv_handshake_new(&mut self) -> AflRet {
+        // Phone home and tell the parent that we're OK. If parent isn't there,
+        // assume we're not running in forkserver mode and just execute program.
+        if !write_u32(FORKSRV_FD + 1, FS_NEW_VERSION_MSG) {
+            return AflRet::NoAfl;
+        }
+
+        match read_u32(FORKSRV_FD) {
+            None => {
+                err!(self, "AFL parent exited before forkserver was up\n");
+                return AflRet::Error;
+            }
+            Some(status) if status != FS_NEW_VERSION_MSG ^ 0xffff_ffff => {
+                err!(
+                    self,
+                    "Unexpected response from AFL++ on forkserver setup, is afl-fuzz older than 4.20? Try AFL_OLD_FORKSERVER=1.\n"
+                );
+                return AflRet::Error;
+            }
+            Some(_) => {}
+        }
+
+        let mut status = FS_NEW_OPT_MAPSIZE;
+        if !self.afl_testcase_ptr.is_null() {
+            status |= FS_NEW_OPT_SHDMEM_FUZZ;
+        }
+        if !self.options.autodict.is_empty() {
+            status |= FS_NEW_OPT_AUTODICT;
+        }
+
+        // The option values follow in the order of the option bits.
+        if !write_u32(FORKSRV_FD + 1, status) || !write_u32(FORKSRV_FD + 1, self.map_size) {
+            err!(self, "Fail to send forkserver options.\n");
+            return AflRet::Error;
+        }
+
+        if !self.options.autodict.is_empty() && !self.send_autodict() {
+            err!(self, "Fail to send the autodictionary.\n");
+            return AflRet::Error;
+        }
+
+        // Welcome message, the handshake is done.
+        if !write_u32(FORKSRV_FD + 1, FS_NEW_VERSION_MSG) {
+            err!(self, "Fail to finish the forkserver handshake.\n");
+            return AflRet::Error;
+        }
+
+        AflRet::Ok
+    }
+
+    fn fksrv_handshake_old(&mut self) -> AflRet {
+        let mut status = FS_OPT_ENABLED;
+
+        if self.map_size <= FS_OPT_MAX_MAPSIZE {
+            status |= FS_OPT_MAPSIZE | fs_opt_set_mapsize(self.map_size);
+        }
+
+        if !self.afl_testcase_ptr.is_null() {
+            // Parent supports testcases via shared map - and the user wants to
+            // use it. Tell AFL.
+            status |= FS_OPT_SHDMEM_FUZZ;
+        }
+
+        if !self.options.autodict.is_empty() {
+            status |= FS_OPT_AUTODICT;
+        }
+
+        // Phone home and tell the parent that we're OK. If parent isn't there,
+        // assume we're not running in forkserver mode and just execute program.
+        if !write_u32(FORKSRV_FD + 1, status) {
+            return AflRet::NoAfl;
+        }
+
+        if status & (FS_OPT_SHDMEM_FUZZ | FS_OPT_AUTODICT) == 0 {
+            err!(
+                self,
+                "AFL++ sharedmap fuzzing not supported/SHM_FUZZ_ENV_VAR not set\n"
+            );
+            return AflRet::Ok;
+        }
+
+        // afl tells us in an extra message if it accepted these options or not
+        let status = match read_u32(FORKSRV_FD) {
+            Some(status) => status,
+            None => {
+                err!(self, "AFL parent exited before forkserver was up\n");
+                return AflRet::Error;
+            }
+        };
+
+        if !self.afl_testcase_ptr.is_null()
+            && status & (FS_OPT_ENABLED | FS_OPT_SHDMEM_FUZZ) != FS_OPT_ENABLED | FS_OPT_SHDMEM_FUZZ
+        {
+            err!(self, "Unexpected response from AFL++ on forkserver setup\n");
+            return AflRet::Error;
+        }
+
+        if status & (FS_OPT_ENABLED | FS_OPT_AUTODICT) == FS_OPT_ENABLED | FS_OPT_AUTODICT
+            && !self.send_autodict()
+        {
+            err!(self, "Fail to send the autodictionary.\n");
+            return AflRet::Error;
+        }
+
+        AflRet::Ok
+    }
+
+    fn send_autodict(&self) -> bool {
+        write_u32(FORKSRV_FD + 1, self.options.autodict.len() as u32)
+            && write_all(FORKSRV_FD + 1, &self.options.autodict)
+    }
+
+    /// Only returns in the child (`AflRet::Child`) or once AFL is gone.
+    fn fksrv_loop(&mut self) -> AflRet {
+        let mut child_pid: libc::pid_t = 0;
+        let mut child_ret = AFL_CHILD_EXITED;
+
+        let old_sigchld_handler = unsafe { libc::signal(libc::SIGCHLD, libc::SIG_DFL) };
+
+        loop {
+            // Wait for parent by reading from the pipe. Abort if read fails.
+            let was_killed = match read_u32(FORKSRV_FD) {
+                Some(was_killed) => was_killed,
+                None => return AflRet::Finished,
+            };
+
+            // If we stopped the child in persistent mode, but there was a race
+            // condition and afl-fuzz already issued SIGKILL, write off the old
+            // process.
+            if child_ret != AFL_CHILD_EXITED && was_killed != 0 {
+                err!(self, "Child was killed by AFL in the meantime.\n");
+
+                child_ret = AFL_CHILD_EXITED;
+                let mut status: c_int = 0;
+                if unsafe { libc::waitpid(child_pid, &mut status, 0) } < 0 {
+                    err!(self, "Error waiting for child!");
+                    return AflRet::Error;
+                }
+            }
+
+            if child_ret == AFL_CHILD_EXITED {
+                // Child dead. Establish new a channel with child to grab
+                // translation commands. We'll read from afl_child_pipe[0],
+                // child will write to afl_child_pipe[1].
+
+                // close the read fd of previous round.
+                if self.afl_child_pipe[0] != 0 {
+                    unsafe {
+                        libc::close(self.afl_child_pipe[0]);
+                        libc::close(self.afl_parent_pipe[1]);
+                    }
+                }
+
+                if unsafe { libc::pipe(self.afl_child_pipe.as_mut_ptr()) } != 0 {
+                    eprintln!("[!] Error creating pipe to child");
+                    return AflRet::Error;
+                }
+                if unsafe { libc::pipe(self.afl_parent_pipe.as_mut_ptr()) } != 0 {
+                    eprintln!("[!] Error creating pipe to parent");
+                    unsafe {
+                        libc::close(self.afl_child_pipe[0]);
+                        libc::close(self.afl_child_pipe[1]);
+                    }
+                    return AflRet::Error;
+                }
+
+                // Create a clone of our process.
+                child_pid = unsafe { libc::fork() };
+                if child_pid < 0 {
+                    eprintln!("[!] Could not fork!");
+                    return AflRet::Error;
+                }
+
+                if child_pid == 0 {
+                    // In child process: close fds, resume execution.
+                    unsafe {
+                        libc::signal(libc::SIGCHLD, old_sigchld_handler);
+                        // FORKSRV_FD is for communication with AFL, we don't
+                        // need it in the child.
+                        libc::close(FORKSRV_FD);
+                        libc::close(FORKSRV_FD + 1);
+                        libc::close(self.afl_child_pipe[0]);
+                        libc::close(self.afl_parent_pipe[1]);
+
+                        ptr::write_bytes(self.afl_area_ptr, 0, self.map_size as usize);
+                    }
+                    // Make very sure everything has been written to the map at
+                    // this point
+                    std::sync::atomic::compiler_fence(Ordering::SeqCst);
+
+                    self.afl_reset_prev_loc();
+                    // Tell AFL we're alive
+                    unsafe { *self.afl_area_ptr = 1 };
+
+                    return AflRet::Child;
+                }
+
+                // If we don't close this in parent, we don't get notified on
+                // afl_child_pipe once child is gone.
+                unsafe {
+                    libc::close(self.afl_child_pipe[1]);
+                    libc::close(self.afl_parent_pipe[0]);
+                }
+            } else {
+                // Special handling for persistent mode: if the child is alive
+                // but currently stopped, simply restart it with a write to
+                // afl_parent_pipe.
+                if !write_u32(self.afl_parent_pipe[1], 0) {
+                    err!(self, "Child died when we tried to resume it\n");
+                    return AflRet::Error;
+                }
+            }
+
+            // In parent process: write PID to AFL.
+            #[allow(clippy::cast_sign_loss)]
+            if !write_u32(FORKSRV_FD + 1, child_pid as u32) {
+                return AflRet::Finished;
+            }
+
+            // Collect translation requests until child finishes a run or dies
+            child_ret = self.handle_child_requests();
+
+            let status: c_int = match child_ret {
+                // Child asks for next in persistent mode. This status tells AFL
+                // we are not crashed.
+                AFL_CHILD_NEXT => 0,
+                // WIFSIGNALED(wifsignaled) == 1 -> tells AFL the child crashed
+                // (even though it's still alive for persistent mode)
+                AFL_CHILD_FOUND_CRASH => self.wifsignaled,
+                _ => {
+                    // If child exited, get and relay exit status to parent
+                    // through waitpid.
+                    let mut status: c_int = 0;
+                    if unsafe { libc::waitpid(child_pid, &mut status, 0) } < 0 {
+                        // Zombie Child could not be collected. Scary!
+                        eprintln!("[!] The child's exit code could not be determined.");
+                        return AflRet::Error;
+                    }
+                    status
+                }
+            };
+
+            // Relay wait status to AFL pipe, then loop back.
+            #[allow(clippy::cast_sign_loss)]
+            if !write_u32(FORKSRV_FD + 1, status as u32) {
+                return AflRet::Finished;
+            }
+        }
+    }
+
+    fn handle_child_requests(&mut self) -> u32 {
+        loop {
+            // Broken pipe means it's time to return to the fork server routine.
+            let child_msg = match read_u32(self.afl_child_pipe[0]) {
+                Some(child_msg) => child_msg,
+                // child is dead.
+                None => return AFL_CHILD_EXITED,
+            };
+
+            err!(self, "Get a child_msg={}\n", child_msg);
+
+            match child_msg {
+                // Forward if child found a crash or not, for persistent mode.
+                AFL_CHILD_NEXT | AFL_CHILD_FOUND_CRASH => return child_msg,
+                AFL_CHILD_TSL_REQUEST => {
+                    let pc = match read_u64(self.afl_child_pipe[0]) {
+                        Some(pc) => pc,
+                        None => {
+                            err!(self, "Fail to read child tsl request.\n");
+                            // child is dead.
+                            return AFL_CHILD_EXITED;
+                        }
+                    };
+
+                    let err = unsafe {
+                        ffi::uc_ctl(
+                            self.uc,
+                            uc_ctl_request(
+                                UC_CTL_TB_REQUEST_CACHE,
+                                2,
+                                UC_CTL_IO_READ | UC_CTL_IO_WRITE,
+                            ),
+                            pc,
+                            ptr::null_mut::<ffi::uc_tb>(),
+                        )
+                    };
+
+                    if err != uc_error::OK {
+                        err!(self, "Fail to cache the TB at {:#x}.\n", pc);
+                    } else {
+                        err!(self, "TB is cached at {:#x}.\n", pc);
+                    }
+                }
+                AFL_CHILD_EDGE_ID => {
+                    let (prev_pc, cur_pc) = match (
+                        read_u64(self.afl_child_pipe[0]),
+                        read_u64(self.afl_child_pipe[0]),
+                    ) {
+                        (Some(prev_pc), Some(cur_pc)) => (prev_pc, cur_pc),
+                        _ => {
+                            err!(self, "Fail to read child edge ID.\n");
+                            // child is dead.
+                            return AFL_CHILD_EXITED;
+                        }
+                    };
+
+                    // Same order as in the child, so we end up with the same ID.
+                    self.afl_edge_ids.get(prev_pc, cur_pc, self.map_size);
+                }
+                _ => {
+                    err!(
+                        self,
+                        "Unexpected response by child! {}. Please report this as bug for unicornafl.\n    \
+                         Expected one of {{AFL_CHILD_NEXT: {}, AFL_CHILD_FOUND_CRASH: {}, AFL_CHILD_TSL_REQUEST: {}, \
+                         AFL_CHILD_EDGE_ID: {}}}.\n",
+                        child_msg,
+                        AFL_CHILD_NEXT,
+                        AFL_CHILD_FOUND_CRASH,
+                        AFL_CHILD_TSL_REQUEST,
+                        AFL_CHILD_EDGE_ID
+                    );
+                }
+            }
+        }
+    }
+
+    fn may_use_shm_testcase(&mut self) {
+        if let Ok(id_str) = env::var(SHM_FUZZ_ENV_VAR) {
+            let shm_id = id_str.parse::<c_int>().unwrap_or(-1);
+            let map = unsafe { libc::shmat(shm_id, ptr::null(), 0) };
+            if map.is_null() || map == libc::MAP_FAILED {
+                eprintln!("[!] could not access fuzzing shared memory");
+                send_forkserver_error(FS_ERROR_SHMAT);
+                process::exit(1);
+            }
+
+            self.afl_testcase_len_p = map as *mut u32;
+            self.afl_testcase_ptr = unsafe { self.afl_testcase_len_p.add(1) } as *mut u8;
+            self.afl_use_shm_testcase = true;
+        } else {
+            self.afl_use_shm_testcase = false;
+            err!(self, "SHARED MEMORY FUZZING Feature is not enabled.\n");
+        }
+    }
+}
+
+impl Drop for UcAfl {
+    fn drop(&mut self) {
+        for hook in self.hooks.drain(..) {
+            unsafe { ffi::uc_hook_del(self.uc, hook) };
+        }
+
+        if let Some(snapshot) = self.persistent.take() {
+            unsafe { ffi::uc_context_free(snapshot.context) };
+        }
+    }
+}
+
+fn uc_strerror(err: uc_error) -> String {
+    unsafe { CStr::from_ptr(ffi::uc_strerror(err)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+unsafe fn uc_get_pc(uc: uc_handle) -> u64 {
+    let mut arch: libc::size_t = 0;
+    let mut mode: libc::size_t = 0;
+    let mut pc: u64 = 0;
+
+    ffi::uc_query(uc, Query::ARCH, &mut arch);
+    ffi::uc_query(uc, Query::MODE, &mut mode);
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let mode = Mode::from_bits_truncate(mode as i32);
+
+    let reg = match arch {
+        a if a == Arch::X86 as usize => {
+            if mode.contains(Mode::MODE_32) {
+                x86::Register::EIP as i32
+            } else if mode.contains(Mode::MODE_16) {
+                x86::Register::IP as i32
+            } else {
+                x86::Register::RIP as i32
+            }
+        }
+        a if a == Arch::ARM as usize => {
+            let mut cpsr: u64 = 0;
+            ffi::uc_reg_read(uc, arm::Register::PC as i32, &mut pc as *mut u64 as _);
+
+            // check for thumb mode
+            ffi::uc_reg_read(uc, arm::Register::CPSR as i32, &mut cpsr as *mut u64 as _);
+            if cpsr & 0x20 != 0 {
+                // thumb mode, the address should end with 1
+                pc |= 1;
+            }
+            return pc;
+        }
+        a if a == Arch::RISCV as usize => riscv::Register::PC as i32,
+        a if a == Arch::MIPS as usize => mips::Register::PC as i32,
+        a if a == Arch::PPC as usize => ppc::Register::PC as i32,
+        a if a == Arch::SPARC as usize => sparc::Register::PC as i32,
+        a if a == Arch::M68K as usize => m68k::Register::PC as i32,
+        a if a == Arch::S390X as usize => s390x::Register::PC as i32,
+        a if a == Arch::TRICORE as usize => tricore::Register::PC as i32,
+        a if a == Arch::ARM64 as usize => arm64::Register::PC as i32,
+        _ => return pc,
+    };
+
+    ffi::uc_reg_read(uc, reg, &mut pc as *mut u64 as _);
+    pc
+}
+
+unsafe fn uc_sp_reg(uc: uc_handle) -> i32 {
+    let mut arch: libc::size_t = 0;
+    let mut mode: libc::size_t = 0;
+
+    ffi::uc_query(uc, Query::ARCH, &mut arch);
+    ffi::uc_query(uc, Query::MODE, &mut mode);
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let mode = Mode::from_bits_truncate(mode as i32);
+
+    match arch {
+        a if a == Arch::X86 as usize => {
+            if mode.contains(Mode::MODE_32) {
+                x86::Register::ESP as i32
+            } else if mode.contains(Mode::MODE_16) {
+                x86::Register::SP as i32
+            } else {
+                x86::Register::RSP as i32
+            }
+        }
+        a if a == Arch::ARM as usize => arm::Register::SP as i32,
+        a if a == Arch::RISCV as usize => riscv::Register::SP as i32,
+        a if a == Arch::MIPS as usize => mips::Register::SP as i32,
+        a if a == Arch::PPC as usize => ppc::Register::GPR1 as i32,
+        a if a == Arch::SPARC as usize => sparc::Register::SP as i32,
+        a if a == Arch::M68K as usize => m68k::Register::A7 as i32,
+        a if a == Arch::S390X as usize => s390x::Register::R15 as i32,
+        a if a == Arch::TRICORE as usize => tricore::Register::SP as i32,
+        a if a == Arch::ARM64 as usize => arm64::Register::SP as i32,
+        _ => 0,
+    }
+}
+
+/// Reads the first integer arguments of a function we just entered, following
+/// the default calling convention of the arch. At most 4 are supported.
+unsafe fn uc_get_args(uc: uc_handle, args: &mut [u64]) -> bool {
+    let mut arch: libc::size_t = 0;
+    let mut mode: libc::size_t = 0;
+
+    if args.len() > 4 {
+        return false;
+    }
+
+    ffi::uc_query(uc, Query::ARCH, &mut arch);
+    ffi::uc_query(uc, Query::MODE, &mut mode);
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let mode = Mode::from_bits_truncate(mode as i32);
+
+    let (regs, sp_reg): ([i32; 4], i32) = match arch {
+        a if a == Arch::X86 as usize => {
+            if mode.contains(Mode::MODE_64) {
+                (
+                    [
+                        x86::Register::RDI as i32,
+                        x86::Register::RSI as i32,
+                        x86::Register::RDX as i32,
+                        x86::Register::RCX as i32,
+                    ],
+                    0,
+                )
+            } else if mode.contains(Mode::MODE_32) {
+                // cdecl, the arguments are behind the return address.
+                ([0; 4], x86::Register::ESP as i32)
+            } else {
+                return false;
+            }
+        }
+        a if a == Arch::ARM as usize => (
+            [
+                arm::Register::R0 as i32,
+                arm::Register::R1 as i32,
+                arm::Register::R2 as i32,
+                arm::Register::R3 as i32,
+            ],
+            0,
+        ),
+        a if a == Arch::ARM64 as usize => (
+            [
+                arm64::Register::X0 as i32,
+                arm64::Register::X1 as i32,
+                arm64::Register::X2 as i32,
+                arm64::Register::X3 as i32,
+            ],
+            0,
+        ),
+        a if a == Arch::MIPS as usize => (
+            [
+                mips::Register::A0 as i32,
+                mips::Register::A1 as i32,
+                mips::Register::A2 as i32,
+                mips::Register::A3 as i32,
+            ],
+            0,
+        ),
+        a if a == Arch::PPC as usize => (
+            [
+                ppc::Register::GPR3 as i32,
+                ppc::Register::GPR4 as i32,
+                ppc::Register::GPR5 as i32,
+                ppc::Register::GPR6 as i32,
+            ],
+            0,
+        ),
+        a if a == Arch::RISCV as usize => (
+            [
+                riscv::Register::A0 as i32,
+                riscv::Register::A1 as i32,
+                riscv::Register::A2 as i32,
+                riscv::Register::A3 as i32,
+            ],
+            0,
+        ),
+        a if a == Arch::SPARC as usize => (
+            [
+                sparc::Register::O0 as i32,
+                sparc::Register::O1 as i32,
+                sparc::Register::O2 as i32,
+                sparc::Register::O3 as i32,
+            ],
+            0,
+        ),
+        // Same as cdecl, but big endian.
+        a if a == Arch::M68K as usize => ([0; 4], m68k::Register::A7 as i32),
+        _ => return false,
+    };
+
+    let mut sp: u64 = 0;
+    if sp_reg != 0 {
+        ffi::uc_reg_read(uc, sp_reg, &mut sp as *mut u64 as _);
+    }
+
+    for (i, arg) in args.iter_mut().enumerate() {
+        *arg = 0;
+
+        if sp_reg == 0 {
+            if ffi::uc_reg_read(uc, regs[i], arg as *mut u64 as _) != uc_error::OK {
+                return false;
+            }
+        } else {
+            let mut bytes = [0u8; 4];
+            if ffi::uc_mem_read(uc, sp + 4 * (i as u64 + 1), bytes.as_mut_ptr(), 4)
+                != uc_error::OK
+            {
+                return false;
+            }
+            *arg = if arch == Arch::M68K as usize {
+                u64::from(u32::from_be_bytes(bytes))
+            } else {
+                u64::from(u32::from_le_bytes(bytes))
+            };
+        }
+    }
+
+    true
+}
+
+/// The compared pointers and the length, from the registers in the calling
+/// convention of `rtn` or the default calling convention of the arch.
+unsafe fn cmplog_rtn_args(uc: uc_handle, rtn: &CmpLogRoutine, args: &mut [u64; 3]) -> bool {
+    let regs = [rtn.cc.s1_reg, rtn.cc.s2_reg, rtn.cc.n_reg];
+    let count = if rtn.kind == CmpLogRoutineKind::Strcmp { 2 } else { 3 };
+
+    if regs[..count].contains(&0) && !uc_get_args(uc, &mut args[..count]) {
+        return false;
+    }
+
+    for (arg, &reg) in args.iter_mut().zip(regs[..count].iter()) {
+        if reg != 0 {
+            *arg = 0;
+            if ffi::uc_reg_read(uc, reg, arg as *mut u64 as _) != uc_error::OK {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// Length of a C string up to and including its terminator, at most `max`.
+fn cstr_len(buf: &[u8], max: usize) -> usize {
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    (len + 1).min(max)
+}
+
+unsafe extern "C" fn dummy_uc_afl_fuzz_callback(uc: uc_handle, _data: *mut c_void) -> uc_error {
+    let pc = uc_get_pc(uc);
+
+    // Note the multiple exits is enabled in this case.
+    ffi::uc_emu_start(uc, pc, 0, 0, 0)
+}
+
+unsafe fn input_file_path(input_file: *const c_char) -> Option<PathBuf> {
+    if input_file.is_null() {
+        return None;
+    }
+    let input_file = CStr::from_ptr(input_file).to_bytes();
+    if input_file.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(OsStr::from_bytes(input_file)))
+    }
+}
+
+/// Rust counterpart of `uc_afl_fuzz_ex` in `unicornafl.cpp`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) unsafe fn uc_afl_fuzz_ex(
+    uc: uc_handle,
+    input_file: *const c_char,
+    place_input_callback: *mut c_void,
+    exits: *const u64,
+    exit_count: libc::size_t,
+    fuzz_callback: *mut c_void,
+    validate_crash_callback: *mut c_void,
+    always_validate: bool,
+    persistent_iters: u32,
+    options: *const uc_afl_options,
+    data: *mut c_void,
+) -> AflRet {
+    let logger = Logger::new();
+    let input_file = input_file_path(input_file);
+    let place_input_callback: Option<PlaceInputCallback> = mem::transmute(place_input_callback);
+    let fuzz_callback: Option<FuzzCallback> = mem::transmute(fuzz_callback);
+    let validate_crash_callback: Option<ValidateCrashCallback> =
+        mem::transmute(validate_crash_callback);
+
+    logger.log(
+        false,
+        format_args!(
+            "Entering uc_afl_fuzz with input_file={:?} and persistent_iters={}\n",
+            input_file, persistent_iters
+        ),
+    );
+
+    // Sanity Check.
+    if uc.is_null() {
+        logger.log(false, format_args!("Unicorn Engine passed to uc_afl_fuzz is NULL!\n"));
+        return AflRet::Error;
+    }
+    if input_file.is_none() {
+        logger.log(false, format_args!("No input file provided to uc_afl_fuzz.\n"));
+        return AflRet::Error;
+    }
+    if place_input_callback.is_none() {
+        logger.log(false, format_args!("no place_input_callback set.\n"));
+        return AflRet::Error;
+    }
+    if always_validate && validate_crash_callback.is_none() {
+        logger.log(
+            false,
+            format_args!("always_validate set but validate_crash_callback is missing.\n"),
+        );
+        return AflRet::Error;
+    }
+    if fuzz_callback.is_none() && (exit_count == 0 || exits.is_null()) {
+        logger.log(false, format_args!("Nullptr provided for exits.\n"));
+        return AflRet::Error;
+    }
+
+    let options = match Options::from_c(options) {
+        Ok(options) => options,
+        Err(msg) => {
+            logger.log(false, format_args!("{}", msg));
+            return AflRet::Error;
+        }
+    };
+
+    let mut ucafl = UcAfl::new(
+        uc,
+        input_file,
+        place_input_callback,
+        validate_crash_callback,
+        Some(fuzz_callback.unwrap_or(dummy_uc_afl_fuzz_callback)),
+        always_validate,
+        persistent_iters,
+        options,
+        data,
+        logger,
+    );
+
+    if exit_count != 0
+        && ucafl.set_exits(std::slice::from_raw_parts(exits, exit_count)) != AflRet::Ok
+    {
+        return AflRet::Error;
+    }
+
+    ucafl.fsrv_run()
+}
+
+/// Rust counterpart of `uc_afl_forkserver_start` in `unicornafl.cpp`.
+pub(crate) unsafe fn uc_afl_forkserver_start(
+    uc: uc_handle,
+    exits: *const u64,
+    exit_count: libc::size_t,
+) -> AflRet {
+    let logger = Logger::new();
+
+    logger.log(
+        false,
+        format_args!(
+            "Entering uc_afl_forkserver_start with exit_count={}\n",
+            exit_count
+        ),
+    );
+
+    if uc.is_null() {
+        logger.log(
+            false,
+            format_args!("Unicorn Engine passed to uc_afl_forkserver_start is NULL!\n"),
+        );
+        return AflRet::Error;
+    }
+    if exit_count != 0 && exits.is_null() {
+        logger.log(false, format_args!("Nullptr provided for exits.\n"));
+        return AflRet::Error;
+    }
+    if !FORKSERVER_UCAFL.load(Ordering::SeqCst).is_null() {
+        logger.log(false, format_args!("Forkserver is already running.\n"));
+        return AflRet::CalledTwice;
+    }
+
+    let mut ucafl = UcAfl::new(
+        uc,
+        None,
+        None,
+        None,
+        None,
+        false,
+        1,
+        Options::default(),
+        ptr::null_mut(),
+        logger,
+    );
+
+    if exit_count != 0
+        && ucafl.set_exits(std::slice::from_raw_parts(exits, exit_count)) != AflRet::Ok
+    {
+        return AflRet::Error;
+    }
+
+    let ucafl = Box::into_raw(ucafl);
+    FORKSERVER_UCAFL.store(ucafl, Ordering::SeqCst);
+
+    let ret = (*ucafl).fsrv_start();
+    if ret != AflRet::Child && ret != AflRet::Finished {
+        // Nothing is running, dropping it removes our hooks from uc.
+        FORKSERVER_UCAFL.store(ptr::null_mut(), Ordering::SeqCst);
+        drop(Box::from_raw(ucafl));
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_ucafl(map: &mut [u8]) -> Box<UcAfl> {
+        let mut ucafl = UcAfl::new(
+            ptr::null_mut(),
+            None,
+            None,
+            None,
+            None,
+            false,
+            1,
+            Options::default(),
+            ptr::null_mut(),
+            Logger::new(),
+        );
+        ucafl.afl_area_ptr = map.as_mut_ptr();
+        ucafl
+    }
+
+    #[test]
+    fn wifsignaled_is_signaled() {
+        assert!(libc::WIFSIGNALED(valid_wifsignaled()));
+    }
+
+    #[test]
+    fn cur_loc_leaves_room_for_compcov() {
+        for map_size in [MAP_SIZE_MIN, 0x1_0040, MAP_SIZE_MAX] {
+            let map_mask = afl_map_mask(map_size);
+            for address in [0, 0x1000, 0x8000_1234, u64::MAX] {
+                assert!(afl_cur_loc(address, map_mask) + 6 < u64::from(map_size));
+            }
+        }
+    }
+
+    #[test]
+    fn map_size_precedence() {
+        assert_eq!(afl_map_size(0, None), Some(MAP_SIZE));
+        assert_eq!(afl_map_size(0, Some("262144")), Some(1 << 18));
+        assert_eq!(afl_map_size(1 << 20, Some("262144")), Some(1 << 20));
+    }
+
+    #[test]
+    fn map_size_is_validated() {
+        // Rounded up to 64 bytes like afl-fuzz does.
+        assert_eq!(afl_map_size(0, Some("100000")), Some(100_032));
+        assert_eq!(afl_map_size(0, Some("big")), None);
+        assert_eq!(afl_map_size(MAP_SIZE_MIN - 1, None), None);
+        assert_eq!(afl_map_size(MAP_SIZE_MAX + 1, None), None);
+    }
+
+    #[test]
+    fn map_size_is_parsed_like_strtoull() {
+        assert_eq!(afl_map_size(0, Some("0x20000")), Some(1 << 17));
+        assert_eq!(afl_map_size(0, Some("0400000")), Some(1 << 17));
+        assert_eq!(afl_map_size(0, Some("0x")), None);
+        assert_eq!(afl_map_size(0, Some(" 0x10000")), Some(1 << 16));
+        assert_eq!(afl_map_size(0, Some("0x10000 ")), Some(1 << 16));
+        assert_eq!(afl_map_size(0, Some("0x10000k")), Some(1 << 16));
+        assert_eq!(afl_map_size(0, Some("99999999999999999999")), None);
+    }
+
+    #[test]
+    fn numbers_are_parsed_like_strtoull() {
+        assert_eq!(strtoull(" 0x10"), Some((0x10, "")));
+        assert_eq!(strtoull("0x10 "), Some((0x10, " ")));
+        assert_eq!(strtoull("0x"), Some((0, "x")));
+        assert_eq!(strtoull("019"), Some((1, "9")));
+        assert_eq!(strtoull("-1"), Some((u64::MAX, "")));
+        assert_eq!(strtoull("0x1ffffffffffffffff-"), Some((u64::MAX, "-")));
+        assert_eq!(strtoull(" -"), None);
+        assert_eq!(strtoull(""), None);
+    }
+
+    #[test]
+    fn inst_ranges_are_parsed() {
+        let ranges = afl_parse_ranges("0x1000-0x1fff, 16384-0x4fff,").unwrap();
+        assert_eq!(
+            ranges,
+            vec![
+                AddressRange::from(0x1000..=0x1fff),
+                AddressRange::from(0x4000..=0x4fff)
+            ]
+        );
+        assert_eq!(afl_parse_ranges(""), Some(vec![]));
+        assert_eq!(afl_parse_ranges("0x1000"), None);
+        assert_eq!(afl_parse_ranges("0x2000-0x1000"), None);
+    }
+
+    #[test]
+    fn inst_ranges_are_parsed_like_the_cpp_runtime() {
+        assert_eq!(
+            afl_parse_ranges("0x1-0x2,"),
+            Some(vec![AddressRange::from(1..=2)])
+        );
+        assert_eq!(afl_parse_ranges("0x1-0x2,,0x3-0x4"), None);
+        assert_eq!(afl_parse_ranges("0x1-0x2, "), None);
+        assert_eq!(afl_parse_ranges("0x1 -0x2"), None);
+        assert_eq!(afl_parse_ranges("0x1-0x2 "), None);
+        assert_eq!(afl_parse_ranges(","), None);
+    }
+
+    #[test]
+    fn inst_ranges_exclude_holes() {
+        let include = [AddressRange::from(0x1000..=0x1fff)];
+        let exclude = [
+            AddressRange::from(0x1100..=0x11ff),
+            AddressRange::from(0x1f00..=0x2fff),
+        ];
+        assert_eq!(
+            afl_inst_ranges(&include, &exclude),
+            vec![
+                AddressRange::from(0x1000..=0x10ff),
+                AddressRange::from(0x1200..=0x1eff)
+            ]
+        );
+
+        // Without includes, everything but the excluded ranges is instrumented.
+        assert_eq!(
+            afl_inst_ranges(&[], &[AddressRange::from(0..=0xfff)]),
+            vec![AddressRange::from(0x1000..=u64::MAX)]
+        );
+        assert_eq!(afl_inst_ranges(&include, &include), vec![]);
+    }
+
+    #[test]
+    fn inst_ranges_merge_overlapping_includes() {
+        let include = [
+            AddressRange::from(0x3000..=0x3fff),
+            AddressRange::from(0x1000..=0x1fff),
+            AddressRange::from(0x1800..=0x27ff),
+            AddressRange::from(0x1000..=0x1fff),
+            AddressRange::from(0x2800..=0x28ff),
+        ];
+        assert_eq!(
+            afl_inst_ranges(&include, &[]),
+            vec![
+                AddressRange::from(0x1000..=0x28ff),
+                AddressRange::from(0x3000..=0x3fff)
+            ]
+        );
+        assert_eq!(
+            afl_inst_ranges(&include, &[AddressRange::from(0x2000..=0x2fff)]),
+            vec![
+                AddressRange::from(0x1000..=0x1fff),
+                AddressRange::from(0x3000..=0x3fff)
+            ]
+        );
+    }
+
+    #[test]
+    fn edge_ids_are_sequential_and_stable() {
+        let mut edge_ids = EdgeIds::new();
+        assert_eq!(edge_ids.get(0, 0x1000, 4), (Some(1), true));
+        assert_eq!(edge_ids.get(0x1000, 0x1010, 4), (Some(2), true));
+        assert_eq!(edge_ids.get(0, 0x1000, 4), (Some(1), false));
+        // The reverse is another edge.
+        assert_eq!(edge_ids.get(0x1010, 0x1000, 4), (Some(3), true));
+        // The map is full.
+        assert_eq!(edge_ids.get(0x1010, 0x1020, 4), (None, false));
+        assert_eq!(edge_ids.get(0x1000, 0x1010, 4), (Some(2), false));
+    }
+
+    fn test_snapshot() -> PersistentSnapshot {
+        PersistentSnapshot {
+            context: ptr::null_mut(),
+            page_size: 0x1000,
+            pages: HashMap::new(),
+            dirty: vec![],
+        }
+    }
+
+    #[test]
+    fn persistent_write_pages() {
+        let snapshot = test_snapshot();
+        assert_eq!(snapshot.pages(0x1ff8, 8).collect::<Vec<_>>(), vec![0x1000]);
+        assert_eq!(
+            snapshot.pages(0x1ffc, 8).collect::<Vec<_>>(),
+            vec![0x1000, 0x2000]
+        );
+    }
+
+    #[test]
+    fn persistent_pages_are_saved_once() {
+        let mut snapshot = test_snapshot();
+        assert!(snapshot.dirty(0x1000, |data| {
+            data[0] = 0x41;
+            true
+        }));
+        // Already saved, dirty again after a restore.
+        snapshot.dirty = vec![];
+        snapshot.pages.get_mut(&0x1000).unwrap().1 = false;
+        assert!(snapshot.dirty(0x1000, |_| unreachable!()));
+        assert!(snapshot.dirty(0x1000, |_| unreachable!()));
+        assert_eq!(snapshot.dirty, vec![0x1000]);
+        assert_eq!(snapshot.pages[&0x1000].0[0], 0x41);
+
+        // Unmapped pages are not tracked.
+        assert!(!snapshot.dirty(0x2000, |_| false));
+        assert_eq!(snapshot.dirty, vec![0x1000]);
+    }
+
+    #[test]
+    fn sub_impl_counts_matching_bytes() {
+        let mut map = vec![0u8; MAP_SIZE as usize];
+        let mut ucafl = test_ucafl(&mut map);
+
+        // The two top bytes match, the third one differs.
+        ucafl.uc_hook_sub_impl(0x100, 0x1122_3344, 0x1122_0044, 32);
+        drop(ucafl);
+        assert_eq!(&map[0x100..0x103], &[0, 1, 1]);
+    }
+
+    #[test]
+    fn sub_impl_64_bit_full_match() {
+        let mut map = vec![0u8; MAP_SIZE as usize];
+        let mut ucafl = test_ucafl(&mut map);
+
+        ucafl.uc_hook_sub_impl(0x200, u64::MAX, u64::MAX, 64);
+        drop(ucafl);
+        assert_eq!(&map[0x200..0x208], &[1, 1, 1, 1, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn fs_opt_mapsize_round_trips() {
+        // afl-fuzz decodes it with `FS_OPT_GET_MAPSIZE`.
+        let status = FS_OPT_ENABLED | FS_OPT_MAPSIZE | fs_opt_set_mapsize(MAP_SIZE);
+        assert_eq!(((status & 0x00ff_fffe) >> 1) + 1, MAP_SIZE);
+        assert_eq!(fs_opt_set_mapsize(FS_OPT_MAX_MAPSIZE + 1), 0);
+    }
+
+    #[test]
+    fn fs_opt_error_keeps_the_marker_bits() {
+        let status = FS_OPT_ERROR | fs_opt_set_error(FS_ERROR_SHMAT);
+        assert_eq!(status & FS_OPT_ERROR, FS_OPT_ERROR);
+        // afl-fuzz decodes it with `FS_OPT_GET_ERROR`.
+        assert_eq!((status & 0x00ffff00) >> 8, FS_ERROR_SHMAT);
+    }
+
+    #[test]
+    fn cmp_map_matches_aflpp_layout() {
+        assert_eq!(mem::size_of::<CmpHeader>(), 2);
+        assert_eq!(mem::size_of::<CmpOperands>(), 72);
+        assert_eq!(mem::size_of::<CmpFnOperands>(), 72);
+        assert_eq!(
+            mem::size_of::<CmpMap>(),
+            CMP_MAP_W * 2 + CMP_MAP_W * CMP_MAP_H * 72
+        );
+    }
+
+    #[test]
+    fn cmp_header_bitfields() {
+        let mut hdr = CmpHeader(0);
+        hdr.set_hits(63);
+        hdr.set_shape(7);
+        hdr.set_cmp_type(CMP_TYPE_RTN);
+        assert_eq!(hdr.0, 63 | (7 << 6) | (1 << 11));
+
+        // hits wraps around like the 6 bit C bitfield.
+        hdr.set_hits(hdr.hits() + 1);
+        assert_eq!((hdr.hits(), hdr.shape(), hdr.cmp_type()), (0, 7, CMP_TYPE_RTN));
+    }
+
+    #[test]
+    fn cstr_len_includes_the_terminator() {
+        assert_eq!(cstr_len(b"abc\0def", 32), 4);
+        assert_eq!(cstr_len(b"abc", 32), 4);
+        assert_eq!(cstr_len(b"abcdef", 3), 3);
+    }
+
+    #[test]
+    fn ngram_loc_xors_the_previous_blocks() {
+        let mut map = vec![0u8; MAP_SIZE as usize];
+        let mut ucafl = test_ucafl(&mut map);
+        ucafl.options.cov_ngram_size = 3;
+
+        assert_eq!(ucafl.afl_ngram_loc(0x10), 0x10);
+        assert_eq!(ucafl.afl_ngram_loc(0x20), 0x20 ^ 0x8);
+        assert_eq!(ucafl.afl_ngram_loc(0x40), 0x40 ^ 0x10 ^ 0x8);
+        // The oldest block falls out of the 3-gram.
+        assert_eq!(ucafl.afl_ngram_loc(0x80), 0x80 ^ 0x20 ^ 0x10);
+
+        ucafl.afl_reset_prev_loc();
+        assert_eq!(ucafl.afl_ngram_loc(0x10), 0x10);
+    }
+
+    #[test]
+    fn ngram_2_is_edge_coverage() {
+        let mut map = vec![0u8; MAP_SIZE as usize];
+        let mut ucafl = test_ucafl(&mut map);
+        ucafl.options.cov_ngram_size = 2;
+
+        let mut prev_loc = 0;
+        for cur_loc in [0x10, 0x2a8, 0x10, 0xfff0] {
+            assert_eq!(ucafl.afl_ngram_loc(cur_loc), cur_loc ^ prev_loc);
+            prev_loc = cur_loc >> 1;
+        }
+    }
+}
@@ -0,0 +1,136 @@
+// S390X registers
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Register {
+    INVALID = 0,
+    R0,
+    R1,
+    R2,
+    R3,
+    R4,
+    R5,
+    R6,
+    R7,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+    F0,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
+    F25,
+    F26,
+    F27,
+    F28,
+    F29,
+    F30,
+    F31,
+    A0,
+    A1,
+    A2,
+    A3,
+    A4,
+    A5,
+    A6,
+    A7,
+    A8,
+    A9,
+    A10,
+    A11,
+    A12,
+    A13,
+    A14,
+    A15,
+    PC,
+    PSWM,
+    ENDING,
+}
+
+impl Register {
+    pub const SP: Register = Register::R15;
+}
+
+impl From<Register> for i32 {
+    fn from(r: Register) -> Self {
+        r as i32
+    }
+}
+
+// S390X CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel {
+    Z900 = 0,
+    Z900_2,
+    Z900_3,
+    Z800,
+    Z990,
+    Z990_2,
+    Z990_3,
+    Z890,
+    Z990_4,
+    Z890_2,
+    Z990_5,
+    Z890_3,
+    Z9EC,
+    Z9EC_2,
+    Z9BC,
+    Z9EC_3,
+    Z9BC_2,
+    Z10EC,
+    Z10EC_2,
+    Z10BC,
+    Z10EC_3,
+    Z10BC_2,
+    Z196,
+    Z196_2,
+    Z114,
+    ZEC12,
+    ZEC12_2,
+    ZBC12,
+    Z13,
+    Z13_2,
+    Z13S,
+    Z14,
+    Z14_2,
+    Z14ZR1,
+    GEN15A,
+    GEN15B,
+    QEMU,
+    MAX,
+}
+
+impl From<CpuModel> for i32 {
+    fn from(model: CpuModel) -> Self {
+        model as i32
+    }
+}
+
+pub use crate::regs::s390x::Regs;
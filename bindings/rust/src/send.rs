@@ -0,0 +1,454 @@
+//! An engine that can move between threads, e.g. one engine per core in a single
+//! fuzzing process.
+
+use std::ffi::c_void;
+use std::path::Path;
+
+use tinyvec::ArrayVec;
+
+use crate::consts::{uc_error, Arch, HookType, MemRegion, MemType, Mode, Permission, Query};
+use crate::consts::{TcgOpCode, TcgOpFlag};
+use crate::ctl::{Ctl, TranslationBlock};
+use crate::ffi;
+use crate::{
+    x86, Context, HookCtx, HookHandle, HookInfo, MemHookAction, RegisterFile, Snapshot,
+    SnapshotError, Unicorn, UnicornBuilder,
+};
+
+/// A `Unicorn` that is `Send`, so it can be moved to another thread.
+///
+/// A `Unicorn` shares its state through an `Rc` with its hook callbacks and hook
+/// handles, which is why it is not `Send`. This type never hands
+/// out the `Unicorn` or anything sharing that `Rc`, so all of it moves together:
+///
+/// * Hook and MMIO callbacks have to be `Send + 'static`, and only see the engine
+///   through a `HookCtx`.
+/// * Adding a hook returns the raw `uc_hook` instead of a `HookHandle`, the hook
+///   lives until `remove_hook` or the end of the engine.
+/// * The data is `Send` and everything else it returns, e.g. `Snapshot`s and
+///   `Context`s, is owned and not tied to the engine.
+///
+/// Otherwise the methods are the ones of `Unicorn`. `into_inner` gives up `Send`
+/// to get the whole `Unicorn` API back on the current thread.
+pub struct SendUnicorn<D: Send + 'static> {
+    uc: Unicorn<'static, D>,
+}
+
+unsafe impl<D: Send + 'static> Send for SendUnicorn<D> {}
+
+impl SendUnicorn<()> {
+    pub fn new(arch: Arch, mode: Mode) -> Result<SendUnicorn<()>, uc_error> {
+        Self::new_with_data(arch, mode, ())
+    }
+}
+
+impl<D: Send + 'static> SendUnicorn<D> {
+    pub fn new_with_data(arch: Arch, mode: Mode, data: D) -> Result<SendUnicorn<D>, uc_error> {
+        Ok(SendUnicorn {
+            uc: Unicorn::new_with_data(arch, mode, data)?,
+        })
+    }
+
+    /// Create the engine with a CPU model or page size, see `Unicorn::builder`.
+    pub fn from_builder(builder: UnicornBuilder, data: D) -> Result<SendUnicorn<D>, uc_error> {
+        Ok(SendUnicorn {
+            uc: builder.build_with_data(data)?,
+        })
+    }
+
+    /// The engine as a `Unicorn`, which stays on this thread.
+    #[must_use]
+    pub fn into_inner(self) -> Unicorn<'static, D> {
+        self.uc
+    }
+
+    #[must_use]
+    pub fn get_data(&self) -> &D {
+        self.uc.get_data()
+    }
+
+    #[must_use]
+    pub fn get_data_mut(&mut self) -> &mut D {
+        self.uc.get_data_mut()
+    }
+
+    #[must_use]
+    pub fn get_arch(&self) -> Arch {
+        self.uc.get_arch()
+    }
+
+    pub fn query(&self, query: Query) -> Result<usize, uc_error> {
+        self.uc.query(query)
+    }
+
+    /// See `Unicorn::ctl`.
+    pub fn ctl(&mut self) -> Ctl<'_> {
+        self.uc.ctl()
+    }
+
+    pub fn mem_regions(&self) -> Result<Vec<MemRegion>, uc_error> {
+        self.uc.mem_regions()
+    }
+
+    pub fn mem_read(&self, address: u64, buf: &mut [u8]) -> Result<(), uc_error> {
+        self.uc.mem_read(address, buf)
+    }
+
+    pub fn mem_read_as_vec(&self, address: u64, size: usize) -> Result<Vec<u8>, uc_error> {
+        self.uc.mem_read_as_vec(address, size)
+    }
+
+    pub fn mem_write(&mut self, address: u64, bytes: &[u8]) -> Result<(), uc_error> {
+        self.uc.mem_write(address, bytes)
+    }
+
+    /// See `Unicorn::mem_map_ptr`. The memory moves between threads with the engine.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` has to stay valid for `size` bytes as long as it is mapped, and must
+    /// not be accessed from other threads meanwhile.
+    pub unsafe fn mem_map_ptr(
+        &mut self,
+        address: u64,
+        size: usize,
+        perms: Permission,
+        ptr: *mut c_void,
+    ) -> Result<(), uc_error> {
+        self.uc.mem_map_ptr(address, size, perms, ptr)
+    }
+
+    pub fn mem_map(
+        &mut self,
+        address: u64,
+        size: libc::size_t,
+        perms: Permission,
+    ) -> Result<(), uc_error> {
+        self.uc.mem_map(address, size, perms)
+    }
+
+    /// See `Unicorn::mmio_map`.
+    pub fn mmio_map<R, W>(
+        &mut self,
+        address: u64,
+        size: libc::size_t,
+        read_callback: Option<R>,
+        write_callback: Option<W>,
+    ) -> Result<(), uc_error>
+    where
+        R: FnMut(&mut HookCtx<D>, u64, usize) -> u64 + Send + 'static,
+        W: FnMut(&mut HookCtx<D>, u64, usize, u64) + Send + 'static,
+    {
+        self.uc
+            .mmio_map(address, size, read_callback, write_callback)
+    }
+
+    pub fn mmio_map_ro<F>(
+        &mut self,
+        address: u64,
+        size: libc::size_t,
+        callback: F,
+    ) -> Result<(), uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, u64, usize) -> u64 + Send + 'static,
+    {
+        self.uc.mmio_map_ro(address, size, callback)
+    }
+
+    pub fn mmio_map_wo<F>(
+        &mut self,
+        address: u64,
+        size: libc::size_t,
+        callback: F,
+    ) -> Result<(), uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, u64, usize, u64) + Send + 'static,
+    {
+        self.uc.mmio_map_wo(address, size, callback)
+    }
+
+    pub fn mem_unmap(&mut self, address: u64, size: libc::size_t) -> Result<(), uc_error> {
+        self.uc.mem_unmap(address, size)
+    }
+
+    pub fn mem_protect(
+        &mut self,
+        address: u64,
+        size: libc::size_t,
+        perms: Permission,
+    ) -> Result<(), uc_error> {
+        self.uc.mem_protect(address, size, perms)
+    }
+
+    /// See `Unicorn::track_dirty_pages`.
+    pub fn track_dirty_pages(&mut self) -> Result<(), uc_error> {
+        self.uc.track_dirty_pages()
+    }
+
+    pub fn untrack_dirty_pages(&mut self) {
+        self.uc.untrack_dirty_pages();
+    }
+
+    #[must_use]
+    pub fn dirty_pages(&self) -> Vec<u64> {
+        self.uc.dirty_pages()
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.uc.clear_dirty();
+    }
+
+    pub fn reg_read<T: Into<i32>>(&self, regid: T) -> Result<u64, uc_error> {
+        self.uc.reg_read(regid)
+    }
+
+    pub fn reg_read_i32<T: Into<i32>>(&self, regid: T) -> Result<i32, uc_error> {
+        self.uc.reg_read_i32(regid)
+    }
+
+    pub fn reg_read_long<T: Into<i32>>(&self, regid: T) -> Result<ArrayVec<[u8; 64]>, uc_error> {
+        self.uc.reg_read_long(regid)
+    }
+
+    pub fn reg_read_batch(&self, regids: &[i32]) -> Result<Vec<u64>, uc_error> {
+        self.uc.reg_read_batch(regids)
+    }
+
+    pub fn regs_read<R: RegisterFile>(&self) -> Result<R, uc_error> {
+        self.uc.regs_read()
+    }
+
+    pub fn reg_write<T: Into<i32>>(&mut self, regid: T, value: u64) -> Result<(), uc_error> {
+        self.uc.reg_write(regid, value)
+    }
+
+    pub fn reg_write_long<T: Into<i32>>(&mut self, regid: T, value: &[u8]) -> Result<(), uc_error> {
+        self.uc.reg_write_long(regid, value)
+    }
+
+    pub fn reg_write_batch(&mut self, regs: &[(i32, u64)]) -> Result<(), uc_error> {
+        self.uc.reg_write_batch(regs)
+    }
+
+    pub fn regs_write<R: RegisterFile>(&mut self, regs: &R) -> Result<(), uc_error> {
+        self.uc.regs_write(regs)
+    }
+
+    pub fn pc_read(&self) -> Result<u64, uc_error> {
+        self.uc.pc_read()
+    }
+
+    pub fn pc_write(&mut self, value: u64) -> Result<(), uc_error> {
+        self.uc.pc_write(value)
+    }
+
+    /// The callback has to be `Send`, one holding an `Rc` is rejected:
+    ///
+    /// ```compile_fail
+    /// use std::rc::Rc;
+    /// use unicornafl::consts::{Arch, Mode};
+    /// use unicornafl::SendUnicorn;
+    ///
+    /// let mut uc = SendUnicorn::new(Arch::X86, Mode::MODE_64).unwrap();
+    /// let count = Rc::new(0);
+    /// uc.add_code_hook(1, 0, move |_, _, _| drop(count.clone()));
+    /// ```
+    pub fn add_code_hook<F>(
+        &mut self,
+        begin: u64,
+        end: u64,
+        callback: F,
+    ) -> Result<ffi::uc_hook, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, u64, u32) + Send + 'static,
+    {
+        self.uc
+            .add_code_hook(begin, end, callback)
+            .map(HookHandle::keep)
+    }
+
+    pub fn add_block_hook<F>(&mut self, callback: F) -> Result<ffi::uc_hook, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, u64, u32) + Send + 'static,
+    {
+        self.uc.add_block_hook(callback).map(HookHandle::keep)
+    }
+
+    pub fn add_tcg_opcode_hook<F>(
+        &mut self,
+        begin: u64,
+        end: u64,
+        op: TcgOpCode,
+        flags: TcgOpFlag,
+        callback: F,
+    ) -> Result<ffi::uc_hook, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, u64, u64, u64, u32) + Send + 'static,
+    {
+        self.uc
+            .add_tcg_opcode_hook(begin, end, op, flags, callback)
+            .map(HookHandle::keep)
+    }
+
+    pub fn add_edge_generated_hook<F>(&mut self, callback: F) -> Result<ffi::uc_hook, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, &TranslationBlock, &TranslationBlock) + Send + 'static,
+    {
+        self.uc
+            .add_edge_generated_hook(callback)
+            .map(HookHandle::keep)
+    }
+
+    pub fn add_mem_hook<F>(
+        &mut self,
+        hook_type: HookType,
+        begin: u64,
+        end: u64,
+        callback: F,
+    ) -> Result<ffi::uc_hook, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, MemType, u64, usize, i64) -> bool + Send + 'static,
+    {
+        self.uc
+            .add_mem_hook(hook_type, begin, end, callback)
+            .map(HookHandle::keep)
+    }
+
+    pub fn add_mem_fault_hook<F>(
+        &mut self,
+        hook_type: HookType,
+        begin: u64,
+        end: u64,
+        callback: F,
+    ) -> Result<ffi::uc_hook, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, MemType, u64, usize, i64) -> MemHookAction + Send + 'static,
+    {
+        self.uc
+            .add_mem_fault_hook(hook_type, begin, end, callback)
+            .map(HookHandle::keep)
+    }
+
+    pub fn add_insn_invalid_hook<F>(&mut self, callback: F) -> Result<ffi::uc_hook, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>) -> bool + Send + 'static,
+    {
+        self.uc
+            .add_insn_invalid_hook(callback)
+            .map(HookHandle::keep)
+    }
+
+    pub fn add_intr_hook<F>(&mut self, callback: F) -> Result<ffi::uc_hook, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, u32) + Send + 'static,
+    {
+        self.uc.add_intr_hook(callback).map(HookHandle::keep)
+    }
+
+    pub fn add_insn_in_hook<F>(&mut self, callback: F) -> Result<ffi::uc_hook, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, u32, usize) + Send + 'static,
+    {
+        self.uc.add_insn_in_hook(callback).map(HookHandle::keep)
+    }
+
+    pub fn add_insn_out_hook<F>(&mut self, callback: F) -> Result<ffi::uc_hook, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>, u32, usize, u32) + Send + 'static,
+    {
+        self.uc.add_insn_out_hook(callback).map(HookHandle::keep)
+    }
+
+    pub fn add_insn_sys_hook<F>(
+        &mut self,
+        insn_type: x86::InsnSys,
+        begin: u64,
+        end: u64,
+        callback: F,
+    ) -> Result<ffi::uc_hook, uc_error>
+    where
+        F: FnMut(&mut HookCtx<D>) + Send + 'static,
+    {
+        self.uc
+            .add_insn_sys_hook(insn_type, begin, end, callback)
+            .map(HookHandle::keep)
+    }
+
+    pub fn remove_hook(&mut self, hook: ffi::uc_hook) -> Result<(), uc_error> {
+        self.uc.remove_hook(hook)
+    }
+
+    #[must_use]
+    pub fn hooks(&self) -> Vec<HookInfo> {
+        self.uc.hooks()
+    }
+
+    pub fn context_alloc(&self) -> Result<Context, uc_error> {
+        self.uc.context_alloc()
+    }
+
+    pub fn context_save(&self, context: &mut Context) -> Result<(), uc_error> {
+        self.uc.context_save(context)
+    }
+
+    pub fn context_init(&self) -> Result<Context, uc_error> {
+        self.uc.context_init()
+    }
+
+    pub fn context_restore(&self, context: &Context) -> Result<(), uc_error> {
+        self.uc.context_restore(context)
+    }
+
+    pub fn save_context<P: AsRef<Path>>(
+        &self,
+        context: &Context,
+        path: P,
+    ) -> Result<(), SnapshotError> {
+        self.uc.save_context(context, path)
+    }
+
+    pub fn load_context<P: AsRef<Path>>(&self, path: P) -> Result<Context, SnapshotError> {
+        self.uc.load_context(path)
+    }
+
+    /// See `Unicorn::snapshot`, the snapshot can be restored on any thread.
+    pub fn snapshot(&mut self) -> Result<Snapshot, uc_error> {
+        self.uc.snapshot()
+    }
+
+    pub fn load_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<Snapshot, SnapshotError> {
+        self.uc.load_snapshot(path)
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), uc_error> {
+        self.uc.restore(snapshot)
+    }
+
+    pub fn emu_start(
+        &mut self,
+        begin: u64,
+        until: u64,
+        timeout: u64,
+        count: usize,
+    ) -> Result<(), uc_error> {
+        self.uc.emu_start(begin, until, timeout, count)
+    }
+
+    pub fn emu_stop(&mut self) -> Result<(), uc_error> {
+        self.uc.emu_stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SendUnicorn;
+    use crate::Snapshot;
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn engines_and_snapshots_are_send() {
+        assert_send::<SendUnicorn<Vec<u8>>>();
+        assert_send::<Snapshot>();
+    }
+}
@@ -0,0 +1,356 @@
+//! On-disk formats of `Snapshot` and `Context`
+//!
+//! All integers are little endian. A context file holds the CPU state:
+//!
+//! ```text
+//! magic           b"UCCTX\0\0\0"
+//! format version  u32
+//! arch            u32
+//! mode            u32
+//! cpu model       i32, as read with `UC_CTL_CPU_MODEL`
+//! register count  u64, followed by the registers:
+//!     id          i32
+//!     size        u32
+//!     value       size bytes, as Unicorn reads it, i.e. in host byte order
+//! ```
+//!
+//! A snapshot file starts like a context file, then holds the memory:
+//!
+//! ```text
+//! magic           b"UCSNAP\0\0"
+//! format version  u32
+//! arch .. registers, as in a context file
+//! page size       u32
+//! region count    u64, followed by the regions:
+//!     begin       u64
+//!     end         u64, inclusive
+//!     perms       u32
+//!     contents    end - begin + 1 bytes
+//! ```
+//!
+//! The registers are the ones of the arch's `RegisterFile`, everything else of
+//! the CPU state is taken from the engine that loads the file.
+
+use std::error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::consts::{uc_error, Arch, Mode, Permission};
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"UCSNAP\0\0";
+const CONTEXT_MAGIC: &[u8; 8] = b"UCCTX\0\0\0";
+const FORMAT_VERSION: u32 = 3;
+/// Unicorn reads no register wider than this
+const MAX_REGISTER_SIZE: u32 = 64;
+
+/// Why a snapshot could not be saved or loaded.
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// Not a snapshot, or one of an unknown format version.
+    Format,
+    /// The snapshot was taken with another arch, mode, CPU model or page size, or
+    /// with a Unicorn version that has other registers.
+    Mismatch,
+    Unicorn(uc_error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot I/O failed: {}", err),
+            SnapshotError::Format => write!(f, "not a snapshot or unsupported format version"),
+            SnapshotError::Mismatch => write!(
+                f,
+                "snapshot of another arch, mode, CPU model, page size or Unicorn version"
+            ),
+            SnapshotError::Unicorn(err) => write!(f, "unicorn failed: {:?}", err),
+        }
+    }
+}
+
+impl error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            SnapshotError::Format
+        } else {
+            SnapshotError::Io(err)
+        }
+    }
+}
+
+impl From<uc_error> for SnapshotError {
+    fn from(err: uc_error) -> Self {
+        SnapshotError::Unicorn(err)
+    }
+}
+
+/// A context as stored on disk.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ContextFile {
+    pub arch: Arch,
+    pub mode: Mode,
+    pub cpu_model: i32,
+    pub registers: Vec<ContextFileRegister>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct ContextFileRegister {
+    pub id: i32,
+    pub value: Vec<u8>,
+}
+
+/// A snapshot as stored on disk.
+#[derive(Debug, PartialEq)]
+pub(crate) struct SnapshotFile {
+    pub context: ContextFile,
+    pub page_size: u32,
+    pub regions: Vec<SnapshotFileRegion>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct SnapshotFileRegion {
+    pub begin: u64,
+    pub end: u64,
+    pub perms: Permission,
+    pub data: Vec<u8>,
+}
+
+pub(crate) fn arch_from_u32(arch: u32) -> Option<Arch> {
+    Some(match arch {
+        1 => Arch::ARM,
+        2 => Arch::ARM64,
+        3 => Arch::MIPS,
+        4 => Arch::X86,
+        5 => Arch::PPC,
+        6 => Arch::SPARC,
+        7 => Arch::M68K,
+        8 => Arch::RISCV,
+        9 => Arch::S390X,
+        10 => Arch::TRICORE,
+        _ => return None,
+    })
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_bytes<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>, SnapshotError> {
+    // Don't trust the length with the allocation, a truncated file ends early.
+    let mut data = vec![];
+    reader.take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(SnapshotError::Format);
+    }
+    Ok(data)
+}
+
+fn read_header<R: Read>(reader: &mut R, magic: &[u8; 8]) -> Result<(), SnapshotError> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    if &buf != magic || read_u32(reader)? != FORMAT_VERSION {
+        return Err(SnapshotError::Format);
+    }
+    Ok(())
+}
+
+impl ContextFile {
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(CONTEXT_MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        self.write_body(writer)
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, SnapshotError> {
+        read_header(reader, CONTEXT_MAGIC)?;
+        Self::read_body(reader)
+    }
+
+    fn write_body<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&(self.arch as u32).to_le_bytes())?;
+        writer.write_all(&self.mode.bits().to_le_bytes())?;
+        writer.write_all(&self.cpu_model.to_le_bytes())?;
+        writer.write_all(&(self.registers.len() as u64).to_le_bytes())?;
+        for register in &self.registers {
+            writer.write_all(&register.id.to_le_bytes())?;
+            writer.write_all(&(register.value.len() as u32).to_le_bytes())?;
+            writer.write_all(&register.value)?;
+        }
+        Ok(())
+    }
+
+    fn read_body<R: Read>(reader: &mut R) -> Result<Self, SnapshotError> {
+        let arch = arch_from_u32(read_u32(reader)?).ok_or(SnapshotError::Format)?;
+        #[allow(clippy::cast_possible_wrap)]
+        let mode = Mode::from_bits(read_u32(reader)? as i32).ok_or(SnapshotError::Format)?;
+        #[allow(clippy::cast_possible_wrap)]
+        let cpu_model = read_u32(reader)? as i32;
+
+        let mut registers = vec![];
+        for _ in 0..read_u64(reader)? {
+            #[allow(clippy::cast_possible_wrap)]
+            let id = read_u32(reader)? as i32;
+            let size = read_u32(reader)?;
+            if size > MAX_REGISTER_SIZE {
+                return Err(SnapshotError::Format);
+            }
+            let value = read_bytes(reader, u64::from(size))?;
+            registers.push(ContextFileRegister { id, value });
+        }
+
+        Ok(ContextFile {
+            arch,
+            mode,
+            cpu_model,
+            registers,
+        })
+    }
+}
+
+impl SnapshotFile {
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        self.context.write_body(writer)?;
+        writer.write_all(&self.page_size.to_le_bytes())?;
+        writer.write_all(&(self.regions.len() as u64).to_le_bytes())?;
+        for region in &self.regions {
+            writer.write_all(&region.begin.to_le_bytes())?;
+            writer.write_all(&region.end.to_le_bytes())?;
+            writer.write_all(&region.perms.bits().to_le_bytes())?;
+            writer.write_all(&region.data)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, SnapshotError> {
+        read_header(reader, SNAPSHOT_MAGIC)?;
+        let context = ContextFile::read_body(reader)?;
+        let page_size = read_u32(reader)?;
+
+        let mut regions = vec![];
+        for _ in 0..read_u64(reader)? {
+            let begin = read_u64(reader)?;
+            let end = read_u64(reader)?;
+            let perms = Permission::from_bits(read_u32(reader)?).ok_or(SnapshotError::Format)?;
+            if begin > end || end - begin == u64::MAX {
+                return Err(SnapshotError::Format);
+            }
+            let data = read_bytes(reader, end - begin + 1)?;
+            regions.push(SnapshotFileRegion {
+                begin,
+                end,
+                perms,
+                data,
+            });
+        }
+
+        Ok(SnapshotFile {
+            context,
+            page_size,
+            regions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ContextFile, ContextFileRegister, SnapshotError, SnapshotFile, SnapshotFileRegion,
+    };
+    use crate::consts::{Arch, Mode, Permission};
+
+    fn test_file() -> SnapshotFile {
+        SnapshotFile {
+            context: ContextFile {
+                arch: Arch::ARM,
+                mode: Mode::THUMB,
+                cpu_model: 14,
+                registers: vec![
+                    ContextFileRegister {
+                        id: 11,
+                        value: vec![1, 2, 3, 4],
+                    },
+                    ContextFileRegister {
+                        id: 12,
+                        value: vec![5; 8],
+                    },
+                ],
+            },
+            page_size: 0x400,
+            regions: vec![SnapshotFileRegion {
+                begin: 0x1000,
+                end: 0x1fff,
+                perms: Permission::READ | Permission::EXEC,
+                data: vec![0x41; 0x1000],
+            }],
+        }
+    }
+
+    #[test]
+    fn snapshot_file_round_trips() {
+        let file = test_file();
+        let mut buf = vec![];
+        file.write(&mut buf).unwrap();
+        assert_eq!(&buf[..8], b"UCSNAP\0\0");
+        assert_eq!(SnapshotFile::read(&mut &buf[..]).unwrap(), file);
+    }
+
+    #[test]
+    fn context_file_round_trips() {
+        let file = test_file().context;
+        let mut buf = vec![];
+        file.write(&mut buf).unwrap();
+        assert_eq!(&buf[..8], b"UCCTX\0\0\0");
+        assert_eq!(ContextFile::read(&mut &buf[..]).unwrap(), file);
+        assert!(matches!(
+            SnapshotFile::read(&mut &buf[..]),
+            Err(SnapshotError::Format)
+        ));
+    }
+
+    #[test]
+    fn snapshot_file_rejects_garbage() {
+        let mut buf = vec![];
+        test_file().write(&mut buf).unwrap();
+
+        let truncated = &buf[..buf.len() - 1];
+        assert!(matches!(
+            SnapshotFile::read(&mut &truncated[..]),
+            Err(SnapshotError::Format)
+        ));
+
+        let mut future = buf.clone();
+        future[8] = 4;
+        assert!(matches!(
+            SnapshotFile::read(&mut &future[..]),
+            Err(SnapshotError::Format)
+        ));
+
+        // The size of the first register
+        let mut wide = buf.clone();
+        wide[36] = 65;
+        assert!(matches!(
+            SnapshotFile::read(&mut &wide[..]),
+            Err(SnapshotError::Format)
+        ));
+    }
+}
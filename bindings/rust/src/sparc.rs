@@ -108,3 +108,61 @@ impl From<Register> for i32 {
         r as i32
     }
 }
+
+// SPARC32 CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel32 {
+    FUJITSU_MB86904 = 0,
+    FUJITSU_MB86907,
+    TI_MICROSPARC_I,
+    TI_MICROSPARC_II,
+    TI_MICROSPARC_IIEP,
+    TI_SUPERSPARC_40,
+    TI_SUPERSPARC_50,
+    TI_SUPERSPARC_51,
+    TI_SUPERSPARC_60,
+    TI_SUPERSPARC_61,
+    TI_SUPERSPARC_II,
+    LEON2,
+    LEON3,
+}
+
+impl From<CpuModel32> for i32 {
+    fn from(model: CpuModel32) -> Self {
+        model as i32
+    }
+}
+
+// SPARC64 CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel64 {
+    FUJITSU = 0,
+    FUJITSU_III,
+    FUJITSU_IV,
+    FUJITSU_V,
+    TI_ULTRASPARC_I,
+    TI_ULTRASPARC_II,
+    TI_ULTRASPARC_III,
+    TI_ULTRASPARC_IIE,
+    SUN_ULTRASPARC_III,
+    SUN_ULTRASPARC_III_CU,
+    SUN_ULTRASPARC_IIII,
+    SUN_ULTRASPARC_IV,
+    SUN_ULTRASPARC_IV_PLUS,
+    SUN_ULTRASPARC_IIII_PLUS,
+    SUN_ULTRASPARC_T1,
+    SUN_ULTRASPARC_T2,
+    NEC_ULTRASPARC_I,
+}
+
+impl From<CpuModel64> for i32 {
+    fn from(model: CpuModel64) -> Self {
+        model as i32
+    }
+}
+
+pub use crate::regs::sparc::Regs;
@@ -0,0 +1,91 @@
+// TriCore registers
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum Register {
+    INVALID = 0,
+    A0,
+    A1,
+    A2,
+    A3,
+    A4,
+    A5,
+    A6,
+    A7,
+    A8,
+    A9,
+    A10,
+    A11,
+    A12,
+    A13,
+    A14,
+    A15,
+    D0,
+    D1,
+    D2,
+    D3,
+    D4,
+    D5,
+    D6,
+    D7,
+    D8,
+    D9,
+    D10,
+    D11,
+    D12,
+    D13,
+    D14,
+    D15,
+    PCXI,
+    PSW,
+    PSW_USB_C,
+    PSW_USB_V,
+    PSW_USB_SV,
+    PSW_USB_AV,
+    PSW_USB_SAV,
+    PC,
+    SYSCON,
+    CPU_ID,
+    BIV,
+    BTV,
+    ISP,
+    ICR,
+    FCX,
+    LCX,
+    COMPAT,
+}
+
+impl Register {
+    // Global address registers
+    pub const GA0: Register = Register::A0;
+    pub const GA1: Register = Register::A1;
+    pub const GA8: Register = Register::A8;
+    pub const GA9: Register = Register::A9;
+    pub const SP: Register = Register::A10;
+    pub const LR: Register = Register::A11;
+    pub const RA: Register = Register::A11;
+}
+
+impl From<Register> for i32 {
+    fn from(r: Register) -> Self {
+        r as i32
+    }
+}
+
+// TriCore CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel {
+    TC1796 = 0,
+    TC1797,
+    TC27X,
+}
+
+impl From<CpuModel> for i32 {
+    fn from(model: CpuModel) -> Self {
+        model as i32
+    }
+}
+
+pub use crate::regs::tricore::Regs;
@@ -273,3 +273,56 @@ pub struct Mmr {
     pub limit: u32,
     pub flags: u32,
 }
+
+// x86 CPU models, see `UnicornBuilder::cpu_model`
+#[repr(C)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+pub enum CpuModel {
+    QEMU64 = 0,
+    PHENOM,
+    CORE2DUO,
+    KVM64,
+    QEMU32,
+    KVM32,
+    COREDUO,
+    I486,
+    PENTIUM,
+    PENTIUM2,
+    PENTIUM3,
+    ATHLON,
+    N270,
+    CONROE,
+    PENRYN,
+    NEHALEM,
+    WESTMERE,
+    SANDYBRIDGE,
+    IVYBRIDGE,
+    HASWELL,
+    BROADWELL,
+    SKYLAKE_CLIENT,
+    SKYLAKE_SERVER,
+    CASCADELAKE_SERVER,
+    COOPERLAKE,
+    ICELAKE_CLIENT,
+    ICELAKE_SERVER,
+    DENVERTON,
+    SNOWRIDGE,
+    KNIGHTSMILL,
+    OPTERON_G1,
+    OPTERON_G2,
+    OPTERON_G3,
+    OPTERON_G4,
+    OPTERON_G5,
+    EPYC,
+    DHYANA,
+    EPYC_ROME,
+}
+
+impl From<CpuModel> for i32 {
+    fn from(model: CpuModel) -> Self {
+        model as i32
+    }
+}
+
+pub use crate::regs::x86::{Regs, Regs32};
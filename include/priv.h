@@ -17,9 +17,90 @@
 
 #define FF16 (0xFFFFFFFFFFFFFFFF)
 
+/* Bounds for a map size set with AFL_MAP_SIZE or uc_afl_options */
+#define MAP_SIZE_MIN 64U
+#define MAP_SIZE_MAX (1U << 30)
+
+/* Bounds for UC_AFL_COV_NGRAM, like AFL_LLVM_NGRAM_SIZE */
+#define NGRAM_SIZE_MIN 2U
+#define NGRAM_SIZE_MAX 16U
+
 /* Copied from aflpp/types.h to talk to forkserver */
 #define FS_OPT_ENABLED 0x80000001
+#define FS_OPT_MAPSIZE 0x40000000
+#define FS_OPT_AUTODICT 0x10000000
 #define FS_OPT_SHDMEM_FUZZ 0x01000000
+#define FS_OPT_ERROR 0xf800008f
+#define FS_OPT_MAX_MAPSIZE ((0x00fffffeU >> 1) + 1)
+#define FS_OPT_SET_MAPSIZE(x)                                                  \
+    ((x) <= 1 || (x) > FS_OPT_MAX_MAPSIZE ? 0 : (((x)-1) << 1))
+#define FS_OPT_SET_ERROR(x) (((x)&0x0000ffff) << 8)
+
+/* Reason codes for FS_OPT_ERROR and FS_NEW_ERROR */
+#define FS_ERROR_MAP_SIZE 1
+#define FS_ERROR_MAP_ADDR 2
+#define FS_ERROR_SHM_OPEN 4
+#define FS_ERROR_SHMAT 8
+#define FS_ERROR_MMAP 16
+
+/* The versioned forkserver handshake of AFL++ >= 4.20 */
+#define FS_NEW_VERSION_MAX 1
+#define FS_NEW_VERSION_MSG (0x41464c00 + FS_NEW_VERSION_MAX)
+#define FS_NEW_ERROR 0xeffe0000
+#define FS_NEW_OPT_MAPSIZE 0x00000001
+#define FS_NEW_OPT_SHDMEM_FUZZ 0x00000002
+#define FS_NEW_OPT_AUTODICT 0x00000800
+
+/* Copied from aflpp/cmplog.h to fill the CmpLog map */
+#define CMP_MAP_W 65536
+#define CMP_MAP_H 32
+#define CMP_MAP_RTN_H (CMP_MAP_H / 2)
+
+#define CMP_TYPE_INS 0
+#define CMP_TYPE_RTN 1
+
+struct cmp_header {
+
+    unsigned hits : 6;
+    unsigned shape : 5;
+    unsigned type : 1;
+    unsigned attribute : 4;
+
+} __attribute__((packed));
+
+struct cmp_operands {
+
+    uint64_t v0;
+    uint64_t v0_128;
+    uint64_t v0_256_0;
+    uint64_t v0_256_1;
+    uint64_t v1;
+    uint64_t v1_128;
+    uint64_t v1_256_0;
+    uint64_t v1_256_1;
+    uint8_t unused[8];
+
+} __attribute__((packed));
+
+struct cmpfn_operands {
+
+    uint8_t v0[32];
+    uint8_t v1[32];
+    uint8_t v0_len;
+    uint8_t v1_len;
+    uint8_t unused[6];
+
+} __attribute__((packed));
+
+struct cmp_map {
+
+    struct cmp_header headers[CMP_MAP_W];
+    struct cmp_operands log[CMP_MAP_W][CMP_MAP_H];
+
+};
+
+/* Longest buffer logged for a compare routine */
+#define CMPLOG_RTN_LEN 32
 
 /**
  * The correct fds for reading and writing pipes
@@ -36,6 +117,8 @@ enum afl_child_ret {
     AFL_CHILD_FOUND_CRASH,
     // Read again, one afl_tsl struct.
     AFL_CHILD_TSL_REQUEST,
+    // Read again, the two addresses of an edge which just got its ID.
+    AFL_CHILD_EDGE_ID,
     // Child no longer there. Read status code.
     AFL_CHILD_EXITED,
 
@@ -34,6 +34,115 @@ typedef bool (*uc_afl_cb_validate_crash_t)(uc_engine* uc, uc_err unicorn_result,
 
 typedef uc_err (*uc_afl_fuzz_cb_t)(uc_engine *uc, void *data);
 
+typedef enum uc_afl_cmplog_rtn_kind {
+    // int strcmp(const char* s1, const char* s2)
+    UC_AFL_CMPLOG_RTN_STRCMP,
+    // int strncmp(const char* s1, const char* s2, size_t n)
+    UC_AFL_CMPLOG_RTN_STRNCMP,
+    // int memcmp(const void* s1, const void* s2, size_t n)
+    UC_AFL_CMPLOG_RTN_MEMCMP,
+} uc_afl_cmplog_rtn_kind;
+
+//
+// The calling convention of a compare routine, for firmware that does not
+// follow the default one of the arch. Registers left at 0 (UC_*_REG_INVALID)
+// are taken from the default calling convention instead.
+//
+//  @s1_reg: The register with the pointer to the first operand.
+//  @s2_reg: The register with the pointer to the second operand.
+//  @n_reg: The register with the length, unused for UC_AFL_CMPLOG_RTN_STRCMP.
+//
+typedef struct uc_afl_cmplog_cc {
+    int s1_reg;
+    int s2_reg;
+    int n_reg;
+} uc_afl_cmplog_cc;
+
+//
+// A compare routine in the target whose operands are logged for CmpLog.
+//
+//  @address: The entry address of the routine.
+//  @kind: What the routine compares.
+//  @cc: Where the routine takes its arguments from. Zero-initialize it for the
+//       default calling convention of the arch.
+//
+typedef struct uc_afl_cmplog_rtn {
+    uint64_t address;
+    uc_afl_cmplog_rtn_kind kind;
+    uc_afl_cmplog_cc cc;
+} uc_afl_cmplog_rtn;
+
+typedef enum uc_afl_cov_mode {
+    // Classic AFL edge coverage, the default.
+    UC_AFL_COV_EDGE,
+    // Only which blocks were hit. The fastest, but blind to new paths between
+    // known blocks.
+    UC_AFL_COV_BLOCK,
+    // Edges from the last cov_ngram_size - 1 blocks, like AFL_LLVM_NGRAM_SIZE.
+    UC_AFL_COV_NGRAM,
+    // Edges in their calling context, like AFL_LLVM_CTX. Unicorn has no notion
+    // of calls, so the context is derived from the stack pointer.
+    UC_AFL_COV_CTX,
+    // Every edge gets its own map entry, numbered in the order the edges are
+    // first hit. Nothing collides until the map is full, after which new edges
+    // fall back to UC_AFL_COV_EDGE. Split compares are still hashed.
+    UC_AFL_COV_EDGE_ID,
+} uc_afl_cov_mode;
+
+// An address range, @end is inclusive like for uc_hook_add.
+typedef struct uc_afl_range {
+    uint64_t begin;
+    uint64_t end;
+} uc_afl_range;
+
+//
+// Extra fuzzer options for uc_afl_fuzz_ex. Zero-initialize it and set @size to
+// get the defaults of uc_afl_fuzz.
+//
+//  @size: sizeof(uc_afl_options), so that fields can be added without breaking
+//         callers built against an older header.
+//  @autodict: AFL++ autodictionary offered to afl-fuzz during the forkserver
+//             handshake. It is a sequence of tokens, each prefixed with its
+//             length as a single byte. May be NULL.
+//  @autodict_len: The length of @autodict in bytes.
+//  @map_size: Size of the coverage map in bytes. If 0, AFL_MAP_SIZE is used and
+//             the default of 64k without it. afl-fuzz has to be started with
+//             at least the same AFL_MAP_SIZE.
+//  @cmplog_rtns: Compare routines logged when afl-fuzz runs with CmpLog
+//                (-c 0). Compare instructions are always logged. May be NULL.
+//  @cmplog_rtn_count: The number of @cmplog_rtns.
+//  @cov_mode: How blocks are recorded in the coverage map.
+//  @cov_ngram_size: The N of UC_AFL_COV_NGRAM, between 2 and 16.
+//  @cov_ranges: If set, only blocks in these ranges are instrumented for
+//               coverage, compcov and CmpLog. AFL_UNICORN_INST_RANGES adds to
+//               them, e.g. "0x10000-0x1ffff,0x40000-0x40fff". May be NULL.
+//  @cov_range_count: The number of @cov_ranges.
+//  @cov_exclude_ranges: Blocks in these ranges are never instrumented, even if
+//                       they are in @cov_ranges. AFL_UNICORN_EXCLUDE_RANGES
+//                       adds to them. May be NULL.
+//  @cov_exclude_range_count: The number of @cov_exclude_ranges.
+//  @persistent_restore: In persistent mode, snapshot the registers before the
+//                       first iteration and restore them, together with every
+//                       page the emulated code wrote to, before each further
+//                       iteration. Memory written with uc_mem_write, e.g. in
+//                       place_input_callback, is not restored.
+//
+typedef struct uc_afl_options {
+    uint32_t size;
+    const uint8_t* autodict;
+    size_t autodict_len;
+    uint32_t map_size;
+    const uc_afl_cmplog_rtn* cmplog_rtns;
+    size_t cmplog_rtn_count;
+    uc_afl_cov_mode cov_mode;
+    uint32_t cov_ngram_size;
+    const uc_afl_range* cov_ranges;
+    size_t cov_range_count;
+    const uc_afl_range* cov_exclude_ranges;
+    size_t cov_exclude_range_count;
+    bool persistent_restore;
+} uc_afl_options;
+
 //
 //  Start our fuzzer.
 //
@@ -67,7 +176,7 @@ uc_afl_ret uc_afl_fuzz(uc_engine* uc, char* input_file,
 //
 // To implement more complex fuzzing logic, pass an extra fuzzing_callback with this API.
 //
-UNICORN_EXPORT
+UNICORNAFL_EXPORT
 uc_afl_ret uc_afl_fuzz_custom(uc_engine* uc, char* input_file,
                               uc_afl_cb_place_input_t place_input_callback,
                               uc_afl_fuzz_cb_t fuzz_callbck,
@@ -75,6 +184,40 @@ uc_afl_ret uc_afl_fuzz_custom(uc_engine* uc, char* input_file,
                               bool always_validate, uint32_t persistent_iters,
                               void* data);
 
+//
+// uc_afl_fuzz and uc_afl_fuzz_custom with extra options.
+//
+// If @fuzz_callback is NULL, the emulation runs from the current pc until one of
+// the @exits is hit, like in uc_afl_fuzz. Otherwise @exits may be NULL.
+// @options may be NULL for the defaults.
+//
+UNICORNAFL_EXPORT
+uc_afl_ret uc_afl_fuzz_ex(uc_engine* uc, char* input_file,
+                          uc_afl_cb_place_input_t place_input_callback,
+                          uint64_t* exits, size_t exit_count,
+                          uc_afl_fuzz_cb_t fuzz_callback,
+                          uc_afl_cb_validate_crash_t validate_crash_callback,
+                          bool always_validate, uint32_t persistent_iters,
+                          const uc_afl_options* options, void* data);
+
+//
+// Only start the forkserver and leave input handling and emulation to the caller.
+//
+// The coverage hooks are installed on @uc and the process forks for every testcase
+// at the point this function is called. Once the child returns from here, it is
+// expected to read its input, run the emulation and exit.
+//
+//  @uc: The uc_engine return-ed from uc_open().
+//  @exits: All possible exits, may be NULL if @exit_count is 0.
+//  @exit_count: The count of the @exits array.
+//
+//  @uc_afl_ret: UC_AFL_RET_CHILD in the child, UC_AFL_RET_NO_AFL if no afl-fuzz
+//               instance is found, UC_AFL_RET_FINISHED in the parent once afl-fuzz
+//               is gone and UC_AFL_RET_CALLED_TWICE if the forkserver is already up.
+UNICORNAFL_EXPORT
+uc_afl_ret uc_afl_forkserver_start(uc_engine* uc, uint64_t* exits,
+                                   size_t exit_count);
+
 #ifdef __cplusplus
 }
 #endif
@@ -5,6 +5,9 @@
 #include <cstdio>
 #include <cstdlib>
 #include <vector>
+#include <unordered_map>
+#include <utility>
+#include <algorithm>
 #include <cstdint>
 #include <cstring>
 #include <sys/mman.h>
@@ -87,6 +90,180 @@ static void log(bool in_child, const char* fmt, ...) {
 
 static uc_err dummy_uc_afl_fuzz_callback(uc_engine* uc, void* data);
 static uint64_t uc_get_pc(uc_engine* uc);
+static bool uc_get_args(uc_engine* uc, uint64_t* args, size_t count);
+static int uc_sp_reg(uc_engine* uc);
+
+// Tell afl-fuzz why we could not start, it only listens while it waits for the
+// forkserver handshake.
+static void send_forkserver_error(uint32_t error) {
+    uint32_t status;
+
+    if (!error || error > 0xffff) {
+        return;
+    }
+
+    if (getenv("AFL_OLD_FORKSERVER")) {
+        status = FS_OPT_ERROR | FS_OPT_SET_ERROR(error);
+    } else {
+        status = FS_NEW_ERROR | error;
+    }
+
+    if (write(FORKSRV_FD + 1, &status, 4) != 4) {
+        return;
+    }
+}
+
+// The coverage map size, in order of precedence: the explicit option,
+// AFL_MAP_SIZE and MAP_SIZE. Rounded up to 64 bytes like afl-fuzz does, 0 if
+// the requested size is invalid.
+static uint32_t afl_map_size(uint32_t requested) {
+    uint64_t map_size = requested;
+
+    if (!map_size) {
+        const char* map_size_str = getenv("AFL_MAP_SIZE");
+
+        if (!map_size_str) {
+            return MAP_SIZE;
+        }
+
+        map_size = strtoull(map_size_str, NULL, 0);
+    }
+
+    if (map_size < MAP_SIZE_MIN || map_size > MAP_SIZE_MAX) {
+        return 0;
+    }
+
+    return (map_size + 63) & ~63ULL;
+}
+
+// Parses an AFL_UNICORN_INST_RANGES style list of ranges like
+// "0x1000-0x1fff,0x4000-0x4fff" and appends them to ranges.
+static bool afl_parse_ranges(const char* str, std::vector<uc_afl_range>& ranges) {
+    const char* p = str;
+
+    while (*p) {
+        uc_afl_range range;
+        char* end;
+
+        range.begin = strtoull(p, &end, 0);
+        if (end == p || *end != '-') {
+            return false;
+        }
+
+        p = end + 1;
+        range.end = strtoull(p, &end, 0);
+        if (end == p || range.begin > range.end) {
+            return false;
+        }
+
+        ranges.push_back(range);
+
+        if (*end == ',') {
+            p = end + 1;
+        } else if (*end) {
+            return false;
+        } else {
+            p = end;
+        }
+    }
+
+    return true;
+}
+
+static bool afl_valid_ranges(const uc_afl_range* ranges, size_t count) {
+    if (count && !ranges) {
+        return false;
+    }
+
+    for (size_t i = 0; i < count; i++) {
+        if (ranges[i].begin > ranges[i].end) {
+            return false;
+        }
+    }
+
+    return true;
+}
+
+// The ranges to instrument: the included ones, or the whole address space if
+// there are none, minus the excluded ones. Overlapping includes are merged, so
+// no code gets hooked twice.
+static std::vector<uc_afl_range>
+afl_inst_ranges(const std::vector<uc_afl_range>& include,
+                const std::vector<uc_afl_range>& exclude) {
+    std::vector<uc_afl_range> sorted = include;
+    std::vector<uc_afl_range> ranges;
+
+    std::sort(sorted.begin(), sorted.end(),
+              [](const uc_afl_range& a, const uc_afl_range& b) {
+                  return a.begin < b.begin;
+              });
+
+    for (const uc_afl_range& r : sorted) {
+        if (!ranges.empty() && (ranges.back().end == UINT64_MAX ||
+                                r.begin <= ranges.back().end + 1)) {
+            ranges.back().end = std::max(ranges.back().end, r.end);
+        } else {
+            ranges.push_back(r);
+        }
+    }
+
+    if (ranges.empty()) {
+        ranges.push_back({0, UINT64_MAX});
+    }
+
+    for (const uc_afl_range& ex : exclude) {
+        std::vector<uc_afl_range> left;
+
+        for (const uc_afl_range& r : ranges) {
+            if (ex.end < r.begin || ex.begin > r.end) {
+                left.push_back(r);
+                continue;
+            }
+
+            if (ex.begin > r.begin) {
+                left.push_back({r.begin, ex.begin - 1});
+            }
+
+            if (ex.end < r.end) {
+                left.push_back({ex.end + 1, r.end});
+            }
+        }
+
+        ranges.swap(left);
+    }
+
+    return ranges;
+}
+
+static bool write_all(int fd, const void* buf, size_t len) {
+    const uint8_t* p = (const uint8_t*)buf;
+
+    while (len) {
+        ssize_t ret = write(fd, p, len);
+        if (ret <= 0) {
+            return false;
+        }
+        p += ret;
+        len -= ret;
+    }
+
+    return true;
+}
+
+typedef std::pair<uint64_t, uint64_t> afl_edge;
+
+struct afl_edge_hash {
+    size_t operator()(const afl_edge& edge) const {
+        return std::hash<uint64_t>()(edge.first * 0x9e3779b97f4a7c15ULL ^
+                                     edge.second);
+    }
+};
+
+// A page saved for UCAFL::options_.persistent_restore.
+struct persistent_page {
+    std::vector<uint8_t> data;
+    bool dirty;
+};
 
 class UCAFL {
 
@@ -95,17 +272,44 @@ class UCAFL {
           uc_afl_cb_place_input_t place_input_callback,
           uc_afl_cb_validate_crash_t validate_crash_callback,
           uc_afl_fuzz_cb_t fuzz_callback, bool always_validate,
-          uint32_t persistent_iters, void* data)
+          uint32_t persistent_iters, const uc_afl_options* options,
+          void* data)
         : uc_(uc), input_file_(input_file),
           place_input_callback_(place_input_callback),
           validate_crash_callback_(validate_crash_callback),
           fuzz_callback_(fuzz_callback), always_validate_(always_validate),
           persistent_iters_(persistent_iters), data_(data),
           afl_testcase_ptr_(nullptr), afl_testcase_len_p_(nullptr),
-          afl_area_ptr_(nullptr), has_afl_(false), afl_prev_loc_(0), h1_(0),
-          h2_(0), h3_(0), h4_(0) {
+          afl_area_ptr_(nullptr), has_afl_(false), map_size_(MAP_SIZE),
+          afl_map_mask_(MAP_SIZE - 7), afl_cmp_map_(nullptr),
+          afl_prev_loc_(0), afl_prev_pc_(0), afl_next_edge_id_(1), sp_reg_(0),
+          persistent_ctx_(nullptr), persistent_hook_(0), page_size_(0),
+          h2_(0) {
         memset(this->afl_child_pipe_, 0, sizeof(this->afl_child_pipe_));
         memset(this->afl_parent_pipe_, 0, sizeof(this->afl_parent_pipe_));
+        memset(this->afl_ngram_prev_locs_, 0,
+               sizeof(this->afl_ngram_prev_locs_));
+        if (options) {
+            this->options_ = *options;
+            if (options->cmplog_rtns) {
+                this->cmplog_rtns_.assign(options->cmplog_rtns,
+                                          options->cmplog_rtns +
+                                              options->cmplog_rtn_count);
+            }
+            if (options->cov_ranges) {
+                this->cov_ranges_.assign(options->cov_ranges,
+                                         options->cov_ranges +
+                                             options->cov_range_count);
+            }
+            if (options->cov_exclude_ranges) {
+                this->cov_exclude_ranges_.assign(
+                    options->cov_exclude_ranges,
+                    options->cov_exclude_ranges +
+                        options->cov_exclude_range_count);
+            }
+        } else {
+            memset(&this->options_, 0, sizeof(this->options_));
+        }
     }
 
     UCAFL(const UCAFL& ucafl) = delete;
@@ -160,25 +364,38 @@ class UCAFL {
         }
     }
 
+    uc_afl_ret fsrv_start() {
+        this->_afl_steup();
+        if (this->has_afl_) {
+            this->_uc_setup();
+        }
+
+        return this->_fksrv_start();
+    }
+
     ~UCAFL() {
-        if (this->h1_) {
-            uc_hook_del(this->uc_, this->h1_);
+        if (this->persistent_hook_) {
+            uc_hook_del(this->uc_, this->persistent_hook_);
         }
 
-        if (this->h2_) {
-            uc_hook_del(this->uc_, this->h2_);
+        if (this->persistent_ctx_) {
+            uc_context_free(this->persistent_ctx_);
         }
 
-        if (this->h3_) {
-            uc_hook_del(this->uc_, this->h3_);
+        for (uc_hook h : this->cov_hooks_) {
+            uc_hook_del(this->uc_, h);
         }
 
-        if (this->h4_) {
-            uc_hook_del(this->uc_, this->h4_);
+        if (this->h2_) {
+            uc_hook_del(this->uc_, this->h2_);
+        }
+
+        for (uc_hook h : this->cmplog_rtn_hooks_) {
+            uc_hook_del(this->uc_, h);
         }
 
         if (!this->has_afl_) {
-            munmap(this->afl_area_ptr_, MAP_SIZE);
+            munmap(this->afl_area_ptr_, this->map_size_);
         }
     }
 
@@ -215,11 +432,11 @@ class UCAFL {
 
         /* For shared map fuzzing, the forkserver parent forwards the size of
          * the current testcase. */
-        memset(this->afl_area_ptr_, 0, MAP_SIZE);
+        memset(this->afl_area_ptr_, 0, this->map_size_);
         mem_barrier(); // Also make sure nothing read before this point.
 
         // Start with a clean slate.
-        this->afl_prev_loc_ = 0;
+        this->_afl_reset_prev_loc();
         this->afl_area_ptr_[0] = 1;
 
         return UC_AFL_RET_OK;
@@ -253,6 +470,10 @@ class UCAFL {
         bool input_accepted;
         uint32_t i = 0;
 
+        if (this->options_.persistent_restore && this->persistent_iters_ != 1) {
+            this->_persistent_setup();
+        }
+
         for (i = 0; this->persistent_iters_ == 0 || i < this->persistent_iters_;
              i++) {
             if (unlikely(first_round)) {
@@ -263,6 +484,10 @@ class UCAFL {
                 }
 
                 crash_found = false;
+
+                if (this->persistent_ctx_) {
+                    this->_persistent_restore();
+                }
             }
 
             AFL_TESTCASE testcase(this);
@@ -313,12 +538,92 @@ class UCAFL {
         return UC_AFL_RET_NO_AFL;
     }
 
+    void _persistent_setup() {
+        uint32_t page_size = 0;
+
+        if (uc_ctl_get_page_size(this->uc_, &page_size) || !page_size) {
+            ERR_CHILD("Fail to get the page size.\n");
+            exit(1);
+        }
+        this->page_size_ = page_size;
+
+        if (uc_context_alloc(this->uc_, &this->persistent_ctx_) ||
+            uc_context_save(this->uc_, this->persistent_ctx_)) {
+            ERR_CHILD("Fail to save the context for persistent mode.\n");
+            exit(1);
+        }
+
+        if (uc_hook_add(this->uc_, &this->persistent_hook_, UC_HOOK_MEM_WRITE,
+                        (void*)_uc_hook_persistent_write, (void*)this, 1, 0)) {
+            ERR_CHILD("Failed to setup persistent write hook.\n");
+            exit(1);
+        }
+    }
+
+    // Saves a page the first time it is written to, the hook runs before the
+    // write lands.
+    static void _uc_hook_persistent_write(uc_engine* uc, uc_mem_type type,
+                                          uint64_t address, int size,
+                                          int64_t value, void* user_data) {
+        UCAFL* ucafl = (UCAFL*)user_data;
+        uint64_t page_mask = ~(ucafl->page_size_ - 1);
+        uint64_t last = (address + size - 1) & page_mask;
+
+        for (uint64_t page = address & page_mask; page <= last;
+             page += ucafl->page_size_) {
+            ucafl->_persistent_dirty(page);
+        }
+    }
+
+    void _persistent_dirty(uint64_t page) {
+        auto it = this->persistent_pages_.find(page);
+
+        if (likely(it != this->persistent_pages_.end())) {
+            if (!it->second.dirty) {
+                it->second.dirty = true;
+                this->dirty_pages_.push_back(page);
+            }
+            return;
+        }
+
+        persistent_page saved = {std::vector<uint8_t>(this->page_size_), true};
+        if (uc_mem_read(this->uc_, page, saved.data.data(), this->page_size_)) {
+            ERR_CHILD("Fail to save the page at 0x%" PRIx64 ".\n", page);
+            return;
+        }
+
+        this->persistent_pages_.emplace(page, std::move(saved));
+        this->dirty_pages_.push_back(page);
+    }
+
+    void _persistent_restore() {
+        for (uint64_t page : this->dirty_pages_) {
+            persistent_page& saved = this->persistent_pages_[page];
+
+            saved.dirty = false;
+            if (uc_mem_write(this->uc_, page, saved.data.data(),
+                             saved.data.size())) {
+                ERR_CHILD("Fail to restore the page at 0x%" PRIx64 ".\n",
+                          page);
+            }
+        }
+
+        this->dirty_pages_.clear();
+
+        if (uc_context_restore(this->uc_, this->persistent_ctx_)) {
+            ERR_CHILD("Fail to restore the context for persistent mode.\n");
+            exit(1);
+        }
+    }
+
     static void _uc_hook_new_tb(uc_engine* uc, uc_tb* cur_tb, uc_tb* prev_tb,
                                 void* user_data) {
         UCAFL* ucafl = (UCAFL*)user_data;
         enum afl_child_ret tsl_req = AFL_CHILD_TSL_REQUEST;
 
-        // TODO: Gen unique id for the edge.
+        // Edge IDs are not handed out here but by the block hook, it also sees
+        // the indirect edges which are never chained and only the blocks in
+        // the instrumented ranges.
         if ((write(_W(ucafl->afl_child_pipe_), &tsl_req,
                    sizeof(enum afl_child_ret))) != sizeof(enum afl_child_ret) ||
             (write(_W(ucafl->afl_child_pipe_), &cur_tb->pc, 8) != 8)) {
@@ -329,12 +634,112 @@ class UCAFL {
         ERR_CHILD("A new TB is generated at 0x%" PRIx64 "\n", cur_tb->pc);
     }
 
+    // Leaves room for the up to 6 extra split compare entries behind cur_loc.
+    inline uint64_t _afl_cur_loc(uint64_t address) {
+        return ((address >> 4) ^ (address << 8)) & this->afl_map_mask_;
+    }
+
+    void _afl_reset_prev_loc() {
+        this->afl_prev_loc_ = 0;
+        this->afl_prev_pc_ = 0;
+        memset(this->afl_ngram_prev_locs_, 0,
+               sizeof(this->afl_ngram_prev_locs_));
+    }
+
+    // The N-gram of the last cov_ngram_size - 1 blocks, like
+    // AFL_LLVM_NGRAM_SIZE.
+    uint64_t _afl_ngram_loc(uint64_t cur_loc) {
+        uint32_t n = this->options_.cov_ngram_size - 1;
+        uint64_t prev_loc = 0;
+
+        for (uint32_t i = 0; i < n; i++) {
+            prev_loc ^= this->afl_ngram_prev_locs_[i];
+        }
+
+        memmove(&this->afl_ngram_prev_locs_[1], &this->afl_ngram_prev_locs_[0],
+                (n - 1) * sizeof(uint64_t));
+        this->afl_ngram_prev_locs_[0] = cur_loc >> 1;
+
+        return cur_loc ^ prev_loc;
+    }
+
+    // Unicorn has no notion of calls, so the calling context is approximated
+    // by the stack pointer.
+    uint64_t _afl_ctx_loc(uint64_t cur_loc) {
+        uint64_t sp = 0;
+
+        uc_reg_read(this->uc_, this->sp_reg_, &sp);
+
+        return cur_loc ^ this->afl_prev_loc_ ^
+               (((sp >> 2) ^ (sp << 6)) & this->afl_map_mask_);
+    }
+
+    // Numbers edges in the order they are first hit, 0 once the map is full.
+    // The forkserver parent repeats every numbering of its children, so the
+    // IDs survive across them.
+    uint32_t _afl_edge_id(uint64_t prev_pc, uint64_t cur_pc, bool* is_new) {
+        afl_edge edge(prev_pc, cur_pc);
+        auto it = this->afl_edge_ids_.find(edge);
+
+        *is_new = false;
+
+        if (likely(it != this->afl_edge_ids_.end())) {
+            return it->second;
+        }
+
+        if (this->afl_next_edge_id_ >= this->map_size_) {
+            return 0;
+        }
+
+        *is_new = true;
+        this->afl_edge_ids_.emplace(edge, this->afl_next_edge_id_);
+
+        return this->afl_next_edge_id_++;
+    }
+
+    uint64_t _afl_edge_loc(uint64_t address, uint64_t cur_loc) {
+        enum afl_child_ret msg = AFL_CHILD_EDGE_ID;
+        uint64_t edge[2] = {this->afl_prev_pc_, address};
+        bool is_new;
+        uint32_t id = this->_afl_edge_id(edge[0], edge[1], &is_new);
+
+        this->afl_prev_pc_ = address;
+
+        if (is_new &&
+            (write(_W(this->afl_child_pipe_), &msg, sizeof(msg)) !=
+                 sizeof(msg) ||
+             write(_W(this->afl_child_pipe_), edge, sizeof(edge)) !=
+                 sizeof(edge))) {
+            ERR_CHILD("Fail to report the ID of the edge to 0x%" PRIx64 "\n",
+                      address);
+        }
+
+        return id ? id : cur_loc ^ this->afl_prev_loc_;
+    }
+
     static void _uc_hook_block(uc_engine* uc, uint64_t address, uint32_t size,
                                void* user_data) {
-        uint64_t cur_loc = ((address >> 4) ^ (address << 8)) & (MAP_SIZE - 7);
         UCAFL* ucafl = (UCAFL*)user_data;
+        uint64_t cur_loc = ucafl->_afl_cur_loc(address);
+
+        switch (ucafl->options_.cov_mode) {
+        case UC_AFL_COV_BLOCK:
+            ucafl->afl_area_ptr_[cur_loc]++;
+            return;
+        case UC_AFL_COV_NGRAM:
+            ucafl->afl_area_ptr_[ucafl->_afl_ngram_loc(cur_loc)]++;
+            break;
+        case UC_AFL_COV_CTX:
+            ucafl->afl_area_ptr_[ucafl->_afl_ctx_loc(cur_loc)]++;
+            break;
+        case UC_AFL_COV_EDGE_ID:
+            ucafl->afl_area_ptr_[ucafl->_afl_edge_loc(address, cur_loc)]++;
+            break;
+        default:
+            ucafl->afl_area_ptr_[cur_loc ^ ucafl->afl_prev_loc_]++;
+            break;
+        }
 
-        ucafl->afl_area_ptr_[cur_loc ^ ucafl->afl_prev_loc_]++;
         ucafl->afl_prev_loc_ = cur_loc >> 1;
 
         ERR_CHILD("uc_hook_block address=0x%" PRIx64 " cur_loc=%" PRIu64
@@ -411,7 +816,19 @@ class UCAFL {
                                  uint64_t arg2, uint32_t size,
                                  void* user_data) {
         UCAFL* ucafl = (UCAFL*)user_data;
-        uint64_t cur_loc = ((address >> 4) ^ (address << 8)) & (MAP_SIZE - 7);
+
+        // Only compares are interesting for CmpLog, not every subtraction.
+        if (ucafl->afl_cmp_map_) {
+            ucafl->_cmplog_ins(address, arg1, arg2, size);
+        }
+
+        _uc_hook_sub(uc, address, arg1, arg2, size, user_data);
+    }
+
+    static void _uc_hook_sub(uc_engine* uc, uint64_t address, uint64_t arg1,
+                             uint64_t arg2, uint32_t size, void* user_data) {
+        UCAFL* ucafl = (UCAFL*)user_data;
+        uint64_t cur_loc = ucafl->_afl_cur_loc(address);
 
         if (unlikely(cur_loc >= ucafl->afl_inst_rms_)) {
             return;
@@ -420,9 +837,147 @@ class UCAFL {
         ucafl->_uc_hook_sub_impl(cur_loc, arg1, arg2, size);
     }
 
-    static void _uc_hook_sub(uc_engine* uc, uint64_t address, uint64_t arg1,
-                             uint64_t arg2, uint32_t size, void* user_data) {
-        _uc_hook_sub_cmp(uc, address, arg1, arg2, size, user_data);
+    static inline uint64_t _cmplog_key(uint64_t address) {
+        return ((address >> 4) ^ (address << 8)) & (CMP_MAP_W - 1);
+    }
+
+    void _cmplog_ins(uint64_t address, uint64_t arg1, uint64_t arg2,
+                     uint32_t size) {
+        uint64_t k = _cmplog_key(address);
+        struct cmp_header* hdr = &this->afl_cmp_map_->headers[k];
+        uint32_t hits;
+
+        if (unlikely(size < 8)) {
+            return;
+        }
+
+        if (hdr->type != CMP_TYPE_INS) {
+            hdr->type = CMP_TYPE_INS;
+            hdr->hits = 0;
+        }
+
+        hits = hdr->hits;
+        hdr->hits = hits + 1;
+        hdr->shape = size / 8 - 1;
+
+        hits &= CMP_MAP_H - 1;
+        this->afl_cmp_map_->log[k][hits].v0 = arg1;
+        this->afl_cmp_map_->log[k][hits].v1 = arg2;
+    }
+
+    void _cmplog_rtn(uint64_t address, const uint8_t* v0, const uint8_t* v1,
+                     uint32_t len) {
+        uint64_t k = _cmplog_key(address);
+        struct cmp_header* hdr = &this->afl_cmp_map_->headers[k];
+        struct cmpfn_operands* cmpfn =
+            (struct cmpfn_operands*)this->afl_cmp_map_->log[k];
+        uint32_t hits;
+
+        if (hdr->type != CMP_TYPE_RTN) {
+            hdr->type = CMP_TYPE_RTN;
+            hdr->hits = 1;
+            hdr->shape = len - 1;
+            hits = 0;
+        } else {
+            hits = hdr->hits;
+            hdr->hits = hits + 1;
+            if (hdr->shape < len - 1) {
+                hdr->shape = len - 1;
+            }
+        }
+
+        hits &= CMP_MAP_RTN_H - 1;
+        cmpfn[hits].v0_len = len;
+        cmpfn[hits].v1_len = len;
+        memcpy(cmpfn[hits].v0, v0, len);
+        memcpy(cmpfn[hits].v1, v1, len);
+    }
+
+    // Reads as much of [address, address + len) as is mapped.
+    size_t _cmplog_mem_read(uint64_t address, uint8_t* buf, size_t len) {
+        size_t i;
+
+        if (uc_mem_read(this->uc_, address, buf, len) == UC_ERR_OK) {
+            return len;
+        }
+
+        for (i = 0; i < len; i++) {
+            if (uc_mem_read(this->uc_, address + i, buf + i, 1)) {
+                break;
+            }
+        }
+
+        return i;
+    }
+
+    // The compared pointers and the length, from the registers in the calling
+    // convention descriptor or the default calling convention of the arch.
+    bool _cmplog_rtn_args(const uc_afl_cmplog_rtn& rtn, uint64_t args[3]) {
+        const int regs[3] = {rtn.cc.s1_reg, rtn.cc.s2_reg, rtn.cc.n_reg};
+        size_t count = rtn.kind == UC_AFL_CMPLOG_RTN_STRCMP ? 2 : 3;
+
+        for (size_t i = 0; i < count; i++) {
+            if (!regs[i]) {
+                if (!uc_get_args(this->uc_, args, count)) {
+                    return false;
+                }
+                break;
+            }
+        }
+
+        for (size_t i = 0; i < count; i++) {
+            if (regs[i]) {
+                args[i] = 0;
+                if (uc_reg_read(this->uc_, regs[i], &args[i])) {
+                    return false;
+                }
+            }
+        }
+
+        return true;
+    }
+
+    static void _uc_hook_cmplog_rtn(uc_engine* uc, uint64_t address,
+                                    uint32_t size, void* user_data) {
+        UCAFL* ucafl = (UCAFL*)user_data;
+        uint8_t v0[CMPLOG_RTN_LEN] = {0};
+        uint8_t v1[CMPLOG_RTN_LEN] = {0};
+        uint64_t args[3] = {0};
+        size_t len = CMPLOG_RTN_LEN;
+
+        for (const uc_afl_cmplog_rtn& rtn : ucafl->cmplog_rtns_) {
+            if (rtn.address != address) {
+                continue;
+            }
+
+            if (!ucafl->_cmplog_rtn_args(rtn, args)) {
+                ERR_CHILD("Can't get the arguments of the routine at "
+                          "0x%" PRIx64 "\n",
+                          address);
+                return;
+            }
+
+            if (rtn.kind != UC_AFL_CMPLOG_RTN_STRCMP && args[2] < len) {
+                len = args[2];
+            }
+
+            size_t l0 = ucafl->_cmplog_mem_read(args[0], v0, len);
+            size_t l1 = ucafl->_cmplog_mem_read(args[1], v1, len);
+
+            if (!l0 || !l1) {
+                return;
+            }
+
+            if (rtn.kind != UC_AFL_CMPLOG_RTN_MEMCMP) {
+                // Up to and including the terminator of the longer string.
+                l0 = std::min(strnlen((char*)v0, l0) + 1, len);
+                l1 = std::min(strnlen((char*)v1, l1) + 1, len);
+                len = std::max(l0, l1);
+            }
+
+            ucafl->_cmplog_rtn(address, v0, v1, len);
+            return;
+        }
     }
 
     void _uc_setup() {
@@ -437,11 +992,26 @@ class UCAFL {
             exit(1);
         }
 
-        // For coverage.
-        err = uc_hook_add(this->uc_, &this->h1_, UC_HOOK_BLOCK,
-                          (void*)_uc_hook_block, (void*)this, 1, 0);
-        if (err) {
-            ERR("Failed to setup block hook.\n");
+        if (this->options_.cov_mode == UC_AFL_COV_CTX) {
+            this->sp_reg_ = uc_sp_reg(this->uc_);
+            if (!this->sp_reg_) {
+                ERR("Context sensitive coverage is not supported for this "
+                    "arch.\n");
+                exit(1);
+            }
+        }
+
+        const char* inst_ranges_str = getenv("AFL_UNICORN_INST_RANGES");
+        if (inst_ranges_str &&
+            !afl_parse_ranges(inst_ranges_str, this->cov_ranges_)) {
+            ERR("Invalid AFL_UNICORN_INST_RANGES.\n");
+            exit(1);
+        }
+
+        const char* exclude_ranges_str = getenv("AFL_UNICORN_EXCLUDE_RANGES");
+        if (exclude_ranges_str &&
+            !afl_parse_ranges(exclude_ranges_str, this->cov_exclude_ranges_)) {
+            ERR("Invalid AFL_UNICORN_EXCLUDE_RANGES.\n");
             exit(1);
         }
 
@@ -453,32 +1023,95 @@ class UCAFL {
             exit(1);
         }
 
+        std::vector<uc_afl_range> ranges =
+            afl_inst_ranges(this->cov_ranges_, this->cov_exclude_ranges_);
+        if (ranges.empty()) {
+            ERR("All code is excluded from instrumentation.\n");
+        }
+
+        // Unicorn does the bound checks, so the hooks themselves never see an
+        // address outside of the ranges.
+        for (const uc_afl_range& range : ranges) {
+            this->_uc_setup_cov_hooks(range.begin, range.end);
+        }
+
+        if (!this->afl_cmp_map_) {
+            return;
+        }
+
+        for (const uc_afl_cmplog_rtn& rtn : this->cmplog_rtns_) {
+            uc_hook h;
+
+            err = uc_hook_add(this->uc_, &h, UC_HOOK_CODE,
+                              (void*)_uc_hook_cmplog_rtn, (void*)this,
+                              rtn.address, rtn.address);
+            if (err) {
+                ERR("Failed to setup CmpLog hook for the routine at "
+                    "0x%" PRIx64 ".\n",
+                    rtn.address);
+                exit(1);
+            }
+
+            this->cmplog_rtn_hooks_.push_back(h);
+        }
+    }
+
+    void _uc_setup_cov_hooks(uint64_t begin, uint64_t end) {
+        uc_err err;
+        uc_hook h;
+
+        // For coverage.
+        err = uc_hook_add(this->uc_, &h, UC_HOOK_BLOCK, (void*)_uc_hook_block,
+                          (void*)this, begin, end);
+        if (err) {
+            ERR("Failed to setup block hook.\n");
+            exit(1);
+        }
+        this->cov_hooks_.push_back(h);
+
         // These two hooks are for compcov and may not be supported by the arch.
-        err = uc_hook_add(this->uc_, &this->h3_, UC_HOOK_TCG_OPCODE,
-                          (void*)_uc_hook_sub, (void*)this, 1, 0, UC_TCG_OP_SUB,
-                          UC_TCG_OP_FLAG_DIRECT);
+        err = uc_hook_add(this->uc_, &h, UC_HOOK_TCG_OPCODE,
+                          (void*)_uc_hook_sub, (void*)this, begin, end,
+                          UC_TCG_OP_SUB, UC_TCG_OP_FLAG_DIRECT);
 
         if (err) {
             ERR("Failed to setup UC_TCG_OP_SUB direct hook.\n");
             exit(1);
         }
+        this->cov_hooks_.push_back(h);
 
-        err = uc_hook_add(this->uc_, &this->h4_, UC_HOOK_TCG_OPCODE,
-                          (void*)_uc_hook_sub_cmp, (void*)this, 1, 0,
+        err = uc_hook_add(this->uc_, &h, UC_HOOK_TCG_OPCODE,
+                          (void*)_uc_hook_sub_cmp, (void*)this, begin, end,
                           UC_TCG_OP_SUB, UC_TCG_OP_FLAG_CMP);
 
         if (err) {
             ERR("Failed to setup UC_TCG_OP_SUB cmp hook.\n");
             exit(1);
         }
+        this->cov_hooks_.push_back(h);
     }
 
     void _afl_steup() {
         char* map_id_str = getenv(SHM_ENV_VAR);
         char* inst_r_str = getenv("AFL_INST_RATIO");
+        char* cmplog_id_str = getenv(CMPLOG_SHM_ENV_VAR);
 
         this->_setup_valid_wifsignaled();
 
+        this->map_size_ = afl_map_size(this->options_.map_size);
+        if (!this->map_size_) {
+            ERR("Invalid map size, it has to be between %u and %u.\n",
+                MAP_SIZE_MIN, MAP_SIZE_MAX);
+            send_forkserver_error(FS_ERROR_MAP_SIZE);
+            exit(1);
+        }
+
+        uint64_t map_pow2 = 1;
+        while (map_pow2 * 2 <= this->map_size_) {
+            map_pow2 *= 2;
+        }
+        this->afl_map_mask_ = map_pow2 - 7;
+
         if (inst_r_str) {
             uint32_t r = atol(inst_r_str);
 
@@ -488,28 +1121,55 @@ class UCAFL {
                 r = 1;
             }
 
-            this->afl_inst_rms_ = MAP_SIZE * r / 100;
+            this->afl_inst_rms_ = (uint64_t)this->map_size_ * r / 100;
         } else {
-            this->afl_inst_rms_ = MAP_SIZE;
+            this->afl_inst_rms_ = this->map_size_;
         }
 
         if (likely(map_id_str)) {
             int map_id = atoi(map_id_str);
+            struct shmid_ds map_ds;
+
+            // afl-fuzz sizes the map from the same AFL_MAP_SIZE, unless the
+            // harness asked for something else.
+            if (shmctl(map_id, IPC_STAT, &map_ds) == 0 &&
+                map_ds.shm_segsz < this->map_size_) {
+                ERR("The afl mapping area has %zu bytes but the map size is "
+                    "%" PRIu32 ", set AFL_MAP_SIZE=%" PRIu32
+                    " for afl-fuzz.\n",
+                    (size_t)map_ds.shm_segsz, this->map_size_,
+                    this->map_size_);
+                send_forkserver_error(FS_ERROR_MAP_SIZE);
+                exit(1);
+            }
 
             this->afl_area_ptr_ = (uint8_t*)shmat(map_id, NULL, 0);
 
             if (this->afl_area_ptr_ == (void*)-1) {
                 ERR("Can't get the afl mapping area.\n");
-                exit(0);
+                send_forkserver_error(FS_ERROR_SHMAT);
+                exit(1);
             }
 
             this->has_afl_ = true;
 
             // Don't let our parent give up on us.
             this->afl_area_ptr_[0] = 1;
+
+            // afl-fuzz only sets this for the CmpLog instance of the target.
+            if (cmplog_id_str) {
+                this->afl_cmp_map_ =
+                    (struct cmp_map*)shmat(atoi(cmplog_id_str), NULL, 0);
+
+                if (this->afl_cmp_map_ == (void*)-1) {
+                    ERR("Can't get the afl CmpLog area.\n");
+                    send_forkserver_error(FS_ERROR_SHMAT);
+                    exit(1);
+                }
+            }
         } else {
             this->afl_area_ptr_ =
-                (uint8_t*)mmap(NULL, MAP_SIZE, PROT_READ | PROT_WRITE,
+                (uint8_t*)mmap(NULL, this->map_size_, PROT_READ | PROT_WRITE,
                                MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
 
             if (this->afl_area_ptr_ == MAP_FAILED) {
@@ -522,19 +1182,98 @@ class UCAFL {
     }
 
     uc_afl_ret _fksrv_start() {
-        unsigned char tmp[4] = {0};
-        pid_t child_pid;
-        enum afl_child_ret child_ret = AFL_CHILD_EXITED;
-        bool first_round = true;
-        uint32_t status = 0;
+        uc_afl_ret ret;
 
         if (!this->has_afl_)
             return UC_AFL_RET_NO_AFL;
 
+        ret = this->_fksrv_handshake();
+        if (ret != UC_AFL_RET_OK) {
+            return ret;
+        }
+
+        return this->_fksrv_loop();
+    }
+
+    uc_afl_ret _fksrv_handshake() {
+        if (getenv("AFL_OLD_FORKSERVER")) {
+            return this->_fksrv_handshake_old();
+        }
+
+        return this->_fksrv_handshake_new();
+    }
+
+    // The versioned handshake of AFL++ >= 4.20.
+    uc_afl_ret _fksrv_handshake_new() {
+        uint32_t status = FS_NEW_VERSION_MSG;
+        uint32_t map_size = this->map_size_;
+        uint32_t dict_len = this->options_.autodict_len;
+
+        /* Phone home and tell the parent that we're OK. If parent isn't there,
+            assume we're not running in forkserver mode and just execute
+           program. */
+
+        if (write(FORKSRV_FD + 1, &status, 4) != 4)
+            return UC_AFL_RET_NO_AFL;
+
+        if (read(FORKSRV_FD, &status, 4) != 4) {
+            ERR("AFL parent exited before forkserver was up\n");
+            return UC_AFL_RET_ERROR;
+        }
+        if (status != (FS_NEW_VERSION_MSG ^ 0xffffffff)) {
+            ERR("Unexpected response from AFL++ on forkserver setup, is "
+                "afl-fuzz older than 4.20? Try AFL_OLD_FORKSERVER=1.\n");
+            return UC_AFL_RET_ERROR;
+        }
+
+        status = FS_NEW_OPT_MAPSIZE;
+        if (this->afl_testcase_ptr_) {
+            status |= FS_NEW_OPT_SHDMEM_FUZZ;
+        }
+        if (dict_len) {
+            status |= FS_NEW_OPT_AUTODICT;
+        }
+
+        // The option values follow in the order of the option bits.
+        if (write(FORKSRV_FD + 1, &status, 4) != 4 ||
+            write(FORKSRV_FD + 1, &map_size, 4) != 4) {
+            ERR("Fail to send forkserver options.\n");
+            return UC_AFL_RET_ERROR;
+        }
+
+        if (dict_len && (write(FORKSRV_FD + 1, &dict_len, 4) != 4 ||
+                         !write_all(FORKSRV_FD + 1, this->options_.autodict,
+                                    dict_len))) {
+            ERR("Fail to send the autodictionary.\n");
+            return UC_AFL_RET_ERROR;
+        }
+
+        // Welcome message, the handshake is done.
+        status = FS_NEW_VERSION_MSG;
+        if (write(FORKSRV_FD + 1, &status, 4) != 4) {
+            ERR("Fail to finish the forkserver handshake.\n");
+            return UC_AFL_RET_ERROR;
+        }
+
+        return UC_AFL_RET_OK;
+    }
+
+    uc_afl_ret _fksrv_handshake_old() {
+        uint32_t status = FS_OPT_ENABLED;
+        uint32_t dict_len = this->options_.autodict_len;
+
+        if (this->map_size_ <= FS_OPT_MAX_MAPSIZE) {
+            status |= FS_OPT_MAPSIZE | FS_OPT_SET_MAPSIZE(this->map_size_);
+        }
+
         if (this->afl_testcase_ptr_) {
             /* Parent supports testcases via shared map - and the user wants to
              * use it. Tell AFL. */
-            status = (FS_OPT_ENABLED | FS_OPT_SHDMEM_FUZZ);
+            status |= FS_OPT_SHDMEM_FUZZ;
+        }
+
+        if (dict_len) {
+            status |= FS_OPT_AUTODICT;
         }
 
         /* Phone home and tell the parent that we're OK. If parent isn't there,
@@ -544,21 +1283,46 @@ class UCAFL {
         if (write(FORKSRV_FD + 1, &status, 4) != 4)
             return UC_AFL_RET_NO_AFL;
 
-        /* afl tells us in an extra message if it accepted this option or not */
-        if (this->afl_testcase_ptr_ && getenv(SHM_FUZZ_ENV_VAR)) {
-            if (read(FORKSRV_FD, &status, 4) != 4) {
-                ERR("AFL parent exited before forkserver was up\n");
-                return UC_AFL_RET_ERROR;
-            }
-            if (status != (FS_OPT_ENABLED | FS_OPT_SHDMEM_FUZZ)) {
-                ERR("Unexpected response from AFL++ on forkserver setup\n");
-                return UC_AFL_RET_ERROR;
-            }
-        } else {
+        if (!(status & (FS_OPT_SHDMEM_FUZZ | FS_OPT_AUTODICT))) {
             ERR("AFL++ sharedmap fuzzing not supported/SHM_FUZZ_ENV_VAR not "
                 "set\n");
+            return UC_AFL_RET_OK;
+        }
+
+        /* afl tells us in an extra message if it accepted these options or
+         * not */
+        if (read(FORKSRV_FD, &status, 4) != 4) {
+            ERR("AFL parent exited before forkserver was up\n");
+            return UC_AFL_RET_ERROR;
         }
 
+        if (this->afl_testcase_ptr_ &&
+            (status & (FS_OPT_ENABLED | FS_OPT_SHDMEM_FUZZ)) !=
+                (FS_OPT_ENABLED | FS_OPT_SHDMEM_FUZZ)) {
+            ERR("Unexpected response from AFL++ on forkserver setup\n");
+            return UC_AFL_RET_ERROR;
+        }
+
+        if ((status & (FS_OPT_ENABLED | FS_OPT_AUTODICT)) ==
+            (FS_OPT_ENABLED | FS_OPT_AUTODICT)) {
+            if (write(FORKSRV_FD + 1, &dict_len, 4) != 4 ||
+                !write_all(FORKSRV_FD + 1, this->options_.autodict,
+                           dict_len)) {
+                ERR("Fail to send the autodictionary.\n");
+                return UC_AFL_RET_ERROR;
+            }
+        }
+
+        return UC_AFL_RET_OK;
+    }
+
+    // Only returns in the child (UC_AFL_RET_CHILD) or once AFL is gone.
+    uc_afl_ret _fksrv_loop() {
+        unsigned char tmp[4] = {0};
+        pid_t child_pid;
+        enum afl_child_ret child_ret = AFL_CHILD_EXITED;
+        bool first_round = true;
+
         void (*old_sigchld_handler)(int) = signal(SIGCHLD, SIG_DFL);
 
         while (1) {
@@ -631,7 +1395,7 @@ class UCAFL {
                     close(_R(this->afl_child_pipe_));
                     close(_W(this->afl_parent_pipe_));
 
-                    memset(this->afl_area_ptr_, 0, MAP_SIZE);
+                    memset(this->afl_area_ptr_, 0, this->map_size_);
                     mem_barrier(); // Make very sure everything has been written
                                    // to the map at this point
 
@@ -639,14 +1403,14 @@ class UCAFL {
 
                         // For persistent mode: Clear the map manually after
                         // forks.
-                        memset(this->afl_area_ptr_, 0, MAP_SIZE);
+                        memset(this->afl_area_ptr_, 0, this->map_size_);
 
                     } else {
 
                         first_round = false;
                     }
 
-                    this->afl_prev_loc_ = 0;
+                    this->_afl_reset_prev_loc();
                     // Tell AFL we're alive
                     this->afl_area_ptr_[0] = 1;
 
@@ -760,14 +1524,28 @@ class UCAFL {
                     ERR("TB is cached at 0x%" PRIx64 ".\n", pc);
                 }
 
+            } else if (child_msg == AFL_CHILD_EDGE_ID) {
+                uint64_t edge[2];
+                bool is_new;
+
+                if (read(_R(this->afl_child_pipe_), edge, sizeof(edge)) !=
+                    sizeof(edge)) {
+                    ERR("Fail to read child edge ID.\n");
+                    return AFL_CHILD_EXITED; // child is dead.
+                }
+
+                // Same order as in the child, so we end up with the same ID.
+                this->_afl_edge_id(edge[0], edge[1], &is_new);
+
             } else {
 
                 ERR("Unexpected response by child! %d. Please report this "
                     "as bug for unicornafl.\n"
                     "    Expected one of {AFL_CHILD_NEXT: %d, "
-                    "AFL_CHILD_FOUND_CRASH: %d, AFL_CHILD_TSL_REQUEST: %d}.\n",
+                    "AFL_CHILD_FOUND_CRASH: %d, AFL_CHILD_TSL_REQUEST: %d, "
+                    "AFL_CHILD_EDGE_ID: %d}.\n",
                     child_msg, AFL_CHILD_NEXT, AFL_CHILD_FOUND_CRASH,
-                    AFL_CHILD_TSL_REQUEST);
+                    AFL_CHILD_TSL_REQUEST, AFL_CHILD_EDGE_ID);
             }
         }
     }
@@ -791,6 +1569,7 @@ class UCAFL {
             uint32_t* map = (uint32_t*)shmat(shm_id, NULL, 0);
             if (!map || map == MAP_FAILED) {
                 perror("[!] could not access fuzzing shared memory");
+                send_forkserver_error(FS_ERROR_SHMAT);
                 exit(1);
             }
 
@@ -846,6 +1625,7 @@ class UCAFL {
     uc_afl_fuzz_cb_t fuzz_callback_;
     bool always_validate_;
     uint32_t persistent_iters_;
+    uc_afl_options options_;
     void* data_;
     // Used both in shm and mmap testcase
     uint32_t* afl_testcase_ptr_;
@@ -857,8 +1637,24 @@ class UCAFL {
     uint8_t* afl_area_ptr_;
     // This variable checks whether we get a mapping from AFL.
     bool has_afl_;
+    // Size of afl_area_ptr_
+    uint32_t map_size_;
+    // Mask for coverage indices, based on the largest power of 2 that fits
+    // into map_size_
+    uint64_t afl_map_mask_;
+    // CmpLog mapping, only there if afl-fuzz runs us for CmpLog
+    struct cmp_map* afl_cmp_map_;
+    std::vector<uc_afl_cmplog_rtn> cmplog_rtns_;
     uint32_t afl_inst_rms_;
     uint64_t afl_prev_loc_;
+    // Only used for UC_AFL_COV_NGRAM, the most recent block first.
+    uint64_t afl_ngram_prev_locs_[NGRAM_SIZE_MAX - 1];
+    // Only used for UC_AFL_COV_EDGE_ID
+    uint64_t afl_prev_pc_;
+    std::unordered_map<afl_edge, uint32_t, afl_edge_hash> afl_edge_ids_;
+    uint32_t afl_next_edge_id_;
+    // Only used for UC_AFL_COV_CTX
+    int sp_reg_;
 
     // Fake signal value
     int wifsignaled_;
@@ -868,12 +1664,23 @@ class UCAFL {
     int afl_parent_pipe_[2];
 
     // Our hooks
-    uc_hook h1_;
+    std::vector<uc_afl_range> cov_ranges_;
+    std::vector<uc_afl_range> cov_exclude_ranges_;
+    std::vector<uc_hook> cov_hooks_;
+    // Only used with persistent_restore
+    uc_context* persistent_ctx_;
+    uc_hook persistent_hook_;
+    uint64_t page_size_;
+    std::unordered_map<uint64_t, persistent_page> persistent_pages_;
+    std::vector<uint64_t> dirty_pages_;
     uc_hook h2_;
-    uc_hook h3_;
-    uc_hook h4_;
+    std::vector<uc_hook> cmplog_rtn_hooks_;
 };
 
+// The forkserver started by uc_afl_forkserver_start, it has to outlive the call
+// because the child keeps running our hooks after it returns.
+static UCAFL* forkserver_ucafl = nullptr;
+
 static uc_err dummy_uc_afl_fuzz_callback(uc_engine* uc, void* data) {
     uint64_t pc;
 
@@ -921,6 +1728,8 @@ static uint64_t uc_get_pc(uc_engine* uc) {
         uc_reg_read(uc, UC_M68K_REG_PC, &pc);
     } else if (arch == UC_ARCH_S390X) {
         uc_reg_read(uc, UC_S390X_REG_PC, &pc);
+    } else if (arch == UC_ARCH_TRICORE) {
+        uc_reg_read(uc, UC_TRICORE_REG_PC, &pc);
     } else if (arch == UC_ARCH_ARM64) {
         uc_reg_read(uc, UC_ARM64_REG_PC, &pc);
     }
@@ -928,11 +1737,135 @@ static uint64_t uc_get_pc(uc_engine* uc) {
     return pc;
 }
 
-extern "C" UNICORNAFL_EXPORT uc_afl_ret uc_afl_fuzz(
+static int uc_sp_reg(uc_engine* uc) {
+    uc_arch arch;
+    uc_mode mode;
+
+    uc_ctl_get_arch(uc, &arch);
+    uc_ctl_get_mode(uc, &mode);
+
+    if (arch == UC_ARCH_X86) {
+        if (mode == UC_MODE_32) {
+            return UC_X86_REG_ESP;
+        } else if (mode == UC_MODE_16) {
+            return UC_X86_REG_SP;
+        } else {
+            return UC_X86_REG_RSP;
+        }
+    } else if (arch == UC_ARCH_ARM) {
+        return UC_ARM_REG_SP;
+    } else if (arch == UC_ARCH_RISCV) {
+        return UC_RISCV_REG_SP;
+    } else if (arch == UC_ARCH_MIPS) {
+        return UC_MIPS_REG_SP;
+    } else if (arch == UC_ARCH_PPC) {
+        return UC_PPC_REG_1;
+    } else if (arch == UC_ARCH_SPARC) {
+        return UC_SPARC_REG_SP;
+    } else if (arch == UC_ARCH_M68K) {
+        return UC_M68K_REG_A7;
+    } else if (arch == UC_ARCH_S390X) {
+        return UC_S390X_REG_R15;
+    } else if (arch == UC_ARCH_TRICORE) {
+        return UC_TRICORE_REG_SP;
+    } else if (arch == UC_ARCH_ARM64) {
+        return UC_ARM64_REG_SP;
+    }
+
+    return 0;
+}
+
+// Reads the first @count (at most 4) integer arguments of a function we just
+// entered, following the default calling convention of the arch.
+static bool uc_get_args(uc_engine* uc, uint64_t* args, size_t count) {
+    uc_arch arch;
+    uc_mode mode;
+    const int* regs = NULL;
+    uint64_t sp = 0;
+
+    static const int x86_64_regs[] = {UC_X86_REG_RDI, UC_X86_REG_RSI,
+                                      UC_X86_REG_RDX, UC_X86_REG_RCX};
+    static const int arm_regs[] = {UC_ARM_REG_R0, UC_ARM_REG_R1, UC_ARM_REG_R2,
+                                   UC_ARM_REG_R3};
+    static const int arm64_regs[] = {UC_ARM64_REG_X0, UC_ARM64_REG_X1,
+                                     UC_ARM64_REG_X2, UC_ARM64_REG_X3};
+    static const int mips_regs[] = {UC_MIPS_REG_A0, UC_MIPS_REG_A1,
+                                    UC_MIPS_REG_A2, UC_MIPS_REG_A3};
+    static const int ppc_regs[] = {UC_PPC_REG_3, UC_PPC_REG_4, UC_PPC_REG_5,
+                                   UC_PPC_REG_6};
+    static const int riscv_regs[] = {UC_RISCV_REG_A0, UC_RISCV_REG_A1,
+                                     UC_RISCV_REG_A2, UC_RISCV_REG_A3};
+    static const int sparc_regs[] = {UC_SPARC_REG_O0, UC_SPARC_REG_O1,
+                                     UC_SPARC_REG_O2, UC_SPARC_REG_O3};
+
+    if (count > 4) {
+        return false;
+    }
+
+    uc_ctl_get_arch(uc, &arch);
+    uc_ctl_get_mode(uc, &mode);
+
+    if (arch == UC_ARCH_X86) {
+        if (mode == UC_MODE_64) {
+            regs = x86_64_regs;
+        } else if (mode == UC_MODE_32) {
+            // cdecl, the arguments are behind the return address.
+            uc_reg_read(uc, UC_X86_REG_ESP, &sp);
+        } else {
+            return false;
+        }
+    } else if (arch == UC_ARCH_ARM) {
+        regs = arm_regs;
+    } else if (arch == UC_ARCH_ARM64) {
+        regs = arm64_regs;
+    } else if (arch == UC_ARCH_MIPS) {
+        regs = mips_regs;
+    } else if (arch == UC_ARCH_PPC) {
+        regs = ppc_regs;
+    } else if (arch == UC_ARCH_RISCV) {
+        regs = riscv_regs;
+    } else if (arch == UC_ARCH_SPARC) {
+        regs = sparc_regs;
+    } else if (arch == UC_ARCH_M68K) {
+        // Same as cdecl, but big endian.
+        uc_reg_read(uc, UC_M68K_REG_A7, &sp);
+    } else {
+        return false;
+    }
+
+    for (size_t i = 0; i < count; i++) {
+        args[i] = 0;
+
+        if (regs) {
+            if (uc_reg_read(uc, regs[i], &args[i])) {
+                return false;
+            }
+        } else {
+            uint8_t arg[4];
+
+            if (uc_mem_read(uc, sp + 4 * (i + 1), arg, 4)) {
+                return false;
+            }
+
+            if (arch == UC_ARCH_M68K) {
+                args[i] = ((uint32_t)arg[0] << 24) | ((uint32_t)arg[1] << 16) |
+                          ((uint32_t)arg[2] << 8) | arg[3];
+            } else {
+                args[i] = ((uint32_t)arg[3] << 24) | ((uint32_t)arg[2] << 16) |
+                          ((uint32_t)arg[1] << 8) | arg[0];
+            }
+        }
+    }
+
+    return true;
+}
+
+extern "C" UNICORNAFL_EXPORT uc_afl_ret uc_afl_fuzz_ex(
     uc_engine* uc, char* input_file,
     uc_afl_cb_place_input_t place_input_callback, uint64_t* exits,
-    size_t exit_count, uc_afl_cb_validate_crash_t validate_crash_callback,
-    bool always_validate, uint32_t persistent_iters, void* data) {
+    size_t exit_count, uc_afl_fuzz_cb_t fuzz_callback,
+    uc_afl_cb_validate_crash_t validate_crash_callback, bool always_validate,
+    uint32_t persistent_iters, const uc_afl_options* options, void* data) {
 
     log_init();
 
@@ -956,53 +1889,111 @@ extern "C" UNICORNAFL_EXPORT uc_afl_ret uc_afl_fuzz(
         ERR("always_validate set but validate_crash_callback is missing.\n");
         return UC_AFL_RET_ERROR;
     }
-    if (!exit_count) {
+    if (!fuzz_callback && !exit_count) {
         ERR("Nullptr provided for exits.\n");
         return UC_AFL_RET_ERROR;
     }
+    if (options && options->size != sizeof(uc_afl_options)) {
+        ERR("Unknown uc_afl_options size %" PRIu32 ".\n", options->size);
+        return UC_AFL_RET_ERROR;
+    }
+    if (options && options->autodict_len && !options->autodict) {
+        ERR("autodict_len set but autodict is missing.\n");
+        return UC_AFL_RET_ERROR;
+    }
+    if (options && options->cov_mode == UC_AFL_COV_NGRAM &&
+        (options->cov_ngram_size < NGRAM_SIZE_MIN ||
+         options->cov_ngram_size > NGRAM_SIZE_MAX)) {
+        ERR("cov_ngram_size has to be between %u and %u.\n", NGRAM_SIZE_MIN,
+            NGRAM_SIZE_MAX);
+        return UC_AFL_RET_ERROR;
+    }
+    if (options && options->cmplog_rtn_count && !options->cmplog_rtns) {
+        ERR("cmplog_rtn_count set but cmplog_rtns is missing.\n");
+        return UC_AFL_RET_ERROR;
+    }
+    if (options &&
+        (!afl_valid_ranges(options->cov_ranges, options->cov_range_count) ||
+         !afl_valid_ranges(options->cov_exclude_ranges,
+                           options->cov_exclude_range_count))) {
+        ERR("Invalid cov_ranges or cov_exclude_ranges.\n");
+        return UC_AFL_RET_ERROR;
+    }
 
     UCAFL ucafl(uc, input_file, place_input_callback, validate_crash_callback,
-                dummy_uc_afl_fuzz_callback, always_validate, persistent_iters,
-                data);
+                fuzz_callback ? fuzz_callback : dummy_uc_afl_fuzz_callback,
+                always_validate, persistent_iters, options, data);
 
-    if (unlikely(ucafl.set_exits(exits, exit_count))) {
+    if (exit_count && unlikely(ucafl.set_exits(exits, exit_count))) {
         return UC_AFL_RET_ERROR;
     }
 
     return ucafl.fsrv_run();
 }
 
+extern "C" UNICORNAFL_EXPORT uc_afl_ret uc_afl_fuzz(
+    uc_engine* uc, char* input_file,
+    uc_afl_cb_place_input_t place_input_callback, uint64_t* exits,
+    size_t exit_count, uc_afl_cb_validate_crash_t validate_crash_callback,
+    bool always_validate, uint32_t persistent_iters, void* data) {
+
+    return uc_afl_fuzz_ex(uc, input_file, place_input_callback, exits,
+                          exit_count, nullptr, validate_crash_callback,
+                          always_validate, persistent_iters, nullptr, data);
+}
+
 extern "C" UNICORNAFL_EXPORT uc_afl_ret uc_afl_fuzz_custom(
     uc_engine* uc, char* input_file,
     uc_afl_cb_place_input_t place_input_callback, uc_afl_fuzz_cb_t fuzz_callbck,
     uc_afl_cb_validate_crash_t validate_crash_callback, bool always_validate,
     uint32_t persistent_iters, void* data) {
 
+    if (!fuzz_callbck) {
+        log_init();
+        ERR("No fuzz_callback set.\n");
+        return UC_AFL_RET_ERROR;
+    }
+
+    return uc_afl_fuzz_ex(uc, input_file, place_input_callback, nullptr, 0,
+                          fuzz_callbck, validate_crash_callback,
+                          always_validate, persistent_iters, nullptr, data);
+}
+
+extern "C" UNICORNAFL_EXPORT uc_afl_ret
+uc_afl_forkserver_start(uc_engine* uc, uint64_t* exits, size_t exit_count) {
+
     log_init();
 
+    ERR("Entering uc_afl_forkserver_start with exit_count=%zu\n", exit_count);
+
     if (!uc) {
-        ERR("Unicorn Engine passed to uc_afl_fuzz is NULL!\n");
-        return UC_AFL_RET_ERROR;
-    }
-    if (!input_file || input_file[0] == 0) {
-        ERR("No input file provided to uc_afl_fuzz.\n");
+        ERR("Unicorn Engine passed to uc_afl_forkserver_start is NULL!\n");
         return UC_AFL_RET_ERROR;
     }
-    if (!place_input_callback) {
-        ERR("no place_input_callback set.\n");
+    if (exit_count && !exits) {
+        ERR("Nullptr provided for exits.\n");
         return UC_AFL_RET_ERROR;
     }
-    if (always_validate && !validate_crash_callback) {
-        ERR("always_validate set but validate_crash_callback is missing.\n");
-        return UC_AFL_RET_ERROR;
+    if (forkserver_ucafl) {
+        ERR("Forkserver is already running.\n");
+        return UC_AFL_RET_CALLED_TWICE;
     }
-    if (!fuzz_callbck) {
-        ERR("No fuzz_callback set.\n");
+
+    forkserver_ucafl = new UCAFL(uc, nullptr, nullptr, nullptr, nullptr, false,
+                                 1, nullptr, nullptr);
+
+    if (exit_count &&
+        unlikely(forkserver_ucafl->set_exits(exits, exit_count))) {
+        delete forkserver_ucafl;
+        forkserver_ucafl = nullptr;
         return UC_AFL_RET_ERROR;
     }
 
-    UCAFL ucafl(uc, input_file, place_input_callback, validate_crash_callback,
-                fuzz_callbck, always_validate, persistent_iters, data);
-
-    return ucafl.fsrv_run();
+    uc_afl_ret ret = forkserver_ucafl->fsrv_start();
+    if (ret != UC_AFL_RET_CHILD && ret != UC_AFL_RET_FINISHED) {
+        // Nothing is running, the destructor removes our hooks from uc.
+        delete forkserver_ucafl;
+        forkserver_ucafl = nullptr;
+    }
+    return ret;
 }